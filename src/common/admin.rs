@@ -0,0 +1,156 @@
+use std::sync::RwLock;
+
+use reqwest::header::CONTENT_TYPE;
+use rocket::{post, State};
+use rocket::serde::json::Json;
+use serde::Serialize;
+use tracing::{info, warn};
+use ttlhashmap::TtlHashMap;
+
+use crate::common::{ExpireRequest, HttpTransport, Key, ManagerError, SessionStatus};
+use crate::common::keygen_room::KeygenRoom;
+use crate::common::auth::AdminAuth;
+use crate::common::signing_room::SigningRoom;
+
+pub const KEYGEN_SIGNUP_KEY: &str = "signup-keygen";
+pub const SIGN_SIGNUP_KEY_PREFIX: &str = "signup-sign-";
+
+#[post("/admin/status")]
+pub fn status(
+    _admin: AdminAuth,
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+) -> Json<Result<Vec<SessionStatus>, ManagerError>> {
+    let mut hm = db_mtx.write().unwrap();
+    Json(Ok(list_sessions(&mut hm)))
+}
+
+#[post("/admin/expire", format = "json", data = "<request>")]
+pub fn expire(
+    _admin: AdminAuth,
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    request: Json<ExpireRequest>,
+) -> Json<Result<Vec<Key>, ManagerError>> {
+    let room = request.room.clone();
+    let mut hm = db_mtx.write().unwrap();
+
+    let session = list_sessions(&mut hm).into_iter()
+        .find(|x| (x.kind == "sign" && x.room_id == room) || x.room_uuid == room);
+    let session = match session {
+        Some(session) => session,
+        None => return Json(Err(ManagerError{
+            error: "No room or session found: ".to_string() + room.as_str()
+        })),
+    };
+
+    let mut removed_keys = vec![signup_key(&session)];
+    removed_keys.extend(
+        session.received_keys.iter().map(|x| format!("{}-{}", x, session.room_uuid))
+    );
+    for key in removed_keys.iter() {
+        hm.remove(key);
+    }
+
+    info!(
+        kind = session.kind.as_str(),
+        room_id = session.room_id.as_str(),
+        room_uuid = session.room_uuid.as_str(),
+        "Room force-expired by admin"
+    );
+    Json(Ok(removed_keys))
+}
+
+fn signup_key(session: &SessionStatus) -> Key {
+    match session.kind.as_str() {
+        "keygen" => KEYGEN_SIGNUP_KEY.to_string(),
+        _ => format!("{}{}", SIGN_SIGNUP_KEY_PREFIX, session.room_id),
+    }
+}
+
+pub fn list_sessions(hm: &mut TtlHashMap<Key, String>) -> Vec<SessionStatus> {
+    let keys: Vec<Key> = hm.keys().cloned().collect();
+    let mut sessions: Vec<SessionStatus> = Vec::new();
+
+    for key in keys.iter() {
+        let value = match hm.get(key) {
+            Some(value) => value.clone(),
+            None => continue, // Expired in the meantime
+        };
+        if key == KEYGEN_SIGNUP_KEY {
+            let keygen_room: KeygenRoom = match serde_json::from_str(&value) {
+                Ok(keygen_room) => keygen_room,
+                Err(e) => {
+                    warn!(key = key.as_str(), "Skipped a keygen room that does not parse: {}", e);
+                    continue;
+                }
+            };
+            sessions.push(SessionStatus {
+                kind: "keygen".to_string(),
+                room_id: "".to_string(),
+                room_uuid: keygen_room.room_uuid.clone(),
+                last_stage: keygen_room.last_stage.clone(),
+                joined: keygen_room.member_info.len() as u16,
+                keygen_room: Some(keygen_room),
+                signing_room: None,
+                received_keys: vec![],
+            });
+        } else if key.starts_with(SIGN_SIGNUP_KEY_PREFIX) {
+            let signing_room: SigningRoom = match serde_json::from_str(&value) {
+                Ok(signing_room) => signing_room,
+                Err(e) => {
+                    warn!(key = key.as_str(), "Skipped a signing room that does not parse: {}", e);
+                    continue;
+                }
+            };
+            sessions.push(SessionStatus {
+                kind: "sign".to_string(),
+                room_id: signing_room.room_id.clone(),
+                room_uuid: signing_room.room_uuid.clone(),
+                last_stage: signing_room.last_stage.clone(),
+                joined: signing_room.member_info.len() as u16,
+                keygen_room: None,
+                signing_room: Some(signing_room),
+                received_keys: vec![],
+            });
+        }
+    }
+
+    for session in sessions.iter_mut() {
+        let suffix = format!("-{}", session.room_uuid);
+        session.received_keys = keys.iter()
+            .filter(|x| !x.starts_with("signup-") && x.ends_with(&suffix))
+            .map(|x| x.trim_end_matches(&suffix).to_string())
+            .collect();
+        session.received_keys.sort();
+    }
+    sessions
+}
+
+pub async fn manager_status(transport: &HttpTransport) -> Result<Vec<SessionStatus>, ManagerError> {
+    let res_body = admin_post(transport, "admin/status", None::<()>).await?;
+    serde_json::from_str(&res_body).map_err(|e| ManagerError{ error: format!("Unexpected answer: {}", e) })?
+}
+
+pub async fn expire_room(transport: &HttpTransport, room: &str) -> Result<Vec<Key>, ManagerError> {
+    let request = ExpireRequest { room: room.to_string() };
+    let res_body = admin_post(transport, "admin/expire", Some(request)).await?;
+    serde_json::from_str(&res_body).map_err(|e| ManagerError{ error: format!("Unexpected answer: {}", e) })?
+}
+
+async fn admin_post<T>(transport: &HttpTransport, path: &str, body: Option<T>) -> Result<String, ManagerError>
+where
+    T: Serialize,
+{
+    let mut request = transport.request(path);
+    if let Some(body) = body {
+        request = request
+            .header(CONTENT_TYPE, "application/json")
+            .body(serde_json::to_string(&body).unwrap());
+    }
+    let res = request.send().await.map_err(|e| ManagerError{ error: e.to_string() })?;
+    if !res.status().is_success() {
+        return Err(ManagerError{
+            error: format!("Manager refused the admin request: {}", res.status())
+        });
+    }
+    res.text().await.map_err(|e| ManagerError{ error: e.to_string() })
+}
@@ -0,0 +1,139 @@
+use ring::constant_time::verify_slices_are_equal;
+use ring::digest::{digest, SHA256};
+use rocket::http::Status;
+use rocket::mtls::Certificate;
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use serde::{Deserialize, Serialize};
+
+use crate::common::{ManagerConfig, ManagerError};
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Keygen,
+    Sign,
+    Admin,
+}
+
+/// A bearer token and the roles it grants.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenGrant {
+    pub token: String,
+    pub roles: Vec<Role>,
+}
+
+/// The SHA-256 fingerprint of an allowed client certificate and the roles it grants.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CertificateGrant {
+    pub fingerprint: String, // Hex, with or without colons, e.g. as printed by `openssl x509 -fingerprint -sha256`
+    pub roles: Vec<Role>,
+}
+
+/// Normalizes a fingerprint to lowercase hex without separators
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").trim().to_lowercase()
+}
+
+pub fn certificate_fingerprint(der: &[u8]) -> String {
+    hex::encode(digest(&SHA256, der).as_ref())
+}
+
+/// Grants a request if it presents a bearer token or a client certificate holding one of `roles`.
+/// A manager without any configured credential accepts all party requests, the admin routes always
+/// need a credential.
+async fn authorize(request: &Request<'_>, roles: &[Role]) -> request::Outcome<(), ManagerError> {
+    let config = request.rocket().state::<ManagerConfig>().unwrap();
+    let has_role = |granted: &Vec<Role>| granted.iter().any(|x| roles.contains(x));
+
+    if !config.requires_auth() && !roles.contains(&Role::Admin) {
+        return Outcome::Success(());
+    }
+
+    let bearer = request.headers().get_one("Authorization")
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(given) = bearer {
+        let granted = config.token_grants().iter().any(|x| {
+            verify_slices_are_equal(given.as_bytes(), x.token.as_bytes()).is_ok() && has_role(&x.roles)
+        });
+        return match granted {
+            true => Outcome::Success(()),
+            false => refuse(request, Status::Unauthorized, ManagerError{
+                error: format!("Invalid token or token without the {:?} role", roles)
+            }),
+        };
+    }
+
+    if let Outcome::Success(certificate) = request.guard::<Certificate<'_>>().await {
+        let fingerprint = certificate_fingerprint(certificate.as_bytes());
+        let granted = config.client_certs.iter().any(|x| {
+            normalize_fingerprint(&x.fingerprint) == fingerprint && has_role(&x.roles)
+        });
+        return match granted {
+            true => Outcome::Success(()),
+            false => refuse(request, Status::Forbidden, ManagerError{
+                error: format!("Client certificate {} is not allowed the {:?} role", fingerprint, roles)
+            }),
+        };
+    }
+
+    refuse(request, Status::Unauthorized, ManagerError{
+        error: "Missing bearer token or client certificate".to_string()
+    })
+}
+
+/// Fails a guard, the error is kept in the request for the catcher that renders the response.
+fn refuse(request: &Request<'_>, status: Status, error: ManagerError) -> request::Outcome<(), ManagerError> {
+    request.local_cache(|| Some(error.clone()));
+    Outcome::Failure((status, error))
+}
+
+/// Request guard of the keygen signup route.
+pub struct KeygenAuth;
+
+/// Request guard of the signing signup route.
+pub struct SignAuth;
+
+/// Request guard of the routes shared by keygen and signing parties, i.e. `get` and `set`.
+pub struct PartyAuth;
+
+/// Request guard of the admin routes.
+pub struct AdminAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for KeygenAuth {
+    type Error = ManagerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[Role::Keygen]).await.map(|_| KeygenAuth)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for SignAuth {
+    type Error = ManagerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[Role::Sign]).await.map(|_| SignAuth)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for PartyAuth {
+    type Error = ManagerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[Role::Keygen, Role::Sign]).await.map(|_| PartyAuth)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AdminAuth {
+    type Error = ManagerError;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        authorize(request, &[Role::Admin]).await.map(|_| AdminAuth)
+    }
+}
@@ -0,0 +1,272 @@
+use bip39::Language;
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::Keys;
+use paillier::{DecryptionKey, EncryptionKey};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use zk_paillier::zkproofs::NICorrectKeyProof;
+
+use crate::common::key_share::KeyRecord;
+use crate::common::keygen::{check_paillier_modulus, create_dlog_statement};
+use crate::common::paillier_keys::{blum_paillier_keys, BlumModulusProof};
+use crate::common::recovery::{write_keys, ShareUpdate};
+use crate::common::schnorr::scalar_bytes;
+use crate::common::KeyShare;
+
+const BACKUP_VERSION: u8 = 2; // 1 held a seed of the Paillier keys
+const SECRET_LENGTH: usize = 2 + 32 + 32; // party_id, u_i, x_i
+const HEADER_LENGTH: usize = 5; // Version, identifier, threshold, index
+const CHECKSUM_LENGTH: usize = 4;
+const SHARE_LENGTH: usize = HEADER_LENGTH + SECRET_LENGTH + CHECKSUM_LENGTH;
+const MAX_SHARES: u8 = 16;
+const BITS_PER_WORD: usize = 11;
+pub const WORDS_PER_SHARE: usize = (SHARE_LENGTH * 8 + BITS_PER_WORD - 1) / BITS_PER_WORD;
+
+/// Secret material of a keys file: the shares u_i and x_i. The Paillier keys are not backed up, a restored
+/// keys file gets new ones. Everything else in a keys file is public and the same for all parties, see `KeyRecord`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct BackupSecret {
+    pub party_id: u16,
+    pub u_i: FE,
+    pub x_i: FE,
+}
+
+impl BackupSecret {
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.party_id.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&scalar_bytes(&self.u_i));
+        bytes.extend_from_slice(&scalar_bytes(&self.x_i));
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() != SECRET_LENGTH {
+            return Err(format!("Backup holds {} bytes, expected {}", bytes.len(), SECRET_LENGTH));
+        }
+        let scalar = |bytes: &[u8]| -> Result<FE, String> {
+            let value = BigInt::from_bytes(bytes);
+            if value == BigInt::from(0) || value >= FE::q() {
+                return Err("Backup holds a share that is not a scalar".to_string());
+            }
+            Ok(ECScalar::from(&value))
+        };
+        Ok(BackupSecret {
+            party_id: u16::from_be_bytes([bytes[0], bytes[1]]),
+            u_i: scalar(&bytes[2..34])?,
+            x_i: scalar(&bytes[34..66])?,
+        })
+    }
+}
+
+/// Backs up the secret material of `key_share` into `shares` word lists, any `threshold` of which restore it.
+/// The keys file is left as is.
+pub fn backup(key_share: &KeyShare, threshold: u8, shares: u8) -> Result<Vec<String>, String> {
+    key_share.validate()?;
+    let secret = BackupSecret {
+        party_id: key_share.party_id,
+        u_i: key_share.party_keys.u_i,
+        x_i: key_share.shared_keys.x_i,
+    };
+    // Refused now rather than when the keys file is lost
+    rebuild(key_share.record(), &secret, key_share.party_keys.ek.clone(), key_share.party_keys.dk.clone())
+        .map_err(|e| format!("Keys file cannot be backed up, it would not restore: {}", e))?;
+    encode_shares(&secret, threshold, shares)
+}
+
+/// Rebuilds a keys file from at least threshold of the word lists of its backup and the public keygen record,
+/// taken from the keys file of any party. The restored party gets new Paillier keys and h1, h2, N~: returns
+/// the keys file and the update the other parties write in theirs with `recover-share --apply`.
+pub fn restore(record: KeyRecord, word_lists: &[String]) -> Result<(KeyShare, ShareUpdate), String> {
+    let secret = decode_shares(word_lists)?;
+    let parties = record.paillier_key_vector.len();
+    if secret.party_id == 0 || usize::from(secret.party_id) > parties {
+        return Err(format!("Backup of party {} does not belong to a key of {} parties", secret.party_id, parties));
+    }
+    let (ek, dk) = blum_paillier_keys();
+    let (dlog_statement, composite_dlog_proof) = create_dlog_statement();
+    let update = ShareUpdate {
+        party_id: secret.party_id,
+        correct_key_proof: NICorrectKeyProof::proof(&dk, None),
+        modulus_proof: BlumModulusProof::prove(&dk),
+        ek: ek.clone(),
+        dlog_statement,
+        composite_dlog_proof,
+    };
+    // The other parties refuse the update without a modulus proof that holds
+    check_paillier_modulus(&update.ek, &update.modulus_proof).map_err(|e| format!("Restore generated {}", e))?;
+    let mut key_share = rebuild(record, &secret, ek, dk)?;
+    write_keys(&mut key_share, &update);
+    Ok((key_share, update))
+}
+
+// Keys file of the party of `secret` on the public `record`. The share x_i is checked against the VSS
+// commitments, not u_i: a recovered party has no keygen secret u_i left and holds x_i in its place.
+fn rebuild(record: KeyRecord, secret: &BackupSecret, ek: EncryptionKey, dk: DecryptionKey) -> Result<KeyShare, String> {
+    let party_keys = Keys {
+        u_i: secret.u_i,
+        y_i: GE::generator() * &secret.u_i,
+        dk,
+        ek,
+        party_index: usize::from(secret.party_id),
+    };
+    let key_share = KeyShare::from_record(record, party_keys, secret.x_i);
+    key_share.validate()?;
+    if GE::generator() * &key_share.shared_keys.x_i != key_share.public_share(secret.party_id) {
+        return Err(format!("Backup of party {} belongs to another key", secret.party_id));
+    }
+    Ok(key_share)
+}
+
+/// Splits `secret` with Shamir's scheme over GF(256), byte by byte like SLIP-39, and writes each share
+/// as a list of BIP39 English words ending with a checksum.
+pub fn encode_shares(secret: &BackupSecret, threshold: u8, shares: u8) -> Result<Vec<String>, String> {
+    let secret = secret.to_bytes();
+    if threshold == 0 || threshold > shares || shares > MAX_SHARES {
+        return Err(format!("Backup needs 1 <= threshold <= shares <= {}, got {}-of-{}", MAX_SHARES, threshold, shares));
+    }
+    let random = SystemRandom::new();
+    let mut identifier = [0u8; 2];
+    random.fill(&mut identifier).map_err(|_| "No randomness for the backup".to_string())?;
+    // Random coefficients of the polynomial of each byte, whose constant is the secret byte
+    let mut coefficients = vec![0u8; secret.len() * usize::from(threshold - 1)];
+    random.fill(&mut coefficients).map_err(|_| "No randomness for the backup".to_string())?;
+
+    let word_list = Language::English.word_list();
+    let word_lists = (1..=shares)
+        .map(|x| {
+            let mut bytes = vec![BACKUP_VERSION, identifier[0], identifier[1], threshold, x];
+            for (i, byte) in secret.iter().enumerate() {
+                let higher = &coefficients[i * usize::from(threshold - 1)..(i + 1) * usize::from(threshold - 1)];
+                // Horner's rule, highest coefficient first
+                let y = higher.iter().rev().fold(0u8, |acc, a| gf_mul(acc, x) ^ a);
+                bytes.push(gf_mul(y, x) ^ byte);
+            }
+            let checksum = digest(&SHA256, &bytes);
+            bytes.extend_from_slice(&checksum.as_ref()[..CHECKSUM_LENGTH]);
+            to_words(&bytes).iter().map(|w| word_list[*w as usize]).collect::<Vec<&str>>().join(" ")
+        })
+        .collect();
+    Ok(word_lists)
+}
+
+/// Checks the word lists of a backup and combines threshold of them into the secret.
+pub fn decode_shares(word_lists: &[String]) -> Result<BackupSecret, String> {
+    let mut shares: Vec<Vec<u8>> = Vec::new();
+    for (n, word_list) in word_lists.iter().enumerate() {
+        let share = from_words(word_list).map_err(|e| format!("Word list {}: {}", n + 1, e))?;
+        if let Some(first) = shares.first() {
+            if share[..4] != first[..4] {
+                return Err(format!("Word list {} belongs to another backup", n + 1));
+            }
+        }
+        if shares.iter().any(|x| x[4] == share[4]) {
+            return Err(format!("Word list {} is given twice", n + 1));
+        }
+        shares.push(share);
+    }
+    let first = shares.first().ok_or("No word list given")?;
+    let threshold = usize::from(first[3]);
+    if shares.len() < threshold {
+        return Err(format!("{} word lists given, the backup needs {}", shares.len(), threshold));
+    }
+    let shares = &shares[..threshold];
+    let secret = (HEADER_LENGTH..first.len())
+        .map(|i| {
+            shares.iter().fold(0u8, |acc, share_j| {
+                // Lagrange coefficient at 0, subtraction is xor in GF(256)
+                let lambda = shares.iter()
+                    .filter(|share_m| share_m[4] != share_j[4])
+                    .fold(1u8, |acc, share_m| gf_mul(acc, gf_mul(share_m[4], gf_inv(share_m[4] ^ share_j[4]))));
+                acc ^ gf_mul(lambda, share_j[i])
+            })
+        })
+        .collect::<Vec<u8>>();
+    BackupSecret::from_bytes(&secret)
+}
+
+// Bytes of a share without its checksum, after checking it
+fn from_words(word_list: &str) -> Result<Vec<u8>, String> {
+    let indices = word_list.split_whitespace()
+        .map(|w| Language::English.find_word(&w.to_lowercase()).ok_or(format!("{:?} is not a backup word", w)))
+        .collect::<Result<Vec<u16>, String>>()?;
+    if indices.len() != WORDS_PER_SHARE {
+        return Err(format!("A backup share has {} words, got {}", WORDS_PER_SHARE, indices.len()));
+    }
+    let mut bytes: Vec<u8> = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0usize);
+    for index in indices {
+        buffer = buffer << BITS_PER_WORD | u32::from(index);
+        bits += BITS_PER_WORD;
+        while bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // The last word is padded with zero bits
+    let padding = bytes.split_off(SHARE_LENGTH);
+    if buffer != 0 || padding.iter().any(|x| *x != 0) {
+        return Err("Words do not form a backup share".to_string());
+    }
+    let (share, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LENGTH);
+    if &digest(&SHA256, share).as_ref()[..CHECKSUM_LENGTH] != checksum {
+        return Err("Checksum does not match, a word is wrong or missing".to_string());
+    }
+    if share[0] != BACKUP_VERSION {
+        return Err(format!("Backup version {} is not supported", share[0]));
+    }
+    if share[3] == 0 || share[4] == 0 {
+        return Err("Words do not form a backup share".to_string());
+    }
+    Ok(share.to_vec())
+}
+
+fn to_words(bytes: &[u8]) -> Vec<u16> {
+    let mut words: Vec<u16> = Vec::new();
+    let (mut buffer, mut bits) = (0u32, 0usize);
+    for byte in bytes {
+        buffer = buffer << 8 | u32::from(*byte);
+        bits += 8;
+        if bits >= BITS_PER_WORD {
+            bits -= BITS_PER_WORD;
+            words.push((buffer >> bits) as u16);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bits > 0 {
+        words.push((buffer << (BITS_PER_WORD - bits)) as u16);
+    }
+    words
+}
+
+// GF(256) with the AES polynomial x^8 + x^4 + x^3 + x + 1, like SLIP-39
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    while b != 0 {
+        if b & 1 != 0 {
+            product ^= a;
+        }
+        let carry = a & 0x80 != 0;
+        a <<= 1;
+        if carry {
+            a ^= 0x1b;
+        }
+        b >>= 1;
+    }
+    product
+}
+
+// a^254 = a^2 * a^4 * ... * a^128, the inverse of a non-zero element
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut power = a;
+    for _ in 0..7 {
+        power = gf_mul(power, power);
+        result = gf_mul(result, power);
+    }
+    result
+}
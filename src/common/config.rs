@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::{env, fmt, fs};
+
+use serde::Deserialize;
+
+use crate::common::auth::{normalize_fingerprint, CertificateGrant, Role, TokenGrant};
+
+pub const CONFIG_PATH_ENV: &str = "TSS_CLI_CONFIG";
+pub const PROFILE_ENV: &str = "TSS_CLI_PROFILE";
+pub const DEFAULT_CONFIG_PATH: &str = "tss.toml";
+pub const DEFAULT_PROFILE: &str = "default";
+
+pub const POLL_TIMEOUT_ENV: &str = "TSS_CLI_POLL_TIMEOUT";
+pub const SIGNUP_TIMEOUT_ENV: &str = "TSS_CLI_SIGNUP_TIMEOUT";
+pub const MANAGER_TTL_ENV: &str = "TSS_CLI_MANAGER_TTL";
+pub const MANAGER_SIGNUP_TIMEOUT_ENV: &str = "TSS_MANAGER_SIGNUP_TIMEOUT";
+pub const ADMIN_TOKEN_ENV: &str = "TSS_MANAGER_ADMIN_TOKEN";
+pub const AUTH_TOKEN_ENV: &str = "TSS_CLI_AUTH_TOKEN";
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct ConfigError {
+    pub error: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration: {}", self.error)
+    }
+}
+
+/// Settings of the keygen and sign clients.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ClientConfig {
+    pub manager_addr: String,
+    pub poll_timeout: u64,    // Seconds to wait for a message of another party
+    pub poll_delay: u64,      // Milliseconds between two polls
+    pub signup_timeout: u64,  // Seconds to wait for a signing room to fill up
+    pub request_timeout: u64, // Seconds before a request to the manager is abandoned
+    pub auth_token: Option<String>, // Bearer token sent to the manager
+    pub client_identity: Option<String>, // PKCS#12 file with the client certificate and key, for mTLS
+    pub client_identity_password: Option<String>,
+    pub ca_cert: Option<String>, // PEM file of a CA to trust for the manager's certificate
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            manager_addr: "http://127.0.0.1:8001".to_string(),
+            poll_timeout: 30,
+            poll_delay: 25,
+            signup_timeout: 30,
+            request_timeout: 600,
+            auth_token: None,
+            client_identity: None,
+            client_identity_password: None,
+            ca_cert: None,
+        }
+    }
+}
+
+/// Settings of the manager.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ManagerConfig {
+    pub ttl: u64,            // Seconds an entry is kept
+    pub signup_timeout: u64, // Seconds without a ping before a signing party is considered gone
+    pub admin_token: Option<String>, // Shorthand for a token with the admin role
+    pub tokens: Vec<TokenGrant>,
+    pub client_certs: Vec<CertificateGrant>,
+    pub client_rate_limit: u32,       // Requests per second from one client address, 0 for no limit
+    pub room_rate_limit: u32,         // Requests per second for one room or session, 0 for no limit
+    pub max_value_size: usize,        // Bytes of a stored value
+    pub max_session_entries: u32,     // Entries stored for one session
+    pub max_rooms: usize,             // Sessions and signing rooms held at once
+}
+
+impl Default for ManagerConfig {
+    fn default() -> Self {
+        ManagerConfig {
+            ttl: 300,
+            signup_timeout: 2,
+            admin_token: None,
+            tokens: vec![],
+            client_certs: vec![],
+            client_rate_limit: 500,
+            room_rate_limit: 2000,
+            max_value_size: 256 * 1024,
+            max_session_entries: 4096,
+            max_rooms: 1000,
+        }
+    }
+}
+
+impl ManagerConfig {
+
+    /// Party routes are open unless at least one party credential is configured
+    pub fn requires_auth(&self) -> bool {
+        !self.tokens.is_empty() || !self.client_certs.is_empty()
+    }
+
+    pub fn token_grants(&self) -> Vec<TokenGrant> {
+        let mut grants = self.tokens.clone();
+        if let Some(token) = &self.admin_token {
+            grants.push(TokenGrant {
+                token: token.clone(),
+                roles: vec![Role::Admin],
+            });
+        }
+        grants
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Config {
+    pub client: ClientConfig,
+    pub manager: ManagerConfig,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientSection {
+    manager_addr: Option<String>,
+    poll_timeout: Option<u64>,
+    poll_delay: Option<u64>,
+    signup_timeout: Option<u64>,
+    request_timeout: Option<u64>,
+    auth_token: Option<String>,
+    client_identity: Option<String>,
+    client_identity_password: Option<String>,
+    ca_cert: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ManagerSection {
+    ttl: Option<u64>,
+    signup_timeout: Option<u64>,
+    admin_token: Option<String>,
+    tokens: Option<Vec<TokenGrant>>,
+    client_certs: Option<Vec<CertificateGrant>>,
+    client_rate_limit: Option<u32>,
+    room_rate_limit: Option<u32>,
+    max_value_size: Option<usize>,
+    max_session_entries: Option<u32>,
+    max_rooms: Option<usize>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    client: Option<ClientSection>,
+    manager: Option<ManagerSection>,
+}
+
+impl Config {
+
+    /// Loads the settings of `profile` (or `TSS_CLI_PROFILE`, or `default`) from the file at `path`
+    /// (or `TSS_CLI_CONFIG`, or `tss.toml` if present). Values of the file override env vars, the
+    /// named profile overrides the `default` one.
+    pub fn load(path: Option<&str>, profile: Option<&str>) -> Result<Self, ConfigError> {
+        let explicit_path = path.map(String::from).or(env::var(CONFIG_PATH_ENV).ok());
+        let profile = profile.map(String::from)
+            .or(env::var(PROFILE_ENV).ok())
+            .unwrap_or(DEFAULT_PROFILE.to_string());
+
+        let data = match &explicit_path {
+            Some(path) => Some(fs::read_to_string(path).map_err(|e| ConfigError{
+                error: format!("unable to read {}: {}", path, e)
+            })?),
+            None => fs::read_to_string(DEFAULT_CONFIG_PATH).ok(),
+        };
+
+        match data {
+            Some(data) => Config::from_toml(&data, &profile),
+            None if profile == DEFAULT_PROFILE => {
+                let config = Config::from_env()?;
+                config.validate()?;
+                Ok(config)
+            },
+            None => Err(ConfigError{
+                error: format!("profile {:?} requested but no config file found", profile)
+            }),
+        }
+    }
+
+    pub fn from_toml(data: &str, profile: &str) -> Result<Self, ConfigError> {
+        let profiles: HashMap<String, Profile> = toml::from_str(data).map_err(|e| ConfigError{
+            error: e.to_string()
+        })?;
+
+        let mut config = Config::from_env()?;
+        if let Some(default_profile) = profiles.get(DEFAULT_PROFILE) {
+            config.apply(default_profile);
+        }
+        if profile != DEFAULT_PROFILE {
+            match profiles.get(profile) {
+                Some(named_profile) => config.apply(named_profile),
+                None => return Err(ConfigError{
+                    error: format!("profile {:?} not found", profile)
+                }),
+            }
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let defaults = Config::default();
+        Ok(Config {
+            client: ClientConfig {
+                poll_timeout: env_u64(POLL_TIMEOUT_ENV, defaults.client.poll_timeout)?,
+                signup_timeout: env_u64(SIGNUP_TIMEOUT_ENV, defaults.client.signup_timeout)?,
+                auth_token: env::var(AUTH_TOKEN_ENV).ok().filter(|x| !x.is_empty()),
+                ..defaults.client
+            },
+            manager: ManagerConfig {
+                ttl: env_u64(MANAGER_TTL_ENV, defaults.manager.ttl)?,
+                signup_timeout: env_u64(MANAGER_SIGNUP_TIMEOUT_ENV, defaults.manager.signup_timeout)?,
+                admin_token: env::var(ADMIN_TOKEN_ENV).ok().filter(|x| !x.is_empty()),
+                ..defaults.manager
+            },
+        })
+    }
+
+    fn apply(&mut self, profile: &Profile) {
+        if let Some(client) = &profile.client {
+            if let Some(x) = &client.manager_addr { self.client.manager_addr = x.clone() }
+            if let Some(x) = client.poll_timeout { self.client.poll_timeout = x }
+            if let Some(x) = client.poll_delay { self.client.poll_delay = x }
+            if let Some(x) = client.signup_timeout { self.client.signup_timeout = x }
+            if let Some(x) = client.request_timeout { self.client.request_timeout = x }
+            if let Some(x) = &client.auth_token { self.client.auth_token = Some(x.clone()) }
+            if let Some(x) = &client.client_identity { self.client.client_identity = Some(x.clone()) }
+            if let Some(x) = &client.client_identity_password { self.client.client_identity_password = Some(x.clone()) }
+            if let Some(x) = &client.ca_cert { self.client.ca_cert = Some(x.clone()) }
+        }
+        if let Some(manager) = &profile.manager {
+            if let Some(x) = manager.ttl { self.manager.ttl = x }
+            if let Some(x) = manager.signup_timeout { self.manager.signup_timeout = x }
+            if let Some(x) = &manager.admin_token { self.manager.admin_token = Some(x.clone()) }
+            if let Some(x) = &manager.tokens { self.manager.tokens = x.clone() }
+            if let Some(x) = &manager.client_certs { self.manager.client_certs = x.clone() }
+            if let Some(x) = manager.client_rate_limit { self.manager.client_rate_limit = x }
+            if let Some(x) = manager.room_rate_limit { self.manager.room_rate_limit = x }
+            if let Some(x) = manager.max_value_size { self.manager.max_value_size = x }
+            if let Some(x) = manager.max_session_entries { self.manager.max_session_entries = x }
+            if let Some(x) = manager.max_rooms { self.manager.max_rooms = x }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let addr = &self.client.manager_addr;
+        if !(addr.starts_with("http://") || addr.starts_with("https://")) {
+            return Err(ConfigError{
+                error: format!("manager_addr must be an http(s) URL, got {:?}", addr)
+            });
+        }
+        let positive = [
+            ("client.poll_timeout", self.client.poll_timeout),
+            ("client.poll_delay", self.client.poll_delay),
+            ("client.signup_timeout", self.client.signup_timeout),
+            ("client.request_timeout", self.client.request_timeout),
+            ("manager.ttl", self.manager.ttl),
+            ("manager.signup_timeout", self.manager.signup_timeout),
+            ("manager.max_value_size", self.manager.max_value_size as u64),
+            ("manager.max_session_entries", self.manager.max_session_entries as u64),
+            ("manager.max_rooms", self.manager.max_rooms as u64),
+        ];
+        for (name, value) in positive.iter() {
+            if *value == 0 {
+                return Err(ConfigError{
+                    error: format!("{} must be greater than zero", name)
+                });
+            }
+        }
+        for grant in self.manager.tokens.iter() {
+            if grant.token.is_empty() || grant.roles.is_empty() {
+                return Err(ConfigError{
+                    error: "manager.tokens entries need a token and at least one role".to_string()
+                });
+            }
+        }
+        for grant in self.manager.client_certs.iter() {
+            let fingerprint = normalize_fingerprint(&grant.fingerprint);
+            if fingerprint.len() != 64 || hex::decode(&fingerprint).is_err() || grant.roles.is_empty() {
+                return Err(ConfigError{
+                    error: format!("manager.client_certs entry {:?} needs a SHA-256 fingerprint and at least one role", grant.fingerprint)
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn env_u64(name: &str, default: u64) -> Result<u64, ConfigError> {
+    match env::var(name) {
+        Ok(value) => value.trim().parse::<u64>().map_err(|_| ConfigError{
+            error: format!("{} must be a number of seconds, got {:?}", name, value)
+        }),
+        Err(_) => Ok(default),
+    }
+}
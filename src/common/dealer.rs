@@ -0,0 +1,197 @@
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::arithmetic::Converter;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use ring::digest::{digest, SHA256};
+
+use crate::common::keygen::create_dlog_statement;
+use crate::common::paillier_keys::blum_paillier_keys;
+use crate::common::{KeyShare, Params, Protocol};
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Splits `secret` into the keys files of a `threshold`/`parties` key, as keygen would have produced them:
+/// the secret is cut into one random part per party, each part is shared with Feldman VSS, and each party
+/// gets fresh Paillier keys with a Blum modulus and h1, h2, N~, like at keygen. The dealer sees every share,
+/// so it must be erased afterwards.
+pub fn split_key(secret: &FE, threshold: u16, parties: u16) -> Result<Vec<KeyShare>, String> {
+    Params::new(threshold, parties).values()?;
+
+    // u_1 + ... + u_n = secret, u_i * G is the constant commitment of party i's scheme like at keygen
+    let mut u_vec: Vec<FE> = (1..parties).map(|_| ECScalar::new_random()).collect();
+    let (head, tail) = u_vec.split_at(1);
+    let others = tail.iter().fold(head[0], |acc, x| acc + x);
+    u_vec.push(secret.sub(&others.get_element()));
+
+    let (vss_scheme_vec, shares_vec): (Vec<VerifiableSS<GE>>, Vec<Vec<FE>>) = u_vec.iter()
+        .map(|u_i| VerifiableSS::share(threshold as usize, parties as usize, u_i))
+        .unzip();
+    let y_sum = GE::generator() * secret;
+
+    let party_keys_vec = (1..=parties)
+        .map(|i| {
+            let mut party_keys = Keys::create(i as usize);
+            let (ek, dk) = blum_paillier_keys();
+            party_keys.ek = ek;
+            party_keys.dk = dk;
+            party_keys.u_i = u_vec[(i - 1) as usize];
+            party_keys.y_i = GE::generator() * &party_keys.u_i;
+            party_keys
+        })
+        .collect::<Vec<Keys>>();
+    let paillier_key_vector = party_keys_vec.iter().map(|x| x.ek.clone()).collect::<Vec<_>>();
+    let dlog_statement_vec = (0..parties).map(|_| create_dlog_statement().0).collect::<Vec<_>>();
+
+    let key_shares = party_keys_vec.into_iter().enumerate()
+        .map(|(i, party_keys)| {
+            let (head, tail) = shares_vec.split_at(1);
+            let x_i = tail.iter().fold(head[0][i], |acc, shares_j| acc + shares_j[i]);
+            KeyShare {
+                protocol: Protocol::Gg18,
+                party_keys,
+                shared_keys: SharedKeys { y: y_sum, x_i },
+                party_id: i as u16 + 1,
+                vss_scheme_vec: vss_scheme_vec.clone(),
+                paillier_key_vector: paillier_key_vector.clone(),
+                dlog_statement_vec: dlog_statement_vec.clone(),
+                y_sum,
+            }
+        })
+        .collect::<Vec<KeyShare>>();
+    for key_share in key_shares.iter() {
+        key_share.validate()?;
+    }
+    Ok(key_shares)
+}
+
+/// Interpolates the private key from the keys files of at least threshold + 1 distinct parties of the same key,
+/// after checking each share against the VSS commitments. With a `path` the key derived at it is returned.
+pub fn reconstruct_key(key_shares: &[KeyShare], path: &str) -> Result<FE, String> {
+    let first = key_shares.first().ok_or("No keys file given")?;
+    let (threshold, _parties) = first.params().values()?;
+    let mut signers_vec: Vec<usize> = Vec::new();
+    for key_share in key_shares {
+        key_share.validate().map_err(|e| format!("Party {}: {}", key_share.party_id, e))?;
+        if key_share.y_sum != first.y_sum || key_share.vss_scheme_vec != first.vss_scheme_vec {
+            return Err(format!("Keys file of party {} belongs to another key", key_share.party_id));
+        }
+        let index = (key_share.party_id - 1) as usize;
+        if signers_vec.contains(&index) {
+            return Err(format!("Party {} is given twice", key_share.party_id));
+        }
+        if GE::generator() * &key_share.shared_keys.x_i != key_share.public_share(key_share.party_id) {
+            return Err(format!("Share of party {} does not match the VSS commitments", key_share.party_id));
+        }
+        signers_vec.push(index);
+    }
+    if signers_vec.len() <= threshold as usize {
+        return Err(format!(
+            "{} keys files given, the key needs {} (threshold + 1)", signers_vec.len(), threshold + 1
+        ));
+    }
+
+    let secret = key_shares.iter().zip(signers_vec.iter())
+        .map(|(key_share, index)| {
+            first.vss_scheme_vec[*index].map_share_to_new_params(*index, &signers_vec) * key_share.shared_keys.x_i
+        })
+        .fold(None, |acc: Option<FE>, x| Some(acc.map_or(x, |acc| acc + x)))
+        .unwrap();
+    if GE::generator() * &secret != first.y_sum {
+        return Err("Interpolated key does not match the public key of the keys files".to_string());
+    }
+    if path.is_empty() {
+        return Ok(secret);
+    }
+    let (f_l_new, public_key) = first.derive(path)?;
+    let derived = secret + f_l_new;
+    if GE::generator() * &derived != public_key {
+        return Err(format!("Key derived at {} does not match the derived public key", path));
+    }
+    Ok(derived)
+}
+
+/// Encodes a private key in compressed mainnet Wallet Import Format.
+pub fn encode_wif(secret: &FE) -> String {
+    let mut payload = vec![0x80];
+    let bytes = BigInt::to_bytes(&secret.to_big_int());
+    payload.extend(std::iter::repeat(0).take(32 - bytes.len()));
+    payload.extend_from_slice(&bytes);
+    payload.push(0x01);
+    let hash = digest(&SHA256, digest(&SHA256, &payload).as_ref());
+    payload.extend_from_slice(&hash.as_ref()[..4]);
+    encode_base58(&payload)
+}
+
+/// Reads a secp256k1 private key given as 32 bytes of hex or in Wallet Import Format.
+pub fn parse_private_key(value: &str) -> Result<FE, String> {
+    let value = value.trim();
+    let bytes = match hex::decode(value) {
+        Ok(bytes) if bytes.len() == 32 => bytes,
+        _ => decode_wif(value)?,
+    };
+    let scalar = BigInt::from_bytes(&bytes);
+    if scalar == BigInt::from(0) || scalar >= FE::q() {
+        return Err("Private key is not between 1 and the curve order".to_string());
+    }
+    Ok(ECScalar::from(&scalar))
+}
+
+// Base58Check with a 0x80 (mainnet) or 0xef (testnet) version byte, and a 0x01 suffix for compressed keys
+fn decode_wif(value: &str) -> Result<Vec<u8>, String> {
+    let invalid = || "Private key is neither 32 bytes of hex nor a WIF key".to_string();
+    let data = decode_base58(value).ok_or_else(invalid)?;
+    if data.len() < 5 {
+        return Err(invalid());
+    }
+    let (payload, checksum) = data.split_at(data.len() - 4);
+    let hash = digest(&SHA256, digest(&SHA256, payload).as_ref());
+    if &hash.as_ref()[..4] != checksum {
+        return Err("WIF key checksum does not match".to_string());
+    }
+    match (payload[0], payload.len()) {
+        (0x80, 33) | (0xef, 33) => Ok(payload[1..].to_vec()),
+        (0x80, 34) | (0xef, 34) if payload[33] == 0x01 => Ok(payload[1..33].to_vec()),
+        _ => Err(invalid()),
+    }
+}
+
+fn encode_base58(data: &[u8]) -> String {
+    let mut digits: Vec<u8> = Vec::new(); // Little endian, base 58
+    for byte in data {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    // Each leading zero byte is written as '1'
+    digits.extend(data.iter().take_while(|x| **x == 0).map(|_| 0));
+    digits.iter().rev().map(|x| BASE58_ALPHABET[*x as usize] as char).collect()
+}
+
+fn decode_base58(value: &str) -> Option<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new(); // Little endian
+    for c in value.bytes() {
+        let mut carry = BASE58_ALPHABET.iter().position(|x| *x == c)? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    // Each leading '1' stands for a zero byte
+    bytes.extend(value.bytes().take_while(|x| *x == b'1').map(|_| 0));
+    bytes.reverse();
+    Some(bytes)
+}
@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+
+use curv::arithmetic::{Converter, Modulo};
+use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::ed25519::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::BigInt;
+use ring::digest::{digest, Context, SHA512};
+use ring::signature::{UnparsedPublicKey, ED25519};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{field, info, instrument, Span};
+
+use crate::common::error::decode;
+use crate::common::frost::{nonce_points, public_share, sum_points, NonceCommitments};
+use crate::common::key_share::{load_keys_file, match_params, vss_params, write_keys_file};
+use crate::common::keygen::keygen_signup;
+use crate::common::p2p::P2pChannel;
+use crate::common::signer::{format_vec_from_reads, round0, signup, Round0Message, SignOptions, SigningContext};
+use crate::common::{
+    block_on, broadcast, poll_for_broadcasts, poll_for_p2p, sendp2p, sha256_digest, ClientConfig, Error, Params,
+    PartySignup, Protocol, Transport,
+};
+
+/// A party's output of the Ed25519 keygen, i.e. the content of its keys file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EdKeyShare {
+    pub protocol: Protocol, // Always frost-ed25519, tells these files apart from the secp256k1 ones
+    pub party_id: u16,
+    pub x_i: FE, // Share of the secret key
+    pub vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    pub public_key: GE,
+}
+
+/// Keygen round 1 message: the party's VSS commitments and a proof that it knows the secret it shares.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct KeygenRound1 {
+    vss_scheme: VerifiableSS<GE>,
+    dlog_proof: DLogProof<GE>,
+}
+
+impl EdKeyShare {
+
+    pub fn load(keysfile_path: &str) -> Result<Self, Error> {
+        load_keys_file(keysfile_path)
+    }
+
+    pub fn save(&self, keysfile_path: &str) -> Result<(), Error> {
+        write_keys_file(keysfile_path, self)
+    }
+
+    /// Threshold params the keys were generated with
+    pub fn params(&self) -> Params {
+        vss_params(&self.vss_scheme_vec)
+    }
+
+    /// Checks that the parts of the keys file agree with each other.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.protocol != Protocol::FrostEd25519 {
+            return Err(format!("Keys file holds {} keys, not frost-ed25519 ones", self.protocol));
+        }
+        if self.vss_scheme_vec.is_empty() {
+            return Err("Keys file holds no VSS scheme".to_string());
+        }
+        let parameters = &self.vss_scheme_vec[0].parameters;
+        let parties = parameters.share_count;
+        if self.vss_scheme_vec.iter().any(|x| x.parameters != *parameters) || self.vss_scheme_vec.len() != parties {
+            return Err(format!("Keys file holds {} VSS schemes for {} parties", self.vss_scheme_vec.len(), parties));
+        }
+        if self.party_id == 0 || usize::from(self.party_id) > parties {
+            return Err(format!("Party id {} of the keys file is not between 1 and {}", self.party_id, parties));
+        }
+        if sum_points(self.vss_scheme_vec.iter().map(|x| x.commitments[0])) != self.public_key {
+            return Err("Public key of the keys file does not match its VSS commitments".to_string());
+        }
+        if GE::generator() * &self.x_i != public_share(&self.vss_scheme_vec, self.party_id) {
+            return Err("Share of the keys file does not match its VSS commitments".to_string());
+        }
+        self.params().values().map(|_| ())
+    }
+
+    /// Checks threshold params given for signing against the ones of the keys file.
+    pub fn check_params(&self, params: &Params) -> Result<(), String> {
+        match_params(params, &self.params())
+    }
+
+    /// Public key in its RFC 8032 encoding.
+    pub fn public_key_hex(&self) -> String {
+        hex::encode(self.public_key.pk_to_key_slice())
+    }
+}
+
+/// Result of an Ed25519 signing session: the 64 bytes RFC 8032 signature and the public key it verifies against.
+#[derive(Clone, Debug)]
+pub struct EdSignResult {
+    pub signature: Vec<u8>,
+    pub public_key: GE,
+    pub message: Vec<u8>,
+}
+
+impl EdSignResult {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "r": hex::encode(&self.signature[..32]),
+            "s": hex::encode(&self.signature[32..]),
+            "signature": hex::encode(&self.signature),
+            "status": "signature_ready",
+            "pubkey": hex::encode(self.public_key.pk_to_key_slice()),
+            "msg": hex::encode(&self.message),
+        })
+    }
+}
+
+pub fn run_keygen(transport: &dyn Transport, config: &ClientConfig, THRESHOLD: u16, PARTIES: u16) -> Result<EdKeyShare, Error> {
+    block_on(run_keygen_async(transport, config, THRESHOLD, PARTIES))
+}
+
+/// Ed25519 keygen: each party shares a random secret with Feldman VSS and proves it knows it, the key is the sum.
+#[instrument(skip(transport, config), fields(party = field::Empty, uuid = field::Empty))]
+pub async fn run_keygen_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    THRESHOLD: u16,
+    PARTIES: u16,
+) -> Result<EdKeyShare, Error> {
+    Params::new(THRESHOLD, PARTIES).values().map_err(Error::InvalidInput)?;
+    let signup = keygen_signup(transport, config, THRESHOLD, PARTIES, Protocol::FrostEd25519).await;
+    let (party_num_int, uuid) = match signup? {
+        PartySignup { number, uuid } => (number, uuid),
+    };
+    Span::current().record("party", &party_num_int);
+    Span::current().record("uuid", &uuid.as_str());
+    info!("Signed up for keygen");
+
+    // round 1: send the VSS commitments with a proof of knowledge of the shared secret
+    let u_i: FE = ECScalar::new_random();
+    let (vss_scheme, secret_shares) = VerifiableSS::share(THRESHOLD as usize, PARTIES as usize, &u_i);
+    let round1 = KeygenRound1 {
+        vss_scheme,
+        dlog_proof: DLogProof::prove(&u_i),
+    };
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&round1).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+    let mut round1_vec: Vec<KeygenRound1> = Vec::new();
+    format_vec_from_reads("round1", &round1_ans_vec, party_num_int as usize, round1, &mut round1_vec)?;
+
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    for (i, round1_j) in (1..=PARTIES).zip(round1_vec.iter()) {
+        let parameters = &round1_j.vss_scheme.parameters;
+        if (parameters.threshold, parameters.share_count) != (THRESHOLD as usize, PARTIES as usize)
+            || round1_j.vss_scheme.commitments.len() != THRESHOLD as usize + 1
+            || round1_j.dlog_proof.pk != round1_j.vss_scheme.commitments[0]
+            || DLogProof::verify(&round1_j.dlog_proof).is_err()
+        {
+            return Err(Error::Protocol(format!("party {} sent invalid VSS commitments or dlog proof", i)));
+        }
+        if i != party_num_int {
+            shared_secrets.insert(i, vec![round1_j.vss_scheme.commitments[0] * &u_i]);
+        }
+    }
+
+    // round 2: send each party its share, sealed with keys from the committed secrets
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+    for (k, i) in (1..=PARTIES).enumerate() {
+        if i != party_num_int {
+            sendp2p(
+                transport,
+                party_num_int,
+                i,
+                "round2",
+                channel.seal(i, "round2", &serde_json::to_string(&secret_shares[k]).unwrap()),
+                uuid.clone(),
+            )
+            .await?;
+        }
+    }
+    let round2_ans_vec = poll_for_p2p(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round2",
+        uuid.clone(),
+    )
+    .await?;
+    let round2_ans_vec = channel.open_all("round2", &round2_ans_vec)?;
+    let mut party_shares: Vec<FE> = Vec::new();
+    format_vec_from_reads(
+        "round2",
+        &round2_ans_vec,
+        party_num_int as usize,
+        secret_shares[(party_num_int - 1) as usize],
+        &mut party_shares,
+    )?;
+    for (i, (share_j, round1_j)) in party_shares.iter().zip(round1_vec.iter()).enumerate() {
+        if round1_j.vss_scheme.validate_share(share_j, party_num_int as usize).is_err() {
+            return Err(Error::Protocol(format!("party {} sent a share that does not match its commitments", i + 1)));
+        }
+    }
+
+    let vss_scheme_vec = round1_vec.into_iter().map(|x| x.vss_scheme).collect::<Vec<_>>();
+    let (head, tail) = party_shares.split_at(1);
+    let x_i = tail.iter().fold(head[0], |acc, x| acc + x);
+    let public_key = sum_points(vss_scheme_vec.iter().map(|x| x.commitments[0]));
+
+    // round 3: confirm that all parties got valid shares of the same key
+    broadcast(
+        transport,
+        party_num_int,
+        "round3",
+        serde_json::to_string(&public_key).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round3_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round3",
+        uuid.clone(),
+    )
+    .await?;
+    for answer in round3_ans_vec.iter() {
+        if decode::<GE>("round3", answer)? != public_key {
+            return Err(Error::Protocol("parties computed different public keys".to_string()));
+        }
+    }
+
+    Ok(EdKeyShare {
+        protocol: Protocol::FrostEd25519,
+        party_id: party_num_int,
+        x_i,
+        vss_scheme_vec,
+        public_key,
+    })
+}
+
+pub fn sign(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &EdKeyShare,
+    params: &Params,
+    options: &SignOptions,
+    message: &[u8],
+) -> Result<EdSignResult, Error> {
+    block_on(sign_async(transport, config, key_share, params, options, message))
+}
+
+/// FROST signing of an RFC 8032 Ed25519 signature, in two rounds after round 0. Every signer's partial
+/// signature is checked against its share.
+#[instrument(skip(transport, config, key_share, params, options, message), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn sign_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &EdKeyShare,
+    params: &Params,
+    options: &SignOptions,
+    message: &[u8],
+) -> Result<EdSignResult, Error> {
+    key_share.validate().and_then(|_| key_share.check_params(params)).map_err(Error::InvalidInput)?;
+    if options.taproot.is_some() {
+        return Err(Error::InvalidInput("Taproot tweaks only apply to Schnorr signatures".to_string()));
+    }
+    let (THRESHOLD, PARTIES) = params.values().map_err(Error::InvalidInput)?;
+    let party_id = key_share.party_id;
+    let message_hash = sha256_digest(message);
+    let room_id = options.room_id.clone().unwrap_or(format!("ed25519-{}", message_hash));
+    Span::current().record("room_id", &room_id.as_str());
+
+    let context = SigningContext {
+        message_hash,
+        path: "".to_string(),
+        public_key: key_share.public_key,
+        threshold: THRESHOLD,
+        parties: PARTIES,
+        protocol: Protocol::FrostEd25519,
+        taproot: None,
+        allow_legacy_keys: false,
+    };
+    let (party_num_int, uuid, total_parties) = match signup(
+        transport, config, context.threshold, room_id, party_id, context.protocol, &options.policy
+    ).await? {
+        (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
+    };
+    Span::current().record("party", &party_num_int);
+    Span::current().record("room_uuid", &uuid.as_str());
+    info!("Signing room is ready");
+
+    let round0_message = Round0Message {
+        party_id,
+        commitment: context.commitment(),
+        p2p_key: None,
+    };
+    let round0_vec = round0(transport, config, party_num_int, total_parties, &uuid, round0_message, &context).await?;
+    let signers_vec = round0_vec.iter().map(|x| (x.party_id - 1) as usize).collect::<Vec<usize>>();
+    let index = (party_num_int - 1) as usize;
+    let public_key = key_share.public_key;
+
+    // round 1: commit to the nonces
+    let (hiding, binding, commitments) = NonceCommitments::<GE>::create();
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&commitments).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+    let mut commitments_vec: Vec<NonceCommitments<GE>> = Vec::new();
+    format_vec_from_reads("round1", &round1_ans_vec, party_num_int as usize, commitments, &mut commitments_vec)?;
+
+    let mut prefix = public_key.pk_to_key_slice();
+    prefix.extend_from_slice(digest(&SHA512, message).as_ref());
+    let nonce_points = nonce_points(
+        prefix,
+        &signers_vec,
+        &commitments_vec,
+        |point| point.pk_to_key_slice(),
+        |binding_input, party_id| {
+            hash_to_scalar(&[b"tss-cli/FROST-ed25519/rho", binding_input, &party_id.to_be_bytes()])
+        },
+    );
+    let R = sum_points(nonce_points.iter().map(|(_, r_j)| *r_j));
+    // RFC 8032 challenge
+    let k = hash_to_scalar(&[&R.pk_to_key_slice(), &public_key.pk_to_key_slice(), message]);
+
+    // round 2: send the partial signature, check the others' against their shares
+    let lambda = |j: usize| key_share.vss_scheme_vec[signers_vec[j]].map_share_to_new_params(signers_vec[j], &signers_vec);
+    let z_i = hiding + binding * nonce_points[index].0 + k * lambda(index) * key_share.x_i;
+    broadcast(
+        transport,
+        party_num_int,
+        "round2",
+        serde_json::to_string(&z_i).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round2_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round2",
+        uuid.clone(),
+    )
+    .await?;
+    let mut z_vec: Vec<FE> = Vec::new();
+    format_vec_from_reads("round2", &round2_ans_vec, party_num_int as usize, z_i, &mut z_vec)?;
+
+    for (j, z_j) in z_vec.iter().enumerate() {
+        let party_id = signers_vec[j] as u16 + 1;
+        let y_j = public_share(&key_share.vss_scheme_vec, party_id);
+        if GE::generator() * z_j != nonce_points[j].1 + y_j * &(k * lambda(j)) {
+            return Err(Error::Protocol(format!("party {} sent an invalid partial signature", party_id)));
+        }
+    }
+
+    let (head, tail) = z_vec.split_at(1);
+    let z = tail.iter().fold(head[0], |acc, z_j| acc + z_j);
+    let mut signature = R.pk_to_key_slice();
+    signature.extend_from_slice(&scalar_le_bytes(&z));
+    // Checked by an independent Ed25519 implementation, as a verifier will
+    UnparsedPublicKey::new(&ED25519, public_key.pk_to_key_slice())
+        .verify(message, &signature)
+        .map_err(|_| Error::Protocol("the Ed25519 signature does not verify".to_string()))?;
+
+    Ok(EdSignResult {
+        signature,
+        public_key,
+        message: message.to_vec(),
+    })
+}
+
+/// SHA-512 of the parts, read little endian and reduced, as RFC 8032 does.
+pub(crate) fn hash_to_scalar(parts: &[&[u8]]) -> FE {
+    let mut context = Context::new(&SHA512);
+    for part in parts {
+        context.update(part);
+    }
+    let mut hash = context.finish().as_ref().to_vec();
+    hash.reverse();
+    ECScalar::from(&BigInt::from_bytes(&hash).modulus(&FE::q()))
+}
+
+/// Little endian encoding of a scalar, the S half of an RFC 8032 signature.
+pub(crate) fn scalar_le_bytes(scalar: &FE) -> [u8; 32] {
+    let bytes = BigInt::to_bytes(&scalar.to_big_int());
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    padded.reverse();
+    padded
+}
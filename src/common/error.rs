@@ -0,0 +1,51 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+
+use crate::common::{ConfigError, ManagerError};
+
+/// Why a keygen, signing or recovery session did not complete. The library entry points return
+/// it instead of panicking, the CLI prints it and exits.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Error {
+    /// The parameters, options or keys file given by the caller cannot be used.
+    InvalidInput(String),
+    /// A keys file could not be read or written.
+    File(String),
+    /// The manager could not be reached, refused a request or answered something unexpected.
+    Manager(String),
+    /// A party did not send its message of a round in time.
+    Timeout(String),
+    /// Another party sent a malformed message or a failed proof, the session was aborted.
+    Protocol(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidInput(error) | Error::File(error) | Error::Timeout(error) => write!(f, "{}", error),
+            Error::Manager(error) => write!(f, "Manager error: {}", error),
+            Error::Protocol(error) => write!(f, "Session aborted: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ManagerError> for Error {
+    fn from(e: ManagerError) -> Self {
+        Error::Manager(e.error)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::InvalidInput(e.to_string())
+    }
+}
+
+/// Parses the message another party sent in `round`, a malformed one aborts the session.
+pub fn decode<T: DeserializeOwned>(round: &str, data: &str) -> Result<T, Error> {
+    serde_json::from_str(data)
+        .map_err(|e| Error::Protocol(format!("malformed {} message: {}", round, e)))
+}
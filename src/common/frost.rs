@@ -0,0 +1,71 @@
+use std::ops::{Add, Mul};
+
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use serde::{Deserialize, Serialize};
+
+/// FROST round 1 message: commitments to the signer's hiding and binding nonces.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NonceCommitments<P> {
+    pub hiding: P,
+    pub binding: P,
+}
+
+impl<P> NonceCommitments<P>
+where
+    P: ECPoint + Copy + Mul<<P as ECPoint>::Scalar, Output = P>,
+    P::Scalar: ECScalar + Copy,
+{
+    /// Draws the signer's hiding and binding nonces, returns them with their commitments.
+    pub fn create() -> (P::Scalar, P::Scalar, Self) {
+        let hiding: P::Scalar = ECScalar::new_random();
+        let binding: P::Scalar = ECScalar::new_random();
+        let commitments = NonceCommitments {
+            hiding: P::generator() * hiding,
+            binding: P::generator() * binding,
+        };
+        (hiding, binding, commitments)
+    }
+}
+
+/// Binding factor rho_j and nonce point D_j + rho_j * E_j of every signer. Each nonce is bound to `prefix`,
+/// i.e. the key and the message, and to all commitments, so that none can be reused elsewhere.
+/// `encode` writes a point into the binding input, `rho` hashes the input and the signer's party id.
+pub fn nonce_points<P>(
+    prefix: Vec<u8>,
+    signers_vec: &[usize],
+    commitments_vec: &[NonceCommitments<P>],
+    encode: impl Fn(&P) -> Vec<u8>,
+    rho: impl Fn(&[u8], u16) -> P::Scalar,
+) -> Vec<(P::Scalar, P)>
+where
+    P: ECPoint + Copy + Add<Output = P> + Mul<<P as ECPoint>::Scalar, Output = P>,
+    P::Scalar: Copy,
+{
+    let mut binding_input = prefix;
+    for (j, commitments_j) in commitments_vec.iter().enumerate() {
+        binding_input.extend_from_slice(&(signers_vec[j] as u16 + 1).to_be_bytes());
+        binding_input.extend_from_slice(&encode(&commitments_j.hiding));
+        binding_input.extend_from_slice(&encode(&commitments_j.binding));
+    }
+    commitments_vec.iter().enumerate()
+        .map(|(j, commitments_j)| {
+            let rho_j = rho(&binding_input, signers_vec[j] as u16 + 1);
+            (rho_j, commitments_j.hiding + commitments_j.binding * rho_j)
+        })
+        .collect()
+}
+
+/// x_j * G of party `party_id`, from the VSS commitments of all parties
+pub fn public_share<P>(vss_scheme_vec: &[VerifiableSS<P>], party_id: u16) -> P
+where
+    P: ECPoint + Copy + Add<Output = P>,
+    P::Scalar: Clone,
+{
+    sum_points(vss_scheme_vec.iter().map(|x| x.get_point_commitment(party_id as usize)))
+}
+
+pub fn sum_points<P: Add<Output = P>>(mut points: impl Iterator<Item = P>) -> P {
+    let first = points.next().expect("No point to sum");
+    points.fold(first, |acc, x| acc + x)
+}
@@ -68,3 +68,15 @@ pub fn hd_key(
             });
     (public_key_new_child, f_l_new, cc_new)
 }
+
+pub fn call_hd_key(path: &str, public_key: GE) -> Result<(FE, GE), String> {
+
+    let path_vector: Vec<BigInt> = path
+        .split('/')
+        .map(|s| BigInt::from_str_radix(s.trim(), 10)
+            .map_err(|_| format!("Invalid derivation path {:?}, expected numbers separated by /", path)))
+        .collect::<Result<_, _>>()?;
+    let (public_key_child, f_l_new) = get_hd_key(&public_key, path_vector.clone());
+    Ok((f_l_new, public_key_child.clone()))
+
+}
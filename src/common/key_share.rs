@@ -0,0 +1,214 @@
+use std::fs;
+
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{Keys, SharedKeys};
+use paillier::EncryptionKey;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::common::{frost, hd_keys, Error, Params, Protocol};
+
+// Layout of the keys files written before the protocol was recorded, all of them GG18
+type KeyShareTuple = (Keys, SharedKeys, u16, Vec<VerifiableSS<GE>>, Vec<EncryptionKey>, GE);
+
+/// A party's output of keygen, i.e. the content of a keys file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyShare {
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub party_keys: Keys,
+    pub shared_keys: SharedKeys,
+    pub party_id: u16,
+    pub vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    pub paillier_key_vector: Vec<EncryptionKey>,
+    #[serde(default)]
+    pub dlog_statement_vec: Vec<DLogStatement>, // Per-party h1, h2, N~ for the MtA range proofs
+    pub y_sum: GE,
+}
+
+/// Public part of a keys file, the same in the keys files of all parties of a key.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRecord {
+    pub protocol: Protocol,
+    pub vss_scheme_vec: Vec<VerifiableSS<GE>>,
+    pub paillier_key_vector: Vec<EncryptionKey>,
+    pub dlog_statement_vec: Vec<DLogStatement>,
+    pub y_sum: GE,
+}
+
+impl KeyShare {
+
+    /// Keys file of the party of `party_keys` with share `x_i`, and the public part of `record`.
+    pub fn from_record(record: KeyRecord, party_keys: Keys, x_i: FE) -> Self {
+        KeyShare {
+            protocol: record.protocol,
+            party_id: party_keys.party_index as u16,
+            party_keys,
+            shared_keys: SharedKeys { y: record.y_sum, x_i },
+            vss_scheme_vec: record.vss_scheme_vec,
+            paillier_key_vector: record.paillier_key_vector,
+            dlog_statement_vec: record.dlog_statement_vec,
+            y_sum: record.y_sum,
+        }
+    }
+
+    pub fn record(&self) -> KeyRecord {
+        KeyRecord {
+            protocol: self.protocol,
+            vss_scheme_vec: self.vss_scheme_vec.clone(),
+            paillier_key_vector: self.paillier_key_vector.clone(),
+            dlog_statement_vec: self.dlog_statement_vec.clone(),
+            y_sum: self.y_sum,
+        }
+    }
+
+    pub fn load(keysfile_path: &str) -> Result<Self, Error> {
+        let data = read_keys_file(keysfile_path)?;
+        serde_json::from_str(&data)
+            .or_else(|_| serde_json::from_str::<KeyShareTuple>(&data).map(KeyShare::from))
+            .map_err(|e| Error::File(format!("Invalid keys file {}: {}", keysfile_path, e)))
+    }
+
+    pub fn save(&self, keysfile_path: &str) -> Result<(), Error> {
+        write_keys_file(keysfile_path, self)
+    }
+
+    /// Threshold params the keys were generated with
+    pub fn params(&self) -> Params {
+        vss_params(&self.vss_scheme_vec)
+    }
+
+    /// Checks that the parts of the keys file agree with each other.
+    pub fn validate(&self) -> Result<(), String> {
+        match self.protocol {
+            Protocol::Lindell17 => return Err("Lindell17 keys are loaded as a TwoPartyKeyShare".to_string()),
+            Protocol::FrostEd25519 => return Err("Ed25519 keys are loaded as an EdKeyShare".to_string()),
+            Protocol::Frost | Protocol::Recovery => return Err("Keys file holds no ECDSA protocol".to_string()),
+            Protocol::Gg18 | Protocol::Gg20 => {}
+        }
+        if self.vss_scheme_vec.is_empty() {
+            return Err("Keys file holds no VSS scheme".to_string());
+        }
+        let parameters = &self.vss_scheme_vec[0].parameters;
+        let parties = parameters.share_count;
+        if self.vss_scheme_vec.iter().any(|x| x.parameters != *parameters) {
+            return Err("Keys file holds VSS schemes with different threshold params".to_string());
+        }
+        if self.vss_scheme_vec.len() != parties || self.paillier_key_vector.len() != parties {
+            return Err(format!(
+                "Keys file holds {} VSS schemes and {} Paillier keys for {} parties",
+                self.vss_scheme_vec.len(), self.paillier_key_vector.len(), parties
+            ));
+        }
+        // Keys files written before the range proofs have no statements, GG20 ones always do
+        let has_statements = self.protocol == Protocol::Gg20 || !self.dlog_statement_vec.is_empty();
+        if has_statements && self.dlog_statement_vec.len() != parties {
+            return Err(format!(
+                "Keys file holds {} h1/h2 statements for {} parties",
+                self.dlog_statement_vec.len(), parties
+            ));
+        }
+        if self.party_id == 0 || usize::from(self.party_id) > parties {
+            return Err(format!("Party id {} of the keys file is not between 1 and {}", self.party_id, parties));
+        }
+        if let Some(i) = self.vss_scheme_vec.iter().position(|x| x.commitments.len() != parameters.threshold + 1) {
+            return Err(format!(
+                "VSS scheme of party {} holds {} commitments, expected threshold + 1 = {}",
+                i + 1, self.vss_scheme_vec[i].commitments.len(), parameters.threshold + 1
+            ));
+        }
+        let mut y_sum = self.vss_scheme_vec[0].commitments[0];
+        for vss_scheme in self.vss_scheme_vec.iter().skip(1) {
+            y_sum = y_sum + vss_scheme.commitments[0];
+        }
+        if y_sum != self.y_sum || self.shared_keys.y != self.y_sum {
+            return Err("Public key of the keys file does not match its VSS commitments".to_string());
+        }
+        self.params().values().map(|_| ())
+    }
+
+    /// Checks threshold params given for signing against the ones of the keys file.
+    pub fn check_params(&self, params: &Params) -> Result<(), String> {
+        match_params(params, &self.params())
+    }
+
+    /// Public share x_j * G of party `party_id`, from the VSS commitments.
+    pub fn public_share(&self, party_id: u16) -> GE {
+        frost::public_share(&self.vss_scheme_vec, party_id)
+    }
+
+    /// Returns the derived public key at `path` together with the tweak applied to the shares,
+    /// or the root public key and a zero tweak if `path` is empty.
+    pub fn derive(&self, path: &str) -> Result<(FE, GE), String> {
+        match path.is_empty() {
+            true => Ok((ECScalar::zero(), self.y_sum)),
+            false => hd_keys::call_hd_key(path, self.y_sum)
+        }
+    }
+}
+
+/// Protocol of the keys file at `keysfile_path`, read before choosing how to load its shares.
+pub fn keys_file_protocol(keysfile_path: &str) -> Result<Protocol, Error> {
+    #[derive(Deserialize)]
+    struct Header {
+        #[serde(default)]
+        protocol: Protocol,
+    }
+    let data = read_keys_file(keysfile_path)?;
+    // Files written before the protocol was recorded hold a JSON array
+    Ok(serde_json::from_str::<Header>(&data).map_or(Protocol::Gg18, |header| header.protocol))
+}
+
+pub(crate) fn read_keys_file(keysfile_path: &str) -> Result<String, Error> {
+    fs::read_to_string(keysfile_path)
+        .map_err(|e| Error::File(format!("Unable to load keys file at location {}: {}", keysfile_path, e)))
+}
+
+/// Threshold params of the first VSS scheme of a keys file.
+pub(crate) fn vss_params<P: ECPoint>(vss_scheme_vec: &[VerifiableSS<P>]) -> Params {
+    let parameters = &vss_scheme_vec[0].parameters;
+    Params::new(parameters.threshold as u16, parameters.share_count as u16)
+}
+
+/// Checks threshold params given for signing against the `expected` ones of a keys file.
+pub(crate) fn match_params(given: &Params, expected: &Params) -> Result<(), String> {
+    let given = given.values()?;
+    let expected = expected.values()?;
+    if given != expected {
+        return Err(format!(
+            "Threshold params {}/{} do not match the keys file, generated for {}/{}",
+            given.0, given.1, expected.0, expected.1
+        ));
+    }
+    Ok(())
+}
+
+/// Parses a keys file that has a single layout, the ones of the Ed25519 and two-party keys.
+pub(crate) fn load_keys_file<T: DeserializeOwned>(keysfile_path: &str) -> Result<T, Error> {
+    serde_json::from_str(&read_keys_file(keysfile_path)?)
+        .map_err(|e| Error::File(format!("Invalid keys file {}: {}", keysfile_path, e)))
+}
+
+pub(crate) fn write_keys_file<T: Serialize>(keysfile_path: &str, key_share: &T) -> Result<(), Error> {
+    let keygen_json = serde_json::to_string(key_share).unwrap();
+    fs::write(keysfile_path, keygen_json)
+        .map_err(|e| Error::File(format!("Unable to save keys file at location {}: {}", keysfile_path, e)))
+}
+
+impl From<KeyShareTuple> for KeyShare {
+    fn from(tuple: KeyShareTuple) -> Self {
+        let (party_keys, shared_keys, party_id, vss_scheme_vec, paillier_key_vector, y_sum) = tuple;
+        KeyShare {
+            protocol: Protocol::Gg18,
+            party_keys,
+            shared_keys,
+            party_id,
+            vss_scheme_vec,
+            paillier_key_vector,
+            dlog_statement_vec: vec![],
+            y_sum,
+        }
+    }
+}
@@ -1,11 +1,11 @@
-use std::{fs, time};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
 
 use curv::{
-    arithmetic::traits::Converter,
+    arithmetic::traits::BitManipulation,
     cryptographic_primitives::{
         proofs::sigma_dlog::DLogProof, secret_sharing::feldman_vss::VerifiableSS,
     },
-    elliptic::curves::traits::{ECPoint, ECScalar},
     BigInt,
     elliptic::curves::secp256_k1::{FE, GE}
 };
@@ -13,99 +13,154 @@ use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{
     KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, Parameters,
 };
 use paillier::EncryptionKey;
-use reqwest::blocking::Client;
+use tracing::{debug, field, info, instrument, Span};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::generate_h1_h2_N_tilde;
+use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement, NICorrectKeyProof, SALT_STRING};
 
 use crate::common::{
-    aes_decrypt, aes_encrypt, broadcast, poll_for_broadcasts, poll_for_p2p, postb, sendp2p, Params,
-    PartySignup, AEAD,
+    block_on, broadcast, keygen_gg20, manager_answer, poll_for_broadcasts, poll_for_p2p, postb, sendp2p, ClientConfig,
+    Error, KeyShare, KeygenSignupRequestBody, ManagerError, Params, PartySignup, Protocol, SigningPartySignup, Transport,
 };
+use crate::common::error::decode;
+use crate::common::p2p::P2pChannel;
+use crate::common::paillier_keys::{blum_paillier_keys, BlumModulusProof};
 
-pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
-    let THRESHOLD: u16 = params[0].parse::<u16>().unwrap();
-    let PARTIES: u16 = params[1].parse::<u16>().unwrap();
+// Keygen creates 2048-bit Paillier and N~ moduli, a shorter one is refused
+const MIN_PAILLIER_KEY_BITS: usize = 2046;
 
-    let client = Client::new();
-
-    // delay:
-    let delay = time::Duration::from_millis(25);
-    let params = Parameters {
-        threshold: THRESHOLD,
-        share_count: PARTIES,
-    };
+pub fn run_keygen(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    THRESHOLD: u16,
+    PARTIES: u16,
+    protocol: Protocol,
+) -> Result<KeyShare, Error> {
+    block_on(run_keygen_async(transport, config, THRESHOLD, PARTIES, protocol))
+}
 
+#[instrument(skip(transport, config), fields(party = field::Empty, uuid = field::Empty))]
+pub async fn run_keygen_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    THRESHOLD: u16,
+    PARTIES: u16,
+    protocol: Protocol,
+) -> Result<KeyShare, Error> {
     //signup:
-    let tn_params = Params {
-        threshold: THRESHOLD.to_string(),
-        parties: PARTIES.to_string(),
+    let tn_params = Params::new(THRESHOLD, PARTIES);
+    tn_params.values().map_err(Error::InvalidInput)?;
+    let unsupported = match protocol {
+        Protocol::Lindell17 => Some("Lindell17 keys are created by two_party::run_keygen"),
+        Protocol::Frost => Some("Schnorr signatures use the shares of gg18 or gg20 keys"),
+        Protocol::FrostEd25519 => Some("Ed25519 keys are created by eddsa::run_keygen"),
+        Protocol::Recovery => Some("Recovery sessions restore a share of an existing key"),
+        Protocol::Gg18 | Protocol::Gg20 => None,
     };
-    let (party_num_int, uuid) = match keygen_signup(&addr, &client, &tn_params).unwrap() {
+    if let Some(error) = unsupported {
+        return Err(Error::InvalidInput(error.to_string()));
+    }
+    let signup = keygen_signup(transport, config, THRESHOLD, PARTIES, protocol).await;
+    let (party_num_int, uuid) = match signup? {
         PartySignup { number, uuid } => (number, uuid),
     };
-    println!("number: {:?}, uuid: {:?}", party_num_int, uuid);
+    Span::current().record("party", &party_num_int);
+    Span::current().record("uuid", &uuid.as_str());
+    info!("Signed up for keygen");
 
-    let party_keys = Keys::create(party_num_int as usize);
+    match protocol {
+        Protocol::Gg18 => keygen_gg18(transport, config, THRESHOLD, PARTIES, party_num_int, uuid).await,
+        Protocol::Gg20 => keygen_gg20::keygen_gg20(transport, config, THRESHOLD, PARTIES, party_num_int, uuid).await,
+        Protocol::Lindell17 | Protocol::Frost | Protocol::FrostEd25519 | Protocol::Recovery => unreachable!(),
+    }
+}
+
+async fn keygen_gg18(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    THRESHOLD: u16,
+    PARTIES: u16,
+    party_num_int: u16,
+    uuid: String,
+) -> Result<KeyShare, Error> {
+    let params = Parameters {
+        threshold: THRESHOLD,
+        share_count: PARTIES,
+    };
+
+    let mut party_keys = Keys::create(party_num_int as usize);
+    let (ek, dk) = blum_paillier_keys();
+    party_keys.ek = ek;
+    party_keys.dk = dk;
+    let modulus_proof_i = BlumModulusProof::prove(&party_keys.dk);
     let (bc_i, decom_i) = party_keys.phase1_broadcast_phase3_proof_of_correct_key();
+    let (dlog_statement_i, composite_dlog_proof_i) = create_dlog_statement();
 
-    // send commitment to ephemeral public keys, get round 1 commitments of other parties
-    assert!(broadcast(
-        &addr,
-        &client,
+    // send commitment to ephemeral public keys, Paillier modulus proof and h1, h2, N~, get round 1 messages of other parties
+    broadcast(
+        transport,
         party_num_int,
         "round1",
-        serde_json::to_string(&bc_i).unwrap(),
+        serde_json::to_string(&(bc_i.clone(), dlog_statement_i.clone(), composite_dlog_proof_i.clone(), modulus_proof_i.clone())).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round1_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         PARTIES,
-        delay,
+        config,
         "round1",
         uuid.clone(),
-    );
+    )
+    .await?;
 
-    let mut bc1_vec = round1_ans_vec
+    let mut round1_vec = round1_ans_vec
         .iter()
-        .map(|m| serde_json::from_str::<KeyGenBroadcastMessage1>(m).unwrap())
-        .collect::<Vec<_>>();
+        .map(|m| decode::<(KeyGenBroadcastMessage1, DLogStatement, CompositeDLogProof, BlumModulusProof)>("round1", m))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    bc1_vec.insert(party_num_int as usize - 1, bc_i);
+    round1_vec.insert(party_num_int as usize - 1, (bc_i, dlog_statement_i, composite_dlog_proof_i, modulus_proof_i));
+    verify_paillier_keys(&round1_vec.iter().map(|x| (&x.0.e, &x.0.correct_key_proof)).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+    verify_paillier_moduli(&round1_vec.iter().map(|x| (&x.0.e, &x.3)).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+    verify_dlog_statements(&round1_vec.iter().map(|x| (&x.1, &x.2)).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+    let bc1_vec = round1_vec.iter().map(|x| x.0.clone()).collect::<Vec<KeyGenBroadcastMessage1>>();
+    let dlog_statement_vec = round1_vec.into_iter().map(|x| x.1).collect::<Vec<DLogStatement>>();
 
     // send ephemeral public keys and check commitments correctness
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int,
         "round2",
         serde_json::to_string(&decom_i).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round2_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         PARTIES,
-        delay,
+        config,
         "round2",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut j = 0;
     let mut point_vec: Vec<GE> = Vec::new();
     let mut decom_vec: Vec<KeyGenDecommitMessage1> = Vec::new();
-    let mut enc_keys: Vec<BigInt> = Vec::new();
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
     for i in 1..=PARTIES {
         if i == party_num_int {
             point_vec.push(decom_i.y_i);
             decom_vec.push(decom_i.clone());
         } else {
-            let decom_j: KeyGenDecommitMessage1 = serde_json::from_str(&round2_ans_vec[j]).unwrap();
+            let decom_j: KeyGenDecommitMessage1 = decode("round2", &round2_ans_vec[j])?;
             point_vec.push(decom_j.y_i);
             decom_vec.push(decom_j.clone());
-            enc_keys.push((decom_j.y_i.clone() * party_keys.u_i).x_coor().unwrap());
+            shared_secrets.insert(i, vec![decom_j.y_i * party_keys.u_i]);
             j = j + 1;
         }
     }
@@ -117,40 +172,36 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
         .phase1_verify_com_phase3_verify_correct_key_phase2_distribute(
             &params, &decom_vec, &bc1_vec,
         )
-        .expect("invalid key");
+        .map_err(|e| Error::Protocol(format!("invalid commitment or Paillier key: {:?}", e)))?;
 
     //////////////////////////////////////////////////////////////////////////////
 
-    let mut j = 0;
+    // Shares are sealed with keys from y_i, whose commitments every party checked above
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
     for (k, i) in (1..=PARTIES).enumerate() {
         if i != party_num_int {
-            // prepare encrypted ss for party i:
-            let key_i = BigInt::to_bytes(&enc_keys[j]);
-            let plaintext = BigInt::to_bytes(&secret_shares[k].to_big_int());
-            let aead_pack_i = aes_encrypt(&key_i, &plaintext);
-            assert!(sendp2p(
-                &addr,
-                &client,
+            sendp2p(
+                transport,
                 party_num_int,
                 i,
                 "round3",
-                serde_json::to_string(&aead_pack_i).unwrap(),
+                channel.seal(i, "round3", &serde_json::to_string(&secret_shares[k]).unwrap()),
                 uuid.clone(),
             )
-            .is_ok());
-            j += 1;
+            .await?;
         }
     }
 
     let round3_ans_vec = poll_for_p2p(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         PARTIES,
-        delay,
+        config,
         "round3",
         uuid.clone(),
-    );
+    )
+    .await?;
+    let round3_ans_vec = channel.open_all("round3", &round3_ans_vec)?;
 
     let mut j = 0;
     let mut party_shares: Vec<FE> = Vec::new();
@@ -158,36 +209,31 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
         if i == party_num_int {
             party_shares.push(secret_shares[(i - 1) as usize]);
         } else {
-            let aead_pack: AEAD = serde_json::from_str(&round3_ans_vec[j]).unwrap();
-            let key_i = BigInt::to_bytes(&enc_keys[j]);
-            let out = aes_decrypt(&key_i, aead_pack);
-            let out_bn = BigInt::from_bytes(&out);
-            let out_fe = ECScalar::from(&out_bn);
-            party_shares.push(out_fe);
+            let share_j: FE = decode("round3", &round3_ans_vec[j])?;
+            party_shares.push(share_j);
 
             j += 1;
         }
     }
 
     // round 4: send vss commitments
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int,
         "round4",
         serde_json::to_string(&vss_scheme).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round4_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         PARTIES,
-        delay,
+        config,
         "round4",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut j = 0;
     let mut vss_scheme_vec: Vec<VerifiableSS<GE>> = Vec::new();
@@ -195,7 +241,7 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
         if i == party_num_int {
             vss_scheme_vec.push(vss_scheme.clone());
         } else {
-            let vss_scheme_j: VerifiableSS<GE> = serde_json::from_str(&round4_ans_vec[j]).unwrap();
+            let vss_scheme_j: VerifiableSS<GE> = decode("round4", &round4_ans_vec[j])?;
             vss_scheme_vec.push(vss_scheme_j);
             j += 1;
         }
@@ -209,27 +255,26 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
             &vss_scheme_vec,
             party_num_int as usize,
         )
-        .expect("invalid vss");
+        .map_err(|e| Error::Protocol(format!("invalid secret share: {:?}", e)))?;
 
     // round 5: send dlog proof
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int,
         "round5",
         serde_json::to_string(&dlog_proof).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round5_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         PARTIES,
-        delay,
+        config,
         "round5",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut j = 0;
     let mut dlog_proof_vec: Vec<DLogProof<GE>> = Vec::new();
@@ -237,32 +282,164 @@ pub fn run_keygen(addr: &String, keysfile_path: &String, params: &Vec<&str>) {
         if i == party_num_int {
             dlog_proof_vec.push(dlog_proof.clone());
         } else {
-            let dlog_proof_j: DLogProof<GE> = serde_json::from_str(&round5_ans_vec[j]).unwrap();
+            let dlog_proof_j: DLogProof<GE> = decode("round5", &round5_ans_vec[j])?;
             dlog_proof_vec.push(dlog_proof_j);
             j += 1;
         }
     }
-    Keys::verify_dlog_proofs(&params, &dlog_proof_vec, &point_vec).expect("bad dlog proof");
+    Keys::verify_dlog_proofs(&params, &dlog_proof_vec, &point_vec)
+        .map_err(|e| Error::Protocol(format!("invalid dlog proof: {:?}", e)))?;
 
-    //save key to file:
     let paillier_key_vec = (0..PARTIES)
         .map(|i| bc1_vec[i as usize].e.clone())
         .collect::<Vec<EncryptionKey>>();
 
-    let keygen_json = serde_json::to_string(&(
+    Ok(KeyShare {
+        protocol: Protocol::Gg18,
         party_keys,
         shared_keys,
-        party_num_int,
+        party_id: party_num_int,
         vss_scheme_vec,
-        paillier_key_vec,
+        paillier_key_vector: paillier_key_vec,
+        dlog_statement_vec,
         y_sum,
-    ))
-    .unwrap();
-    println!("Keys data written to file: {:?}", keysfile_path);
-    fs::write(&keysfile_path, keygen_json).expect("Unable to save !");
+    })
+}
+
+/// Checks the Paillier key of every party, in party order, against its proof of correctness
+/// (the modulus is coprime with its totient), before any secret share is encrypted to it.
+pub fn verify_paillier_keys(keys: &[(&EncryptionKey, &NICorrectKeyProof)]) -> Result<(), String> {
+    for (i, (ek, proof)) in keys.iter().enumerate() {
+        check_paillier_key(ek, proof).map_err(|e| format!("Party {} sent {}", i + 1, e))?;
+    }
+    Ok(())
+}
+
+/// Checks the size of one Paillier key and its correctness proof, the error tells what the party sent.
+pub(crate) fn check_paillier_key(ek: &EncryptionKey, proof: &NICorrectKeyProof) -> Result<(), String> {
+    let bits = ek.n.bit_length();
+    if bits < MIN_PAILLIER_KEY_BITS {
+        return Err(format!("a Paillier key of {} bits, expected 2048", bits));
+    }
+    if proof.verify(ek, SALT_STRING).is_err() {
+        return Err("a Paillier key that fails its correctness proof".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that the Paillier modulus of every party, in party order, is the product of two primes that are
+/// 3 mod 4, which the correctness proof alone does not rule out.
+pub fn verify_paillier_moduli(moduli: &[(&EncryptionKey, &BlumModulusProof)]) -> Result<(), String> {
+    for (i, (ek, proof)) in moduli.iter().enumerate() {
+        check_paillier_modulus(ek, proof).map_err(|e| format!("Party {} sent {}", i + 1, e))?;
+    }
+    Ok(())
+}
+
+/// Checks the modulus proof of one Paillier key, the error tells what the party sent.
+pub(crate) fn check_paillier_modulus(ek: &EncryptionKey, proof: &BlumModulusProof) -> Result<(), String> {
+    proof.verify(ek).map_err(|e| format!("a Paillier key that fails its modulus proof: {}", e))
+}
+
+/// Creates the party's h1, h2, N~ for the MtA range proofs, with a proof that h2 = h1^x mod N~.
+pub fn create_dlog_statement() -> (DLogStatement, CompositeDLogProof) {
+    let (N_tilde, h1, h2, xhi) = generate_h1_h2_N_tilde();
+    let dlog_statement = DLogStatement {
+        N: N_tilde,
+        g: h1,
+        ni: h2,
+    };
+    let composite_dlog_proof = CompositeDLogProof::prove(&dlog_statement, &xhi);
+    (dlog_statement, composite_dlog_proof)
+}
+
+/// Checks the h1, h2, N~ of every party, in party order, against their proof.
+pub fn verify_dlog_statements(statements: &[(&DLogStatement, &CompositeDLogProof)]) -> Result<(), String> {
+    for (i, (statement, proof)) in statements.iter().enumerate() {
+        check_dlog_statement(statement, proof).map_err(|e| format!("Party {} sent {}", i + 1, e))?;
+    }
+    Ok(())
 }
 
-pub fn keygen_signup(addr: &String, client: &Client, params: &Params) -> Result<PartySignup, ()> {
-    let res_body = postb(&addr, &client, "signupkeygen", params).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+/// Checks one party's h1, h2, N~ against its proof, the error tells what the party sent.
+pub(crate) fn check_dlog_statement(statement: &DLogStatement, proof: &CompositeDLogProof) -> Result<(), String> {
+    let one = BigInt::from(1);
+    let bits = statement.N.bit_length();
+    if bits < MIN_PAILLIER_KEY_BITS {
+        return Err(format!("an N~ of {} bits, expected 2048", bits));
+    }
+    if statement.g == statement.ni || statement.g == one || statement.ni == one {
+        return Err("degenerate h1, h2".to_string());
+    }
+    if proof.verify(statement).is_err() {
+        return Err("h1, h2 that fail their proof".to_string());
+    }
+    Ok(())
+}
+
+/// Joins the keygen room, then pings it until all parties are there. The slot is given back if
+/// they do not all show up within `signup_timeout` seconds of the last join.
+pub async fn keygen_signup(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    threshold: u16,
+    parties: u16,
+    protocol: Protocol,
+) -> Result<PartySignup, Error> {
+    let mut request_body = KeygenSignupRequestBody {
+        threshold,
+        parties,
+        protocol,
+        party_number: 0,
+        party_uuid: "".to_string(),
+    };
+    let path = "signupkeygen";
+    let delay = Duration::from_millis(100);
+    let timeout = config.signup_timeout;
+
+    // The room may be full or still held by a starting keygen, retry until a slot frees up
+    let mut start_time = Instant::now();
+    let SigningPartySignup { party_order, party_uuid, mut room_uuid, total_joined } = loop {
+        let res_body = postb(transport, path, request_body.clone()).await?;
+        let answer: Result<SigningPartySignup, ManagerError> = manager_answer(path, &res_body)?;
+        match answer {
+            Ok(signup) => break signup,
+            Err(ManagerError{error}) => {
+                if start_time.elapsed().as_secs() > timeout {
+                    return Err(Error::Manager(error));
+                }
+                debug!(error = error.as_str(), "Keygen signup refused, retrying");
+            }
+        }
+        tokio::time::sleep(delay).await;
+    };
+    info!(party = party_order, total_joined, "Signed up, waiting for all parties");
+
+    request_body.party_number = party_order;
+    request_body.party_uuid = party_uuid;
+    start_time = Instant::now();
+    let mut last_total_joined = total_joined;
+    while room_uuid.is_empty() {
+        if start_time.elapsed().as_secs() > timeout {
+            let _ = postb(transport, "cancelkeygen", request_body.clone()).await;
+            return Err(Error::Timeout(format!(
+                "Only {} of {} parties joined the keygen within {} seconds, signup cancelled",
+                last_total_joined, parties, timeout
+            )));
+        }
+        tokio::time::sleep(delay).await;
+        let res_body = postb(transport, path, request_body.clone()).await?;
+        let answer: Result<SigningPartySignup, ManagerError> = manager_answer(path, &res_body)?;
+        let answer = answer?;
+        if answer.total_joined != last_total_joined {
+            info!(total_joined = answer.total_joined, "Joined so far");
+            last_total_joined = answer.total_joined;
+            start_time = Instant::now();
+        }
+        room_uuid = answer.room_uuid;
+    }
+    Ok(PartySignup {
+        number: party_order,
+        uuid: room_uuid,
+    })
 }
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use curv::{
+    cryptographic_primitives::{
+        proofs::sigma_dlog::DLogProof, secret_sharing::feldman_vss::VerifiableSS,
+    },
+    elliptic::curves::secp256_k1::{FE, GE}
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i as gg_2018;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::{
+    KeyGenBroadcastMessage1, KeyGenDecommitMessage1, Keys, Parameters,
+};
+use paillier::EncryptionKey;
+use zk_paillier::zkproofs::DLogStatement;
+
+use crate::common::error::decode;
+use crate::common::keygen::{verify_dlog_statements, verify_paillier_keys, verify_paillier_moduli};
+use crate::common::paillier_keys::{blum_paillier_keys, BlumModulusProof};
+use crate::common::p2p::P2pChannel;
+use crate::common::{
+    broadcast, poll_for_broadcasts, poll_for_p2p, sendp2p, ClientConfig, Error, KeyShare, Protocol, Transport,
+};
+
+/// GG20 keygen rounds, the GG18 steps with the library's identifiable checks. A failed check
+/// aborts with the index (0-based) of the party that sent the bad message.
+pub async fn keygen_gg20(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    THRESHOLD: u16,
+    PARTIES: u16,
+    party_num_int: u16,
+    uuid: String,
+) -> Result<KeyShare, Error> {
+    let params = Parameters {
+        threshold: THRESHOLD,
+        share_count: PARTIES,
+    };
+
+    let mut party_keys = Keys::create(party_num_int as usize);
+    let (ek, dk) = blum_paillier_keys();
+    party_keys.ek = ek;
+    party_keys.dk = dk;
+    let modulus_proof_i = BlumModulusProof::prove(&party_keys.dk);
+    let (bc_i, decom_i) = party_keys.phase1_broadcast_phase3_proof_of_correct_key_proof_of_correct_h1h2();
+
+    // send commitment to ephemeral public keys and Paillier modulus proof, get round 1 messages of other parties
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&(bc_i.clone(), modulus_proof_i.clone())).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut round1_vec = round1_ans_vec
+        .iter()
+        .map(|m| decode::<(KeyGenBroadcastMessage1, BlumModulusProof)>("round1", m))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    round1_vec.insert(party_num_int as usize - 1, (bc_i, modulus_proof_i));
+    let (bc1_vec, modulus_proof_vec): (Vec<KeyGenBroadcastMessage1>, Vec<BlumModulusProof>) = round1_vec.into_iter().unzip();
+    verify_paillier_keys(&bc1_vec.iter().map(|bc1| (&bc1.e, &bc1.correct_key_proof)).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+    verify_paillier_moduli(&bc1_vec.iter().map(|bc1| &bc1.e).zip(modulus_proof_vec.iter()).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+    verify_dlog_statements(&bc1_vec.iter().map(|bc1| (&bc1.dlog_statement, &bc1.composite_dlog_proof)).collect::<Vec<_>>())
+        .map_err(Error::Protocol)?;
+
+    // send ephemeral public keys and check commitments correctness
+    broadcast(
+        transport,
+        party_num_int,
+        "round2",
+        serde_json::to_string(&decom_i).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round2_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round2",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut j = 0;
+    let mut point_vec: Vec<GE> = Vec::new();
+    let mut decom_vec: Vec<KeyGenDecommitMessage1> = Vec::new();
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    for i in 1..=PARTIES {
+        if i == party_num_int {
+            point_vec.push(decom_i.y_i);
+            decom_vec.push(decom_i.clone());
+        } else {
+            let decom_j: KeyGenDecommitMessage1 = decode("round2", &round2_ans_vec[j])?;
+            point_vec.push(decom_j.y_i);
+            decom_vec.push(decom_j.clone());
+            shared_secrets.insert(i, vec![decom_j.y_i * party_keys.u_i]);
+            j += 1;
+        }
+    }
+
+    let (head, tail) = point_vec.split_at(1);
+    let y_sum = tail.iter().fold(head[0], |acc, x| acc + x);
+
+    let (vss_scheme, secret_shares, _index) = party_keys
+        .phase1_verify_com_phase3_verify_correct_key_verify_dlog_phase2_distribute(
+            &params, &decom_vec, &bc1_vec,
+        )
+        .map_err(|e| Error::Protocol(format!("invalid commitment or key proof: {:?}", e)))?;
+
+    //////////////////////////////////////////////////////////////////////////////
+
+    // Shares are sealed with keys from y_i, whose commitments every party checked above
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+    for (k, i) in (1..=PARTIES).enumerate() {
+        if i != party_num_int {
+            sendp2p(
+                transport,
+                party_num_int,
+                i,
+                "round3",
+                channel.seal(i, "round3", &serde_json::to_string(&secret_shares[k]).unwrap()),
+                uuid.clone(),
+            )
+            .await?;
+        }
+    }
+
+    let round3_ans_vec = poll_for_p2p(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round3",
+        uuid.clone(),
+    )
+    .await?;
+    let round3_ans_vec = channel.open_all("round3", &round3_ans_vec)?;
+
+    let mut j = 0;
+    let mut party_shares: Vec<FE> = Vec::new();
+    for i in 1..=PARTIES {
+        if i == party_num_int {
+            party_shares.push(secret_shares[(i - 1) as usize]);
+        } else {
+            let share_j: FE = decode("round3", &round3_ans_vec[j])?;
+            party_shares.push(share_j);
+
+            j += 1;
+        }
+    }
+
+    // round 4: send vss commitments
+    broadcast(
+        transport,
+        party_num_int,
+        "round4",
+        serde_json::to_string(&vss_scheme).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round4_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round4",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut j = 0;
+    let mut vss_scheme_vec: Vec<VerifiableSS<GE>> = Vec::new();
+    for i in 1..=PARTIES {
+        if i == party_num_int {
+            vss_scheme_vec.push(vss_scheme.clone());
+        } else {
+            let vss_scheme_j: VerifiableSS<GE> = decode("round4", &round4_ans_vec[j])?;
+            vss_scheme_vec.push(vss_scheme_j);
+            j += 1;
+        }
+    }
+
+    let (shared_keys, dlog_proof) = party_keys
+        .phase2_verify_vss_construct_keypair_phase3_pok_dlog(
+            &params,
+            &point_vec,
+            &party_shares,
+            &vss_scheme_vec,
+            party_num_int as usize,
+        )
+        .map_err(|e| Error::Protocol(format!("invalid secret share: {:?}", e)))?;
+
+    // round 5: send dlog proof
+    broadcast(
+        transport,
+        party_num_int,
+        "round5",
+        serde_json::to_string(&dlog_proof).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round5_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        PARTIES,
+        config,
+        "round5",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut j = 0;
+    let mut dlog_proof_vec: Vec<DLogProof<GE>> = Vec::new();
+    for i in 1..=PARTIES {
+        if i == party_num_int {
+            dlog_proof_vec.push(dlog_proof.clone());
+        } else {
+            let dlog_proof_j: DLogProof<GE> = decode("round5", &round5_ans_vec[j])?;
+            dlog_proof_vec.push(dlog_proof_j);
+            j += 1;
+        }
+    }
+    Keys::verify_dlog_proofs_check_against_vss(&params, &dlog_proof_vec, &point_vec, &vss_scheme_vec)
+        .map_err(|e| Error::Protocol(format!("invalid dlog proof: {:?}", e)))?;
+
+    let paillier_key_vec = bc1_vec.iter()
+        .map(|bc1| bc1.e.clone())
+        .collect::<Vec<EncryptionKey>>();
+    let dlog_statement_vec = bc1_vec.iter()
+        .map(|bc1| bc1.dlog_statement.clone())
+        .collect::<Vec<DLogStatement>>();
+
+    // Signing only needs the Paillier keys and the shares, both shaped like GG18's
+    Ok(KeyShare {
+        protocol: Protocol::Gg20,
+        party_keys: gg_2018::Keys {
+            u_i: party_keys.u_i,
+            y_i: party_keys.y_i,
+            dk: party_keys.dk,
+            ek: party_keys.ek,
+            party_index: party_keys.party_index,
+        },
+        shared_keys: gg_2018::SharedKeys {
+            y: shared_keys.y,
+            x_i: shared_keys.x_i,
+        },
+        party_id: party_num_int,
+        vss_scheme_vec,
+        paillier_key_vector: paillier_key_vec,
+        dlog_statement_vec,
+        y_sum,
+    })
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use crate::common::{Protocol, SigningPartyInfo, SigningPartySignup};
+
+/// Parties gathering for a keygen. Party numbers are handed out on signup and kept by replacements,
+/// the rounds start once all `room_size` parties are present and pinging.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeygenRoom {
+    pub room_uuid: String, // ID set by manager, used during the rounds
+    pub threshold: u16,
+    pub room_size: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub member_info: HashMap<u16, SigningPartyInfo>,
+    pub last_stage: String,
+    pub signup_timeout: u64, // Seconds without a ping before a member is considered inactive
+    #[serde(default)]
+    pub started: Vec<u16>, // Members that received the room uuid once signup was over
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+impl KeygenRoom {
+
+    pub fn new(threshold: u16, size: u16, protocol: Protocol, signup_timeout: u64) -> Self {
+        KeygenRoom {
+            room_uuid: Uuid::new_v4().to_string(),
+            threshold,
+            room_size: size,
+            protocol,
+            member_info: Default::default(),
+            last_stage: "signup".to_string(),
+            signup_timeout,
+            started: vec![],
+        }
+    }
+
+    fn is_timeout(&self, party: &SigningPartyInfo) -> bool {
+        party.last_ping < now() - self.signup_timeout
+    }
+
+    pub fn has_member(&self, party_number: u16, party_uuid: &str) -> bool {
+        self.member_info.get(&party_number).map_or(false, |x| x.party_id == party_uuid)
+    }
+
+    pub fn are_all_members_inactive(&self) -> bool {
+        self.member_info.values().all(|x| self.is_timeout(x))
+    }
+
+    /// A new keygen may take over the room once nobody waits in it anymore: either all members are
+    /// gone during signup, or all of them have learnt the room uuid (or timed out before).
+    pub fn is_replaceable(&self) -> bool {
+        match self.last_stage.as_str() {
+            "signup" => self.are_all_members_inactive(),
+            _ => self.member_info.iter().all(|(key, x)| self.started.contains(key) || self.is_timeout(x)),
+        }
+    }
+
+    /// Gives the new party the first number that is free or held by a timed-out party.
+    pub fn add_party(&mut self) -> Option<SigningPartySignup> {
+        let party_number = (1..=self.room_size).find(|x| match self.member_info.get(x) {
+            Some(member) => self.is_timeout(member),
+            None => true,
+        })?;
+        self.member_info.insert(party_number, SigningPartyInfo {
+            party_id: Uuid::new_v4().to_string(),
+            party_order: party_number,
+            last_ping: now(),
+        });
+        Some(self.get_signup_info(party_number))
+    }
+
+    pub fn remove_party(&mut self, party_number: u16) {
+        self.member_info.remove(&party_number);
+    }
+
+    pub fn update_ping(&mut self, party_number: u16) -> SigningPartySignup {
+        self.member_info.get_mut(&party_number).unwrap().last_ping = now();
+        let active = self.member_info.values().filter(|x| !self.is_timeout(x)).count();
+        if self.last_stage == "signup" && active >= usize::from(self.room_size) {
+            self.last_stage = "terminated".to_string();
+        }
+        if self.last_stage != "signup" && !self.started.contains(&party_number) {
+            self.started.push(party_number);
+        }
+        self.get_signup_info(party_number)
+    }
+
+    pub fn get_signup_info(&self, party_number: u16) -> SigningPartySignup {
+        let member_info = self.member_info.get(&party_number).unwrap();
+        let room_uuid = match self.last_stage.as_str() {
+            "signup" => "".to_string(),
+            _ => self.room_uuid.clone(),
+        };
+        SigningPartySignup {
+            party_order: member_info.party_order,
+            party_uuid: member_info.party_id.clone(),
+            room_uuid,
+            total_joined: u16::try_from(
+                self.member_info.values().filter(|x| !self.is_timeout(x)).count()
+            ).unwrap(),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::common::{split_round_key, ManagerConfig, ManagerError, MAX_KEY_LENGTH};
+
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// Counts requests per key over fixed one-second windows. A limit of 0 disables it.
+struct RateLimiter {
+    limit: u32,
+    counters: Mutex<HashMap<String, (Instant, u32)>>, // Key => (window start, requests in window)
+}
+
+impl RateLimiter {
+
+    fn new(limit: u32) -> Self {
+        RateLimiter { limit, counters: Mutex::new(HashMap::new()) }
+    }
+
+    fn allow(&self, key: &str) -> bool {
+        if self.limit == 0 {
+            return true;
+        }
+        let mut counters = self.counters.lock().unwrap();
+        if counters.len() > 10_000 {
+            counters.retain(|_key, (start, _count)| start.elapsed() < RATE_WINDOW);
+        }
+        let (start, count) = counters.entry(key.to_string()).or_insert((Instant::now(), 0));
+        if start.elapsed() >= RATE_WINDOW {
+            *start = Instant::now();
+            *count = 0;
+        }
+        *count += 1;
+        *count <= self.limit
+    }
+}
+
+/// Manager-side protections against clients flooding it with requests or data.
+pub struct Limits {
+    config: ManagerConfig,
+    clients: RateLimiter,
+    rooms: RateLimiter,
+    session_entries: Mutex<HashMap<String, (Instant, u32)>>, // Session uuid => (last write, entries written)
+}
+
+impl Limits {
+
+    pub fn new(config: &ManagerConfig) -> Self {
+        Limits {
+            config: config.clone(),
+            clients: RateLimiter::new(config.client_rate_limit),
+            rooms: RateLimiter::new(config.room_rate_limit),
+            session_entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Applies the per-client and per-room request rates. `room` is the signing room id, the session
+    /// uuid of a round key, or `keygen` for keygen signups.
+    pub fn check_rate(&self, client: &str, room: &str) -> Result<(), ManagerError> {
+        if !self.clients.allow(client) {
+            return Err(ManagerError{
+                error: format!("Too many requests from {}, limit is {} per second", client, self.config.client_rate_limit)
+            });
+        }
+        if !self.rooms.allow(room) {
+            return Err(ManagerError{
+                error: format!("Too many requests for room {}, limit is {} per second", room, self.config.room_rate_limit)
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks an entry before it is stored: the key must end with a session uuid, the value must fit
+    /// `max_value_size`, and its session must stay under `max_session_entries` and `max_rooms`.
+    pub fn check_entry(&self, key: &str, value: &str, is_new: bool) -> Result<(), ManagerError> {
+        let (_round, uuid) = split_round_key(key).ok_or(ManagerError{
+            error: format!("Invalid key, expected at most {} characters ending with a session uuid", MAX_KEY_LENGTH)
+        })?;
+        if value.len() > self.config.max_value_size {
+            return Err(ManagerError{
+                error: format!("Value of {} bytes exceeds the limit of {} bytes", value.len(), self.config.max_value_size)
+            });
+        }
+        if !is_new {
+            return Ok(());
+        }
+
+        let ttl = Duration::from_secs(self.config.ttl);
+        let mut sessions = self.session_entries.lock().unwrap();
+        if !sessions.contains_key(uuid) {
+            // Sessions idle for longer than the TTL have lost their data
+            sessions.retain(|_uuid, (last_write, _count)| last_write.elapsed() < ttl);
+            if sessions.len() >= self.config.max_rooms {
+                return Err(ManagerError{
+                    error: format!("Too many concurrent sessions, limit is {}", self.config.max_rooms)
+                });
+            }
+        }
+        let (last_write, count) = sessions.entry(uuid.to_string()).or_insert((Instant::now(), 0));
+        if *count >= self.config.max_session_entries {
+            return Err(ManagerError{
+                error: format!("Session {} reached the limit of {} entries", uuid, self.config.max_session_entries)
+            });
+        }
+        *last_write = Instant::now();
+        *count += 1;
+        Ok(())
+    }
+
+    /// Checks that a new signing room can be opened while `open_rooms` are held.
+    pub fn check_new_room(&self, open_rooms: usize) -> Result<(), ManagerError> {
+        match open_rooms >= self.config.max_rooms {
+            true => Err(ManagerError{
+                error: format!("Too many concurrent signing rooms, limit is {}", self.config.max_rooms)
+            }),
+            false => Ok(()),
+        }
+    }
+}
@@ -1,41 +1,59 @@
+use std::net::IpAddr;
 use std::sync::RwLock;
 use std::time::{Duration};
 
-use rocket::{Ignite, post, Rocket, routes, State};
+use rocket::{catch, catchers, Build, Ignite, post, Request, Rocket, routes, State};
 use rocket::serde::json::Json;
-use serde_json::{json};
+use tracing::{debug, info};
 
 use ttlhashmap::TtlHashMap;
 
-use uuid::Uuid;
-
-use crate::common::{Entry, Index, Key, ManagerError, Params, PartySignup, PartySignupRequestBody, SigningPartySignup};
+use crate::common::{Entry, Index, Key, KeygenSignupRequestBody, ManagerConfig, ManagerError, Params, PartySignupRequestBody, SigningPartySignup, split_round_key};
+use crate::common::admin::{self, KEYGEN_SIGNUP_KEY, SIGN_SIGNUP_KEY_PREFIX};
+use crate::common::auth::{KeygenAuth, PartyAuth, SignAuth};
+use crate::common::limits::Limits;
+use crate::common::metrics::{self, Metrics};
+use crate::common::keygen_room::KeygenRoom;
 use crate::common::signing_room::SigningRoom;
 
 #[rocket::main]
-pub async fn run_manager() -> Result<(), rocket::Error> {
+pub async fn run_manager(config: ManagerConfig) -> Result<(), rocket::Error> {
+    build_manager(config).launch().await
+}
+
+/// The routes and state of the manager, tests serve it in memory.
+pub fn build_manager(config: ManagerConfig) -> Rocket<Build> {
     //     let mut my_config = Config::development();
     //     my_config.set_port(18001);
-    let ttl = std::env::var("TSS_CLI_MANAGER_TTL")
-        .unwrap_or("300".to_string()).parse::<u64>().unwrap();
-    let db: TtlHashMap<Key, String> = TtlHashMap::new(Duration::from_secs(ttl));
+    let db: TtlHashMap<Key, String> = TtlHashMap::new(Duration::from_secs(config.ttl));
     let db_mtx = RwLock::new(db);
     //rocket::custom(my_config).mount("/", routes![get, set]).manage(db_mtx).launch();
 
     /////////////////////////////////////////////////////////////////
     rocket::build()
-        .mount("/", routes![get, set, signup_keygen, signup_sign])
+        .mount("/", routes![get, set, signup_keygen, cancel_keygen, signup_sign, admin::status, admin::expire, metrics::metrics])
+        .register("/", catchers![unauthorized, forbidden, payload_too_large, too_many_requests])
         .manage(db_mtx)
-        .launch()
-        .await
+        .manage(Metrics::new())
+        .manage(Limits::new(&config))
+        .manage(config)
 }
 
 #[post("/get", format = "json", data = "<request>")]
 fn get(
+    _auth: PartyAuth,
+    client_ip: Option<IpAddr>,
     db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    metrics: &State<Metrics>,
+    limits: &State<Limits>,
     request: Json<Index>,
 ) -> Json<Result<Entry, ManagerError>> {
+    metrics.requests.with_label_values(&["get"]).inc();
     let index: Index = request.0;
+    let room = split_round_key(&index.key).map_or("", |(_round, uuid)| uuid);
+    if let Err(e) = limits.check_rate(&client_name(client_ip), room) {
+        return rejected(metrics, "rate", e);
+    }
     let mut hm = db_mtx.write().unwrap();
 
     match hm.get(&index.key) {
@@ -55,81 +73,199 @@ fn get(
 }
 
 #[post("/set", format = "json", data = "<request>")]
-fn set(db_mtx: &State<RwLock<TtlHashMap<Key, String>>>, request: Json<Entry>) -> Json<Result<(), ()>> {
+fn set(
+    _auth: PartyAuth,
+    client_ip: Option<IpAddr>,
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    metrics: &State<Metrics>,
+    limits: &State<Limits>,
+    request: Json<Entry>,
+) -> Json<Result<(), ManagerError>> {
+    metrics.requests.with_label_values(&["set"]).inc();
     let entry: Entry = request.0;
+    let room = split_round_key(&entry.key).map_or("", |(_round, uuid)| uuid);
+    if let Err(e) = limits.check_rate(&client_name(client_ip), room) {
+        return rejected(metrics, "rate", e);
+    }
     let mut hm = db_mtx.write().unwrap();
+    let is_new = hm.get(&entry.key).is_none();
+    if let Err(e) = limits.check_entry(&entry.key, &entry.value, is_new) {
+        return rejected(metrics, "entries", e);
+    }
     hm.insert(entry.key.clone(), entry.value.clone());
+    metrics.entry_stored(&entry.key);
     Json(Ok(()))
 }
 
 #[post("/signupkeygen", format = "json", data = "<request>")]
 fn signup_keygen(
+    _auth: KeygenAuth,
+    client_ip: Option<IpAddr>,
     db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
-    request: Json<Params>,
-) -> Json<Result<PartySignup, ()>> {
-    let parties = request.parties.parse::<u16>().unwrap();
-    let key = "signup-keygen".to_string();
+    metrics: &State<Metrics>,
+    limits: &State<Limits>,
+    config: &State<ManagerConfig>,
+    request: Json<KeygenSignupRequestBody>,
+) -> Json<Result<SigningPartySignup, ManagerError>> {
+    metrics.requests.with_label_values(&["signupkeygen"]).inc();
+    if let Err(e) = limits.check_rate(&client_name(client_ip), "keygen") {
+        return rejected(metrics, "rate", e);
+    }
+    let (threshold, parties) = match Params::new(request.threshold, request.parties).values() {
+        Ok(values) => values,
+        Err(error) => return Json(Err(ManagerError{ error })),
+    };
+    let new_signup_request = request.party_uuid.is_empty();
+    let party_number = request.party_number;
+    let key = KEYGEN_SIGNUP_KEY.to_string();
     let mut hm = db_mtx.write().unwrap();
 
-    let client_signup = match hm.get(&key) {
-        Some(o) => serde_json::from_str(o).unwrap(),
-        None => PartySignup {
-            number: 0,
-            uuid: Uuid::new_v4().to_string(),
-        },
+    let stored_room: Option<KeygenRoom> = hm.get(&key).map(|o| serde_json::from_str(o).unwrap());
+    let mut keygen_room = match stored_room {
+        Some(room) if !new_signup_request || !room.is_replaceable() => room,
+        _ => KeygenRoom::new(threshold, parties, request.protocol, config.signup_timeout),
     };
 
-    let party_signup = {
-        if client_signup.number < parties {
-            PartySignup {
-                number: client_signup.number + 1,
-                uuid: client_signup.uuid,
-            }
-        } else {
-            PartySignup {
-                number: 1,
-                uuid: Uuid::new_v4().to_string(),
-            }
+    let party_signup = if !new_signup_request {
+        if !keygen_room.has_member(party_number, &request.party_uuid) {
+            metrics.signup_timeouts.inc();
+            return Json(Err(ManagerError{
+                error: "No party found with the given uuid, probably replaced due to timeout".to_string()
+            }));
+        }
+        let was_signup = keygen_room.last_stage == "signup";
+        let party_signup = keygen_room.update_ping(party_number);
+        if was_signup && keygen_room.last_stage != "signup" {
+            info!(room_uuid = keygen_room.room_uuid.as_str(), "All keygen parties joined, rounds start now");
+            metrics.session_started("keygen", keygen_room.protocol, &keygen_room.room_uuid);
+        }
+        party_signup
+    } else {
+        if keygen_room.last_stage != "signup" {
+            return Json(Err(ManagerError{
+                error: "Previous keygen is starting, retry shortly".to_string()
+            }));
+        }
+        if (keygen_room.threshold, keygen_room.room_size, keygen_room.protocol) != (threshold, parties, request.protocol) {
+            return Json(Err(ManagerError{
+                error: format!(
+                    "Another {} keygen for {}/{} is gathering parties, retry later",
+                    keygen_room.protocol, keygen_room.threshold, keygen_room.room_size
+                )
+            }));
+        }
+        match keygen_room.add_party() {
+            Some(party_signup) => party_signup,
+            None => return Json(Err(ManagerError{
+                error: "Keygen room is full, all members active".to_string()
+            })),
         }
     };
 
-    hm.insert(key, serde_json::to_string(&party_signup).unwrap());
+    hm.insert(key, serde_json::to_string(&keygen_room).unwrap());
     Json(Ok(party_signup))
 }
 
+/// Lets a party give up its keygen slot during signup, e.g. when it stops waiting for the others
+#[post("/cancelkeygen", format = "json", data = "<request>")]
+fn cancel_keygen(
+    _auth: KeygenAuth,
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    metrics: &State<Metrics>,
+    request: Json<KeygenSignupRequestBody>,
+) -> Json<Result<(), ManagerError>> {
+    metrics.requests.with_label_values(&["cancelkeygen"]).inc();
+    let key = KEYGEN_SIGNUP_KEY.to_string();
+    let mut hm = db_mtx.write().unwrap();
+
+    let mut keygen_room: KeygenRoom = match hm.get(&key) {
+        Some(o) => serde_json::from_str(o).unwrap(),
+        None => return Json(Err(ManagerError{ error: "No keygen room found".to_string() })),
+    };
+    if !keygen_room.has_member(request.party_number, &request.party_uuid) {
+        return Json(Err(ManagerError{ error: "No party found with the given uuid".to_string() }));
+    }
+    if keygen_room.last_stage != "signup" {
+        return Json(Err(ManagerError{ error: "Keygen signup phase is terminated".to_string() }));
+    }
+    keygen_room.remove_party(request.party_number);
+    info!(party = request.party_number, "Keygen party cancelled its signup");
+    hm.insert(key, serde_json::to_string(&keygen_room).unwrap());
+    Json(Ok(()))
+}
+
 #[post("/signupsign", format = "json", data = "<request>")]
 fn signup_sign(
+    _auth: SignAuth,
+    client_ip: Option<IpAddr>,
     db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+    metrics: &State<Metrics>,
+    limits: &State<Limits>,
+    config: &State<ManagerConfig>,
     request: Json<PartySignupRequestBody>,
 ) -> Json<Result<SigningPartySignup, ManagerError>> {
+    metrics.requests.with_label_values(&["signupsign"]).inc();
     let threshold = request.clone().threshold;
     let room_id = request.room_id.clone();
+    if let Err(e) = limits.check_rate(&client_name(client_ip), &room_id) {
+        return rejected(metrics, "rate", e);
+    }
     let party_uuid = request.party_uuid.clone();
     let new_signup_request = party_uuid.is_empty();
     let party_number = request.party_number;
-    let mut key = "signup-sign-".to_owned();
+    let policy = request.policy.clone();
+    let mut key = SIGN_SIGNUP_KEY_PREFIX.to_owned();
     key.push_str(&room_id);
 
     let mut hm = db_mtx.write().unwrap();
 
-    let mut signing_room = match hm.get(&key) {
+    let mut signing_room: SigningRoom = match hm.get(&key) {
         Some(o) => serde_json::from_str(o).unwrap(),
-        None => SigningRoom::new(room_id.clone(), threshold+1),
+        None => {
+            let open_rooms = hm.keys().filter(|x| x.starts_with(SIGN_SIGNUP_KEY_PREFIX)).count();
+            if let Err(e) = limits.check_new_room(open_rooms) {
+                return rejected(metrics, "rooms", e);
+            }
+            let policy = policy.clone().unwrap_or_default();
+            if let Err(error) = policy.validate(threshold + 1) {
+                return Json(Err(ManagerError{ error }));
+            }
+            SigningRoom::new(room_id.clone(), threshold+1, request.protocol, config.signup_timeout, policy)
+        },
     };
 
+    if policy.map_or(false, |x| x != signing_room.policy) {
+        return Json(Err(ManagerError{
+            error: "Room exists with a different signer policy".to_string()
+        }));
+    }
+    if signing_room.protocol != request.protocol {
+        return Json(Err(ManagerError{
+            error: format!("Room signs with {}, party uses {}", signing_room.protocol, request.protocol)
+        }));
+    }
+    if !signing_room.policy.admits(party_number) {
+        return Json(Err(ManagerError{
+            error: format!("Party {} is not allowed to sign in this room", party_number)
+        }));
+    }
+
     if signing_room.last_stage != "signup" {
         if signing_room.has_member(party_number, party_uuid.clone()) {
             return Json(Ok(signing_room.get_signup_info(party_number)));
         }
 
         if signing_room.are_all_members_inactive() {
-            let debug = json!({
-                "message": "All parties have been inactive. Renewed the room.",
-                "room_id": room_id,
-                "fragment.index": party_number,
-            });
-            println!("{}", serde_json::to_string_pretty(&debug).unwrap());
-            signing_room = SigningRoom::new(room_id, threshold + 1)
+            info!(room_id = room_id.as_str(), party = party_number, "All parties have been inactive. Renewed the room.");
+            metrics.signup_timeouts.inc();
+            signing_room = SigningRoom::new(
+                room_id.clone(), threshold + 1, signing_room.protocol, config.signup_timeout, signing_room.policy.clone()
+            )
+        }
+        else if !new_signup_request {
+            return Json(Err(ManagerError{
+                error: "Room signup phase is terminated, this party was not selected to sign".to_string()
+            }));
         }
         else {
             return Json(Err(ManagerError{
@@ -147,6 +283,7 @@ fn signup_sign(
     let party_signup = {
         if !new_signup_request {
             if !signing_room.has_member(party_number, party_uuid) {
+                metrics.signup_timeouts.inc();
                 return Json(Err(ManagerError{
                     error: "No party found with the given uuid, probably replaced due to timeout".to_string()
                 }));
@@ -161,7 +298,8 @@ fn signup_sign(
                     error: "Received a re-signup request for an active party. Request ignored".to_string()
                 }));
             }
-            println!("Received a re-signup request for a timed-out party {:?}, thus UUID is renewed", party_number);
+            info!(room_id = room_id.as_str(), party = party_number, "Received a re-signup request for a timed-out party, thus UUID is renewed");
+            metrics.resignups.inc();
             signing_room.replace_party(party_number)
         }
         else {
@@ -169,6 +307,48 @@ fn signup_sign(
         }
     };
 
+    if signing_room.last_stage != "signup" {
+        // This request closed the signup window, rounds start now
+        metrics.session_started("sign", signing_room.protocol, &signing_room.room_uuid);
+    }
     hm.insert(key.clone(), serde_json::to_string(&signing_room).unwrap());
     Json(Ok(party_signup))
 }
+
+fn client_name(client_ip: Option<IpAddr>) -> String {
+    client_ip.map_or("unknown".to_string(), |x| x.to_string())
+}
+
+/// Renders a refused request as the JSON error the clients expect, with the guard's error if one
+/// was kept.
+fn refusal(request: &Request<'_>, default: &str) -> Json<Result<(), ManagerError>> {
+    let error = request.local_cache(|| None::<ManagerError>).clone()
+        .unwrap_or_else(|| ManagerError{ error: default.to_string() });
+    Json(Err(error))
+}
+
+#[catch(401)]
+fn unauthorized(request: &Request<'_>) -> Json<Result<(), ManagerError>> {
+    refusal(request, "Missing or invalid credentials")
+}
+
+#[catch(403)]
+fn forbidden(request: &Request<'_>) -> Json<Result<(), ManagerError>> {
+    refusal(request, "Credentials not allowed for this request")
+}
+
+#[catch(413)]
+fn payload_too_large(request: &Request<'_>) -> Json<Result<(), ManagerError>> {
+    refusal(request, "Request body exceeds the size limit")
+}
+
+#[catch(429)]
+fn too_many_requests(request: &Request<'_>) -> Json<Result<(), ManagerError>> {
+    refusal(request, "Too many requests")
+}
+
+fn rejected<T>(metrics: &Metrics, reason: &str, error: ManagerError) -> Json<Result<T, ManagerError>> {
+    metrics.rejections.with_label_values(&[reason]).inc();
+    debug!(reason, error = error.error.as_str(), "Request rejected");
+    Json(Err(error))
+}
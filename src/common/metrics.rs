@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, RwLock};
+use std::time::Instant;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use rocket::{get, State};
+use ttlhashmap::TtlHashMap;
+
+use crate::common::admin::list_sessions;
+use crate::common::{split_round_key, Key, ManagerConfig, Protocol};
+
+/// Last round of each protocol, used to detect that a session has completed
+fn final_round(kind: &str, protocol: Protocol) -> &'static str {
+    match (kind, protocol) {
+        (_, Protocol::Lindell17) => "round3",
+        (_, Protocol::Recovery) => "round4",
+        ("keygen", Protocol::FrostEd25519) => "round3",
+        (_, Protocol::Frost) | (_, Protocol::FrostEd25519) => "round2",
+        ("keygen", _) => "round5",
+        (_, Protocol::Gg18) => "round9",
+        (_, Protocol::Gg20) => "round7",
+    }
+}
+
+pub struct Metrics {
+    registry: Registry,
+    pub requests: IntCounterVec,
+    pub rejections: IntCounterVec,
+    pub signup_timeouts: IntCounter,
+    pub resignups: IntCounter,
+    entries: IntGauge,
+    rooms: IntGaugeVec,
+    sessions_in_progress: IntGaugeVec,
+    session_duration: HistogramVec,
+    session_starts: Mutex<HashMap<String, (String, Protocol, Instant)>>, // Session uuid => (kind, protocol, start)
+}
+
+impl Metrics {
+
+    pub fn new() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("tss_manager_requests_total", "Requests received, per route"),
+            &["route"],
+        ).unwrap();
+        let rejections = IntCounterVec::new(
+            Opts::new("tss_manager_rejections_total", "Requests refused by the manager limits, per reason"),
+            &["reason"],
+        ).unwrap();
+        let signup_timeouts = IntCounter::new(
+            "tss_manager_signup_timeouts_total",
+            "Signing signups that hit a timed-out party or room",
+        ).unwrap();
+        let resignups = IntCounter::new(
+            "tss_manager_resignups_total",
+            "Timed-out signing parties replaced by a re-signup",
+        ).unwrap();
+        let entries = IntGauge::new(
+            "tss_manager_entries",
+            "Entries currently held by the manager",
+        ).unwrap();
+        let rooms = IntGaugeVec::new(
+            Opts::new("tss_manager_rooms", "Keygen and signing rooms, per stage"),
+            &["kind", "stage"],
+        ).unwrap();
+        let sessions_in_progress = IntGaugeVec::new(
+            Opts::new("tss_manager_sessions_in_progress", "Sessions past signup that did not reach their last round yet"),
+            &["kind"],
+        ).unwrap();
+        let session_duration = HistogramVec::new(
+            HistogramOpts::new("tss_manager_session_duration_seconds", "Time from the end of signup to the last round")
+                .buckets(vec![1.0, 2.5, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0]),
+            &["kind"],
+        ).unwrap();
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(rejections.clone())).unwrap();
+        registry.register(Box::new(signup_timeouts.clone())).unwrap();
+        registry.register(Box::new(resignups.clone())).unwrap();
+        registry.register(Box::new(entries.clone())).unwrap();
+        registry.register(Box::new(rooms.clone())).unwrap();
+        registry.register(Box::new(sessions_in_progress.clone())).unwrap();
+        registry.register(Box::new(session_duration.clone())).unwrap();
+
+        Metrics {
+            registry,
+            requests,
+            rejections,
+            signup_timeouts,
+            resignups,
+            entries,
+            rooms,
+            sessions_in_progress,
+            session_duration,
+            session_starts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn session_started(&self, kind: &str, protocol: Protocol, uuid: &str) {
+        let mut starts = self.session_starts.lock().unwrap();
+        starts.insert(uuid.to_string(), (kind.to_string(), protocol, Instant::now()));
+    }
+
+    /// Called for every stored key, i.e. `{party}-{round}-{uuid}` or `{from}-{to}-{round}-{uuid}`
+    pub fn entry_stored(&self, key: &str) {
+        let (round, uuid) = match split_round_key(key) {
+            Some(parts) => parts,
+            None => return,
+        };
+
+        let mut starts = self.session_starts.lock().unwrap();
+        let finished = match starts.get(uuid) {
+            Some((kind, protocol, _)) => round == final_round(kind, *protocol),
+            None => false,
+        };
+        if finished {
+            let (kind, _protocol, start) = starts.remove(uuid).unwrap();
+            self.session_duration
+                .with_label_values(&[kind.as_str()])
+                .observe(start.elapsed().as_secs_f64());
+        }
+    }
+
+    pub fn render(&self, hm: &mut TtlHashMap<Key, String>, ttl: u64) -> String {
+        self.entries.set(hm.len() as i64);
+
+        self.rooms.reset();
+        for session in list_sessions(hm) {
+            self.rooms
+                .with_label_values(&[session.kind.as_str(), session.last_stage.as_str()])
+                .inc();
+        }
+
+        self.sessions_in_progress.reset();
+        let mut starts = self.session_starts.lock().unwrap();
+        // Sessions older than the TTL have lost their data and will never finish
+        starts.retain(|_uuid, (_kind, _protocol, start)| start.elapsed().as_secs() < ttl);
+        for (kind, _protocol, _start) in starts.values() {
+            self.sessions_in_progress.with_label_values(&[kind.as_str()]).inc();
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+#[get("/metrics")]
+pub fn metrics(
+    metrics: &State<Metrics>,
+    config: &State<ManagerConfig>,
+    db_mtx: &State<RwLock<TtlHashMap<Key, String>>>,
+) -> String {
+    let mut hm = db_mtx.write().unwrap();
+    metrics.render(&mut hm, config.ttl)
+}
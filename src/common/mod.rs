@@ -1,10 +1,30 @@
+pub mod admin;
+pub mod auth;
+pub mod backup;
+pub mod config;
+pub mod dealer;
+pub mod eddsa;
+pub mod error;
+pub mod frost;
 pub mod hd_keys;
+pub mod key_share;
 pub mod keygen;
+pub mod keygen_gg20;
+pub mod keygen_room;
+pub mod limits;
 pub mod manager;
+pub mod metrics;
+pub mod p2p;
+pub mod paillier_keys;
+pub mod recovery;
+pub mod schnorr;
 pub mod signer;
+pub mod signer_gg20;
 pub mod signing_room;
+pub mod two_party;
 
-use std::{iter::repeat, thread, time, time::Duration};
+use std::{fmt, fs, iter::repeat, time, time::Duration};
+use std::future::Future;
 use std::time::Instant;
 
 use aes_gcm::{Aes256Gcm, Nonce};
@@ -16,14 +36,29 @@ use curv::{
     BigInt,
 };
 use curv::arithmetic::Converter;
-use reqwest::blocking::Client;
-use serde::{Deserialize, Serialize};
+use async_trait::async_trait;
+use reqwest::{Certificate, Client, Identity};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, instrument, trace};
+use tracing_subscriber::EnvFilter;
 use curv::cryptographic_primitives::hashing::hash_sha256::HSha256;
 use curv::cryptographic_primitives::hashing::traits::Hash;
 
 
+pub use config::{ClientConfig, Config, ConfigError, ManagerConfig};
+pub use error::Error;
+pub use key_share::KeyShare;
+pub use signing_room::SignerPolicy;
+use keygen_room::KeygenRoom;
+use signing_room::SigningRoom;
+
 pub type Key = String;
 
+pub const LOG_LEVEL_ENV: &str = "TSS_CLI_LOG";
+pub const MAX_KEY_LENGTH: usize = 128;
+const UUID_LENGTH: usize = 36;
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct AEAD {
     pub ciphertext: Vec<u8>,
@@ -35,7 +70,89 @@ pub struct PartySignupRequestBody {
     pub threshold: u16,
     pub room_id: String,
     pub party_number: u16,  // It's better to rename this to fragment_index
-    pub party_uuid: String
+    pub party_uuid: String,
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub policy: Option<SignerPolicy>, // Set by the initiator, parties giving one must match the room's
+}
+
+/// Threshold ECDSA protocol of a key, chosen at keygen, or of a signing session.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Gg18,
+    Gg20, // Identifiable abort
+    Lindell17, // Two parties only, see two_party
+    Frost, // Schnorr signing sessions with the shares of GG18 or GG20 keys, never chosen at keygen
+    Recovery, // Sessions restoring a lost share of a GG18 or GG20 key, see recovery
+    #[serde(rename = "frost-ed25519")]
+    FrostEd25519, // Keygen and signing of Ed25519 keys, see eddsa
+}
+
+impl Default for Protocol {
+    fn default() -> Self {
+        Protocol::Gg18
+    }
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "gg18" => Ok(Protocol::Gg18),
+            "gg20" => Ok(Protocol::Gg20),
+            "lindell17" => Ok(Protocol::Lindell17),
+            _ => Err(format!("Unknown protocol {:?}, expected gg18, gg20 or lindell17", value)),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Gg18 => write!(f, "gg18"),
+            Protocol::Gg20 => write!(f, "gg20"),
+            Protocol::Lindell17 => write!(f, "lindell17"),
+            Protocol::Frost => write!(f, "frost"),
+            Protocol::Recovery => write!(f, "recovery"),
+            Protocol::FrostEd25519 => write!(f, "frost-ed25519"),
+        }
+    }
+}
+
+/// Curve of a key, chosen at keygen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Curve {
+    Secp256k1,
+    Ed25519,
+}
+
+impl std::str::FromStr for Curve {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "secp256k1" => Ok(Curve::Secp256k1),
+            "ed25519" => Ok(Curve::Ed25519),
+            // The GG18, GG20 and Lindell17 code of multi-party-ecdsa, MtA and range proofs included, is typed on secp256k1
+            "p256" | "p-256" | "secp256r1" | "prime256v1" => Err(format!(
+                "Curve {:?} is not supported: threshold ECDSA here only runs on secp256k1", value
+            )),
+            _ => Err(format!("Unknown curve {:?}, expected secp256k1 or ed25519", value)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct KeygenSignupRequestBody {
+    pub threshold: u16,
+    pub parties: u16,
+    #[serde(default)]
+    pub protocol: Protocol,
+    pub party_number: u16, // Number handed out by the manager, 0 for a new signup
+    pub party_uuid: String,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -70,17 +187,84 @@ pub struct Entry {
     pub value: String,
 }
 
+/// Splits a round key, i.e. `{party}-{round}-{uuid}` or `{from}-{to}-{round}-{uuid}`, into its round
+/// and session uuid. Keys longer than `MAX_KEY_LENGTH` or not ending with a uuid give `None`.
+pub fn split_round_key(key: &str) -> Option<(&str, &str)> {
+    if key.len() > MAX_KEY_LENGTH {
+        return None;
+    }
+    let split = key.len().checked_sub(UUID_LENGTH)?;
+    let (rest, uuid) = (key.get(..split)?, key.get(split..)?);
+    let round = rest.strip_suffix('-')?.rsplit('-').next().unwrap_or("");
+    uuid::Uuid::parse_str(uuid).ok()?;
+    Some((round, uuid))
+}
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ManagerError {
     pub error: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SessionStatus {
+    pub kind: String, // "keygen" or "sign"
+    pub room_id: String,
+    pub room_uuid: String,
+    pub last_stage: String,
+    pub joined: u16,
+    #[serde(default)]
+    pub keygen_room: Option<KeygenRoom>,
+    pub signing_room: Option<SigningRoom>,
+    pub received_keys: Vec<String>, // Round keys stored for this session, without the uuid suffix
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ExpireRequest {
+    pub room: String, // Signing room_id, or the uuid of a keygen or signing session
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Params {
     pub parties: String,
     pub threshold: String,
 }
 
+impl Params {
+
+    pub fn new(threshold: u16, parties: u16) -> Self {
+        Params {
+            threshold: threshold.to_string(),
+            parties: parties.to_string(),
+        }
+    }
+
+    /// Parses threshold params given as `t/n`, e.g. `1/3` for a 2 of 3 scheme
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let params: Vec<&str> = value.split('/').collect();
+        if params.len() != 2 {
+            return Err(format!("Threshold params must be given as threshold/parties, e.g. 1/3, got {:?}", value));
+        }
+        let params = Params {
+            threshold: params[0].trim().to_string(),
+            parties: params[1].trim().to_string(),
+        };
+        params.values()?;
+        Ok(params)
+    }
+
+    /// Returns `(t, n)` once checked that signing takes at least 2 and at most all `n` parties
+    pub fn values(&self) -> Result<(u16, u16), String> {
+        let threshold = self.threshold.parse::<u16>().map_err(|_| format!("Invalid threshold {:?}", self.threshold))?;
+        let parties = self.parties.parse::<u16>().map_err(|_| format!("Invalid number of parties {:?}", self.parties))?;
+        if threshold == 0 || threshold >= parties {
+            return Err(format!(
+                "Threshold must be between 1 and parties - 1 (t+1 of n parties sign), got {}/{}", threshold, parties
+            ));
+        }
+        Ok((threshold, parties))
+    }
+}
+
 #[allow(dead_code)]
 pub fn aes_encrypt(key: &[u8], plaintext: &[u8]) -> AEAD {
 
@@ -131,60 +315,168 @@ pub fn aes_decrypt(key: &[u8], aead_pack: AEAD) -> Vec<u8> {
     out.unwrap_or_default()
 }
 
-pub fn postb<T>(addr: &String, client: &Client, path: &str, body: T) -> Option<String>
+/// Carries the JSON requests of the protocol runners to the manager and returns the raw answers.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn post(&self, path: &str, body: &str) -> Result<String, ManagerError>;
+}
+
+pub struct HttpTransport {
+    pub addr: String,
+    pub client: Client,
+    pub auth_token: Option<String>,
+}
+
+impl HttpTransport {
+    pub fn new(addr: String) -> Self {
+        HttpTransport::from_config(&ClientConfig {
+            manager_addr: addr,
+            ..ClientConfig::default()
+        }).unwrap()
+    }
+
+    pub fn from_config(config: &ClientConfig) -> Result<Self, ConfigError> {
+        Ok(HttpTransport {
+            addr: config.manager_addr.clone(),
+            client: build_client(config)?,
+            auth_token: config.auth_token.clone(),
+        })
+    }
+
+    pub fn request(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.post(&format!("{}/{}", self.addr, path));
+        match &self.auth_token {
+            Some(token) => request.header(AUTHORIZATION, format!("Bearer {}", token)),
+            None => request,
+        }
+    }
+}
+
+/// Builds the HTTP client to the manager, with the client certificate and CA of the config if any.
+pub fn build_client(config: &ClientConfig) -> Result<Client, ConfigError> {
+    let read = |path: &String| fs::read(path).map_err(|e| ConfigError{
+        error: format!("unable to read {}: {}", path, e)
+    });
+
+    let mut builder = Client::builder()
+        .timeout(Duration::from_secs(config.request_timeout));
+    if let Some(path) = &config.client_identity {
+        let password = config.client_identity_password.clone().unwrap_or_default();
+        let identity = Identity::from_pkcs12_der(&read(path)?, &password).map_err(|e| ConfigError{
+            error: format!("invalid client identity {}: {}", path, e)
+        })?;
+        builder = builder.identity(identity);
+    }
+    if let Some(path) = &config.ca_cert {
+        let certificate = Certificate::from_pem(&read(path)?).map_err(|e| ConfigError{
+            error: format!("invalid CA certificate {}: {}", path, e)
+        })?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    builder.build().map_err(|e| ConfigError{ error: e.to_string() })
+}
+
+#[async_trait]
+impl Transport for HttpTransport {
+    async fn post(&self, path: &str, body: &str) -> Result<String, ManagerError> {
+        let retries = 3;
+        let retry_delay = time::Duration::from_millis(250);
+        let mut last_error = String::new();
+        for _i in 1..retries {
+            let res = self.request(path)
+                .header(CONTENT_TYPE, "application/json")
+                .body(body.to_string())
+                .send()
+                .await;
+
+            match res {
+                Ok(res) => {
+                    let status = res.status();
+                    let text = res.text().await.map_err(|e| ManagerError{ error: e.to_string() })?;
+                    if !status.is_success() {
+                        // The manager's catchers answer with the reason of the refusal
+                        let refusal = serde_json::from_str::<Result<(), ManagerError>>(&text).ok().and_then(Result::err);
+                        return Err(refusal.unwrap_or(ManagerError{
+                            error: format!("Manager answered /{} with {}", path, status)
+                        }));
+                    }
+                    return Ok(text);
+                }
+                Err(e) => last_error = e.to_string(),
+            }
+            tokio::time::sleep(retry_delay).await;
+        }
+        Err(ManagerError{ error: format!("no answer to /{}: {}", path, last_error) })
+    }
+}
+
+pub async fn postb<T>(transport: &dyn Transport, path: &str, body: T) -> Result<String, Error>
 where
     T: serde::ser::Serialize,
 {
-    //    let mut addr = env::args()
-    //        .nth(4)
-    //        .unwrap_or_else(|| "http://127.0.0.1:8001".to_string());
-    //    for argument in env::args() {
-    //        if argument.contains("://") {
-    //            let addr_parts: Vec<&str> = argument.split("http:").collect();
-    //            addr = format!("http:{}", addr_parts[1]);
-    //        }
-    //    }
-    let retries = 3;
-    let retry_delay = time::Duration::from_millis(250);
-    for _i in 1..retries {
-        let addr = format!("{}/{}", addr, path);
-        let res = client.post(&addr).json(&body).send();
-
-        if let Ok(res) = res {
-            return Some(res.text().unwrap());
-        }
-        thread::sleep(retry_delay);
+    Ok(transport.post(path, &serde_json::to_string(&body).unwrap()).await?)
+}
+
+/// Parses an answer of the manager, which carries either the value or the manager's refusal.
+pub(crate) fn manager_answer<R: DeserializeOwned>(path: &str, res_body: &str) -> Result<Result<R, ManagerError>, Error> {
+    serde_json::from_str(res_body)
+        .map_err(|e| Error::Manager(format!("unexpected answer to /{}: {}", path, e)))
+}
+
+/// Sends logs to stderr, so that stdout only carries the JSON results.
+/// `level` takes the `tracing` filter syntax, e.g. `debug` or `tss_cli=trace`.
+pub fn init_logging(level: Option<&str>, json: bool) {
+    let filter = match level {
+        Some(level) => EnvFilter::new(level),
+        None => EnvFilter::try_from_env(LOG_LEVEL_ENV).unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    if json {
+        builder.json().init();
+    } else {
+        builder.init();
     }
-    None
 }
 
-pub fn broadcast(
-    addr: &String,
-    client: &Client,
+/// Runs a protocol future to completion on a single-threaded runtime, for the blocking entry points.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[instrument(level = "debug", skip(transport, data, sender_uuid))]
+pub async fn broadcast(
+    transport: &dyn Transport,
     party_num: u16,
     round: &str,
     data: String,
     sender_uuid: String,
-) -> Result<(), ()> {
+) -> Result<(), Error> {
     let key = format!("{}-{}-{}", party_num, round, sender_uuid);
     let entry = Entry {
         key: key.clone(),
         value: data,
     };
 
-    let res_body = postb(&addr, &client, "set", entry).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+    let res_body = postb(transport, "set", entry).await?;
+    manager_answer::<()>("set", &res_body)??;
+    Ok(())
 }
 
-pub fn sendp2p(
-    addr: &String,
-    client: &Client,
+#[instrument(level = "debug", skip(transport, data, sender_uuid))]
+pub async fn sendp2p(
+    transport: &dyn Transport,
     party_from: u16,
     party_to: u16,
     round: &str,
     data: String,
     sender_uuid: String,
-) -> Result<(), ()> {
+) -> Result<(), Error> {
     let key = format!("{}-{}-{}-{}", party_from, party_to, round, sender_uuid);
 
     let entry = Entry {
@@ -192,93 +484,113 @@ pub fn sendp2p(
         value: data,
     };
 
-    let res_body = postb(&addr, &client, "set", entry).unwrap();
-    serde_json::from_str(&res_body).unwrap()
+    let res_body = postb(transport, "set", entry).await?;
+    manager_answer::<()>("set", &res_body)??;
+    Ok(())
 }
 
-pub fn poll_for_broadcasts(
-    addr: &String,
-    client: &Client,
+#[instrument(level = "debug", skip(transport, config, sender_uuid))]
+pub async fn poll_for_broadcasts(
+    transport: &dyn Transport,
     party_num: u16,
     n: u16,
-    delay: Duration,
+    config: &ClientConfig,
     round: &str,
     sender_uuid: String,
-) -> Vec<String> {
+) -> Result<Vec<String>, Error> {
+    let parties = (1..=n).filter(|i| *i != party_num).collect::<Vec<u16>>();
+    poll_for_broadcasts_from(transport, &parties, config, round, sender_uuid).await
+}
+
+/// Like `poll_for_broadcasts`, for the messages of the given parties only, in their order.
+pub async fn poll_for_broadcasts_from(
+    transport: &dyn Transport,
+    parties: &[u16],
+    config: &ClientConfig,
+    round: &str,
+    sender_uuid: String,
+) -> Result<Vec<String>, Error> {
     let mut ans_vec = Vec::new();
-    let timeout = std::env::var("TSS_CLI_POLL_TIMEOUT")
-        .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    for i in 1..=n {
-        if i != party_num {
-            let key = format!("{}-{}-{}", i, round, sender_uuid);
-            let index = Index { key };
-            let start_time = Instant::now();
-            loop {
-                // add delay to allow the server to process request:
-                let res_body = postb(&addr, &client, "get", index.clone()).unwrap();
-                let answer: Result<Entry, ManagerError> = serde_json::from_str(&res_body).unwrap();
-                match answer {
-                    Ok(answer) => {
-                        ans_vec.push(answer.value);
-                        println!("[{:?}] party {:?} => party {:?}", round, i, party_num);
-                        break;
-                    },
-                    Err(ManagerError{error}) => {
-                        #[cfg(debug_assertions)]
-                        println!("[{:?}] party {:?} => party {:?}, error: {:?}", round, i, party_num, error);
-                    }
+    let timeout = config.poll_timeout;
+    let delay = Duration::from_millis(config.poll_delay);
+    for i in parties.iter().copied() {
+        let key = format!("{}-{}-{}", i, round, sender_uuid);
+        let index = Index { key };
+        let start_time = Instant::now();
+        loop {
+            // add delay to allow the server to process request:
+            let res_body = postb(transport, "get", index.clone()).await?;
+            let answer: Result<Entry, ManagerError> = manager_answer("get", &res_body)?;
+            match answer {
+                Ok(answer) => {
+                    ans_vec.push(answer.value);
+                    debug!(from = i, "Received message");
+                    break;
+                },
+                Err(ManagerError{error}) => {
+                    trace!(from = i, error = error.as_str(), "Message not available yet");
                 }
-                if start_time.elapsed().as_secs() > timeout {
-                    panic!("Polling timed out! No response received from party number {:?}", i);
-                };
-
-                thread::sleep(delay);
             }
+            if start_time.elapsed().as_secs() > timeout {
+                return Err(Error::Timeout(format!("Polling timed out! No response received in {:?} from party number {:?}", round, i)));
+            };
+
+            tokio::time::sleep(delay).await;
         }
     }
-    ans_vec
+    Ok(ans_vec)
 }
 
-pub fn poll_for_p2p(
-    addr: &String,
-    client: &Client,
+#[instrument(level = "debug", skip(transport, config, sender_uuid))]
+pub async fn poll_for_p2p(
+    transport: &dyn Transport,
     party_num: u16,
     n: u16,
-    delay: Duration,
+    config: &ClientConfig,
     round: &str,
     sender_uuid: String,
-) -> Vec<String> {
+) -> Result<Vec<String>, Error> {
+    let parties = (1..=n).filter(|i| *i != party_num).collect::<Vec<u16>>();
+    poll_for_p2p_from(transport, party_num, &parties, config, round, sender_uuid).await
+}
+
+/// Like `poll_for_p2p`, for the messages of the given parties only, in their order.
+pub async fn poll_for_p2p_from(
+    transport: &dyn Transport,
+    party_num: u16,
+    parties: &[u16],
+    config: &ClientConfig,
+    round: &str,
+    sender_uuid: String,
+) -> Result<Vec<String>, Error> {
     let mut ans_vec = Vec::new();
-    let timeout = std::env::var("TSS_CLI_POLL_TIMEOUT")
-        .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    for i in 1..=n {
-        if i != party_num {
-            let key = format!("{}-{}-{}-{}", i, party_num, round, sender_uuid);
-            let index = Index { key };
-            let start_time = Instant::now();
-            loop {
-                // add delay to allow the server to process request:
-                thread::sleep(delay);
-                let res_body = postb(&addr, &client, "get", index.clone()).unwrap();
-                let answer: Result<Entry, ManagerError> = serde_json::from_str(&res_body).unwrap();
-                match answer {
-                    Ok(answer) => {
-                        ans_vec.push(answer.value);
-                        println!("[{:?}] party {:?} => party {:?}", round, i, party_num);
-                        break;
-                    },
-                    Err(ManagerError{error}) => {
-                        if start_time.elapsed().as_secs() > timeout {
-                            panic!("Polling timed out! No response received in {:?} from party number {:?}", round, i);
-                        };
-                        #[cfg(debug_assertions)]
-                        println!("[{:?}] party {:?} => party {:?}, error: {:?}", round, i, party_num, error);
-                    }
+    let timeout = config.poll_timeout;
+    let delay = Duration::from_millis(config.poll_delay);
+    for i in parties.iter().copied() {
+        let key = format!("{}-{}-{}-{}", i, party_num, round, sender_uuid);
+        let index = Index { key };
+        let start_time = Instant::now();
+        loop {
+            // add delay to allow the server to process request:
+            tokio::time::sleep(delay).await;
+            let res_body = postb(transport, "get", index.clone()).await?;
+            let answer: Result<Entry, ManagerError> = manager_answer("get", &res_body)?;
+            match answer {
+                Ok(answer) => {
+                    ans_vec.push(answer.value);
+                    debug!(from = i, "Received message");
+                    break;
+                },
+                Err(ManagerError{error}) => {
+                    if start_time.elapsed().as_secs() > timeout {
+                        return Err(Error::Timeout(format!("Polling timed out! No response received in {:?} from party number {:?}", round, i)));
+                    };
+                    trace!(from = i, error = error.as_str(), "Message not available yet");
                 }
             }
         }
     }
-    ans_vec
+    Ok(ans_vec)
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use curv::elliptic::curves::traits::ECPoint;
+use ring::digest::{Context, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::common::Error;
+
+const KDF_LABEL: &[u8] = b"tss-cli p2p v1";
+const NONCE_LENGTH: usize = 12;
+
+/// A p2p message sealed to its recipient, the manager only stores it.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SealedMessage {
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>, // Ends with the GCM tag
+}
+
+/// AES-GCM keys of a party's p2p messages with each other party of a session, one per direction.
+/// They are derived from Diffie-Hellman values only the two parties can compute, the session uuid
+/// and both party numbers, so a message only opens for its recipient and in its own session.
+#[derive(Clone)]
+pub struct P2pChannel {
+    party_num: u16,
+    uuid: String,
+    keys: HashMap<u16, ([u8; 32], [u8; 32])>, // Other party's number => (sending key, receiving key)
+}
+
+impl fmt::Debug for P2pChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parties = self.keys.keys().collect::<Vec<_>>();
+        parties.sort();
+        f.debug_struct("P2pChannel")
+            .field("party_num", &self.party_num)
+            .field("uuid", &self.uuid)
+            .field("parties", &parties)
+            .finish()
+    }
+}
+
+impl P2pChannel {
+
+    /// `shared_secrets` holds, for the number of each other party, the Diffie-Hellman values shared with it.
+    pub fn new<P: ECPoint>(party_num: u16, uuid: &str, shared_secrets: HashMap<u16, Vec<P>>) -> Self {
+        let keys = shared_secrets.iter()
+            .map(|(other, secrets)| (*other, (
+                derive_key(uuid, party_num, *other, secrets),
+                derive_key(uuid, *other, party_num, secrets),
+            )))
+            .collect();
+        P2pChannel { party_num, uuid: uuid.to_string(), keys }
+    }
+
+    /// Seals the message of `round` to party `to`, returns the JSON to send.
+    pub fn seal(&self, to: u16, round: &str, plaintext: &str) -> String {
+        let (key, _) = self.keys.get(&to).expect("No p2p key for this party");
+        let mut nonce = [0u8; NONCE_LENGTH];
+        SystemRandom::new().fill(&mut nonce).expect("No randomness for the p2p nonce");
+        let aad = self.aad(self.party_num, to, round);
+        let ciphertext = Aes256Gcm::new(Key::from_slice(key))
+            .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext.as_bytes(), aad: aad.as_bytes() })
+            .expect("p2p encryption failure");
+        serde_json::to_string(&SealedMessage { nonce: nonce.to_vec(), ciphertext }).unwrap()
+    }
+
+    /// Opens the message of `round` received from party `from`.
+    pub fn open(&self, from: u16, round: &str, message: &str) -> Result<String, String> {
+        let failed = || format!("Could not open the {} message of party {}, aborting", round, from);
+        let (_, key) = self.keys.get(&from).ok_or_else(failed)?;
+        let sealed: SealedMessage = serde_json::from_str(message).map_err(|_| failed())?;
+        if sealed.nonce.len() != NONCE_LENGTH {
+            return Err(failed());
+        }
+        let aad = self.aad(from, self.party_num, round);
+        let plaintext = Aes256Gcm::new(Key::from_slice(key))
+            .decrypt(Nonce::from_slice(&sealed.nonce), Payload { msg: &sealed.ciphertext, aad: aad.as_bytes() })
+            .map_err(|_| failed())?;
+        String::from_utf8(plaintext).map_err(|_| failed())
+    }
+
+    /// Opens the answers of `poll_for_p2p`, which come in the order of the other parties' numbers.
+    pub fn open_all(&self, round: &str, ans_vec: &[String]) -> Result<Vec<String>, Error> {
+        let mut parties = self.keys.keys().copied().collect::<Vec<u16>>();
+        parties.sort_unstable();
+        parties.iter()
+            .zip(ans_vec)
+            .map(|(from, message)| self.open(*from, round, message).map_err(Error::Protocol))
+            .collect()
+    }
+
+    // Same layout as the manager key of the message
+    fn aad(&self, from: u16, to: u16, round: &str) -> String {
+        format!("{}-{}-{}-{}", from, to, round, self.uuid)
+    }
+}
+
+fn derive_key<P: ECPoint>(uuid: &str, from: u16, to: u16, shared_secrets: &[P]) -> [u8; 32] {
+    let mut context = Context::new(&SHA256);
+    context.update(KDF_LABEL);
+    context.update(uuid.as_bytes());
+    context.update(&from.to_be_bytes());
+    context.update(&to.to_be_bytes());
+    for secret in shared_secrets {
+        context.update(&secret.pk_to_key_slice());
+    }
+    let mut key = [0u8; 32];
+    key.copy_from_slice(context.finish().as_ref());
+    key
+}
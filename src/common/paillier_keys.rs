@@ -0,0 +1,282 @@
+use curv::arithmetic::{BasicOps, Converter, Modulo};
+use curv::BigInt;
+use paillier::{DecryptionKey, EncryptionKey, Keypair};
+use ring::digest::{Context, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+const PRIME_LENGTH: usize = 128; // Each of the two primes of a 2048 bits Paillier modulus
+const MILLER_RABIN_ROUNDS: usize = 40;
+const SMALL_PRIMES: [u32; 24] = [3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97];
+const SEED_LENGTH: usize = 32;
+// Each challenge of the modulus proof at least halves the chance of a modulus that is not a Blum integer passing
+const MODULUS_CHALLENGES: usize = 80;
+
+/// Paillier keys whose modulus is a Blum integer, i.e. both primes are 3 mod 4, as keygen proves with
+/// `BlumModulusProof`.
+pub fn blum_paillier_keys() -> (EncryptionKey, DecryptionKey) {
+    let mut seed = [0u8; SEED_LENGTH];
+    SystemRandom::new().fill(&mut seed).expect("no randomness for the Paillier primes");
+    let p = prime_from_seed(&seed, b"blum p", true);
+    let q = prime_from_seed(&seed, b"blum q", true);
+    Keypair { p, q }.keys()
+}
+
+/// Non-interactive proof that a Paillier modulus N is the product of two primes that are 3 mod 4
+/// (CGGMP21, figure 16), the challenges y_i are hashed from N and w. With z_i^N = y_i the modulus is
+/// coprime with its totient, with x_i^4 = (-1)^a_i * w^b_i * y_i every y_i has a fourth root up to sign
+/// and w, which fails for about half of the y_i when N has more than two factors or a prime that is 1 mod 4.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct BlumModulusProof {
+    pub w: BigInt,
+    pub x: Vec<BigInt>,
+    pub a: Vec<bool>,
+    pub b: Vec<bool>,
+    pub z: Vec<BigInt>,
+}
+
+impl BlumModulusProof {
+
+    pub fn prove(dk: &DecryptionKey) -> Self {
+        let (p, q) = (&dk.p, &dk.q);
+        let n = p.mul(q);
+        let one = BigInt::from(1);
+        let phi = p.sub(&one).mul(&q.sub(&one));
+        let n_inv = mod_inverse(&n, &phi).expect("Paillier modulus is coprime with its totient");
+        let p_inv = mod_inverse(p, q).expect("Paillier primes are distinct");
+        let w = loop {
+            let w = random_below(&n);
+            if jacobi(&w, &n) == -1 {
+                break w;
+            }
+        };
+
+        let mut proof = BlumModulusProof { w, x: vec![], a: vec![], b: vec![], z: vec![] };
+        for y in challenges(&n, &proof.w) {
+            // Exactly one of y, -y, wy, -wy is a square modulo p and q
+            let (a, b, y_adjusted) = [(false, false), (true, false), (false, true), (true, true)].iter()
+                .map(|&(a, b)| (a, b, adjust(&y, a, b, &proof.w, &n)))
+                .find(|(_, _, y_adjusted)| is_square(y_adjusted, p) && is_square(y_adjusted, q))
+                .expect("Paillier modulus is a Blum integer");
+            let x_p = fourth_root(&y_adjusted, p);
+            let x_q = fourth_root(&y_adjusted, q);
+            let h = BigInt::mod_mul(&BigInt::mod_sub(&x_q, &x_p, q), &p_inv, q);
+            proof.x.push(x_p.add(&p.mul(&h)));
+            proof.a.push(a);
+            proof.b.push(b);
+            proof.z.push(BigInt::mod_pow(&y, &n_inv, &n));
+        }
+        proof
+    }
+
+    pub fn verify(&self, ek: &EncryptionKey) -> Result<(), String> {
+        let n = &ek.n;
+        let zero = BigInt::from(0);
+        let one = BigInt::from(1);
+        let two = BigInt::from(2);
+        if *n <= two || n.modulus(&two) == zero || BigInt::mod_pow(&two, &n.sub(&one), n) == one {
+            return Err("modulus is even or prime".to_string());
+        }
+        let lengths = [self.x.len(), self.a.len(), self.b.len(), self.z.len()];
+        if lengths.iter().any(|x| *x != MODULUS_CHALLENGES) {
+            return Err(format!("proof holds {:?} answers, expected {}", lengths, MODULUS_CHALLENGES));
+        }
+        let in_range = |v: &BigInt| *v >= zero && v < n;
+        if !in_range(&self.w) || jacobi(&self.w, n) != -1 {
+            return Err("w is not a non-residue of Jacobi symbol -1".to_string());
+        }
+        for (i, y) in challenges(n, &self.w).iter().enumerate() {
+            if !in_range(&self.z[i]) || BigInt::mod_pow(&self.z[i], n, n) != *y {
+                return Err(format!("answer {} is not an N-th root", i + 1));
+            }
+            let x_4 = BigInt::mod_pow(&self.x[i], &BigInt::from(4), n);
+            if !in_range(&self.x[i]) || x_4 != adjust(y, self.a[i], self.b[i], &self.w, n) {
+                return Err(format!("answer {} is not a fourth root", i + 1));
+            }
+        }
+        Ok(())
+    }
+}
+
+// (-1)^a * w^b * y mod n
+fn adjust(y: &BigInt, a: bool, b: bool, w: &BigInt, n: &BigInt) -> BigInt {
+    let mut value = y.modulus(n);
+    if b {
+        value = BigInt::mod_mul(&value, w, n);
+    }
+    if a {
+        value = n.sub(&value).modulus(n);
+    }
+    value
+}
+
+// Euler's criterion, p an odd prime, zero counts as a square
+fn is_square(value: &BigInt, p: &BigInt) -> bool {
+    let p_minus_one = p.sub(&BigInt::from(1));
+    let exponent = p_minus_one.clone() / BigInt::from(2);
+    BigInt::mod_pow(value, &exponent, p) != p_minus_one
+}
+
+// For p = 3 mod 4, v^((p+1)/4) is the square root of a square v that is itself a square, taking it twice
+// gives a fourth root
+fn fourth_root(value: &BigInt, p: &BigInt) -> BigInt {
+    let exponent = p.add(&BigInt::from(1)) / BigInt::from(4);
+    BigInt::mod_pow(value, &exponent.mul(&exponent), p)
+}
+
+// Jacobi symbol (a/n) of an odd n
+fn jacobi(a: &BigInt, n: &BigInt) -> i8 {
+    let zero = BigInt::from(0);
+    let two = BigInt::from(2);
+    let three = BigInt::from(3);
+    let four = BigInt::from(4);
+    let eight = BigInt::from(8);
+    let mut a = a.modulus(n);
+    let mut n = n.clone();
+    let mut result = 1;
+    while a != zero {
+        while a.modulus(&two) == zero {
+            a = a / two.clone();
+            let r = n.modulus(&eight);
+            if r == three || r == BigInt::from(5) {
+                result = -result;
+            }
+        }
+        std::mem::swap(&mut a, &mut n);
+        if a.modulus(&four) == three && n.modulus(&four) == three {
+            result = -result;
+        }
+        a = a.modulus(&n);
+    }
+    match n == BigInt::from(1) {
+        true => result,
+        false => 0,
+    }
+}
+
+// Inverse of a modulo m by the extended Euclidean algorithm, None if they are not coprime
+fn mod_inverse(a: &BigInt, m: &BigInt) -> Option<BigInt> {
+    let zero = BigInt::from(0);
+    let (mut old_r, mut r) = (a.modulus(m), m.clone());
+    let (mut old_s, mut s) = (BigInt::from(1), zero.clone());
+    while r != zero {
+        let quotient = old_r.clone() / r.clone();
+        let next_r = old_r.sub(&quotient.mul(&r));
+        old_r = std::mem::replace(&mut r, next_r);
+        let next_s = BigInt::mod_sub(&old_s, &BigInt::mod_mul(&quotient, &s, m), m);
+        old_s = std::mem::replace(&mut s, next_s);
+    }
+    match old_r == BigInt::from(1) {
+        true => Some(old_s.modulus(m)),
+        false => None,
+    }
+}
+
+fn random_below(n: &BigInt) -> BigInt {
+    let mut bytes = vec![0u8; n.to_bytes().len() + 16];
+    SystemRandom::new().fill(&mut bytes).expect("no randomness for the modulus proof");
+    BigInt::from_bytes(&bytes).modulus(n)
+}
+
+// y_1 .. y_m, SHA256 in counter mode over N and w, with 128 more bits than N so the bias is negligible
+fn challenges(n: &BigInt, w: &BigInt) -> Vec<BigInt> {
+    let n_bytes = n.to_bytes();
+    let w_bytes = w.to_bytes();
+    let length = n_bytes.len() + 16;
+    (0..MODULUS_CHALLENGES as u32)
+        .map(|i| {
+            let mut bytes: Vec<u8> = Vec::new();
+            let mut counter: u32 = 0;
+            while bytes.len() < length {
+                let mut context = Context::new(&SHA256);
+                context.update(b"tss-cli blum modulus v1");
+                context.update(&(n_bytes.len() as u32).to_be_bytes());
+                context.update(&n_bytes);
+                context.update(&(w_bytes.len() as u32).to_be_bytes());
+                context.update(&w_bytes);
+                context.update(&i.to_be_bytes());
+                context.update(&counter.to_be_bytes());
+                bytes.extend_from_slice(context.finish().as_ref());
+                counter += 1;
+            }
+            bytes.truncate(length);
+            BigInt::from_bytes(&bytes).modulus(n)
+        })
+        .collect()
+}
+
+/// First probable prime from a start derived from `seed` with its two top bits set, so the modulus has
+/// 2048 bits. A `blum` prime is 3 mod 4.
+pub(crate) fn prime_from_seed(seed: &[u8], label: &[u8], blum: bool) -> BigInt {
+    let mut stream = SeedStream { seed, label, counter: 0 };
+    let mut bytes = stream.next_bytes(PRIME_LENGTH);
+    bytes[0] |= 0xc0;
+    bytes[PRIME_LENGTH - 1] |= if blum { 3 } else { 1 };
+    let step = BigInt::from(if blum { 4 } else { 2 });
+    let mut candidate = BigInt::from_bytes(&bytes);
+    while !is_probable_prime(&candidate, &mut stream) {
+        candidate = candidate + step.clone();
+    }
+    candidate
+}
+
+// Miller-Rabin with bases from the seed, after trial division
+fn is_probable_prime(n: &BigInt, stream: &mut SeedStream) -> bool {
+    let zero = BigInt::from(0);
+    let one = BigInt::from(1);
+    let two = BigInt::from(2);
+    if SMALL_PRIMES.iter().any(|p| n.modulus(&BigInt::from(*p as i32)) == zero) {
+        return false;
+    }
+    let n_minus_one = n.sub(&one);
+    let mut d = n_minus_one.clone();
+    let mut s = 0;
+    while d.modulus(&two) == zero {
+        d = d / two.clone();
+        s += 1;
+    }
+    let range = n.sub(&BigInt::from(3));
+    for _ in 0..MILLER_RABIN_ROUNDS {
+        let a = BigInt::from_bytes(&stream.next_bytes(PRIME_LENGTH)).modulus(&range) + two.clone();
+        let mut x = BigInt::mod_pow(&a, &d, n);
+        if x == one || x == n_minus_one {
+            continue;
+        }
+        let mut witness = true;
+        for _ in 1..s {
+            x = BigInt::mod_mul(&x, &x, n);
+            if x == n_minus_one {
+                witness = false;
+                break;
+            }
+        }
+        if witness {
+            return false;
+        }
+    }
+    true
+}
+
+// SHA256 in counter mode over the seed, one stream per prime
+struct SeedStream<'a> {
+    seed: &'a [u8],
+    label: &'a [u8],
+    counter: u32,
+}
+
+impl<'a> SeedStream<'a> {
+    fn next_bytes(&mut self, length: usize) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        while bytes.len() < length {
+            let mut context = Context::new(&SHA256);
+            context.update(b"tss-cli paillier seed v1");
+            context.update(self.seed);
+            context.update(self.label);
+            context.update(&self.counter.to_be_bytes());
+            bytes.extend_from_slice(context.finish().as_ref());
+            self.counter += 1;
+        }
+        bytes.truncate(length);
+        bytes
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::HashMap;
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::Keys;
+use paillier::EncryptionKey;
+use serde::{Deserialize, Serialize};
+use tracing::{field, info, instrument, Span};
+use zk_paillier::zkproofs::{CompositeDLogProof, DLogStatement, NICorrectKeyProof};
+
+use crate::common::error::decode;
+use crate::common::key_share::KeyRecord;
+use crate::common::keygen::{check_dlog_statement, check_paillier_key, check_paillier_modulus, create_dlog_statement};
+use crate::common::p2p::P2pChannel;
+use crate::common::paillier_keys::{blum_paillier_keys, BlumModulusProof};
+use crate::common::signer::{format_vec_from_reads, signup};
+use crate::common::{
+    block_on, broadcast, poll_for_broadcasts, poll_for_broadcasts_from, poll_for_p2p, poll_for_p2p_from, sendp2p,
+    ClientConfig, Error, KeyShare, Params, PartySignup, Protocol, SignerPolicy, Transport,
+};
+
+/// New public keys of a recovered party, which the other parties write in their keys files.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ShareUpdate {
+    pub party_id: u16,
+    pub ek: EncryptionKey,
+    pub correct_key_proof: NICorrectKeyProof,
+    pub modulus_proof: BlumModulusProof,
+    pub dlog_statement: DLogStatement,
+    pub composite_dlog_proof: CompositeDLogProof,
+}
+
+impl ShareUpdate {
+
+    /// Checks the new keys against their proofs and writes them in the keys file of another party.
+    pub fn apply(&self, key_share: &mut KeyShare) -> Result<(), String> {
+        let (_threshold, parties) = key_share.params().values()?;
+        if self.party_id == 0 || self.party_id > parties {
+            return Err(format!("Party id {} of the update is not between 1 and {}", self.party_id, parties));
+        }
+        if self.party_id == key_share.party_id {
+            return Err("Update is for the party of this keys file, which keeps its own keys".to_string());
+        }
+        check_paillier_key(&self.ek, &self.correct_key_proof)
+            .and_then(|_| check_paillier_modulus(&self.ek, &self.modulus_proof))
+            .and_then(|_| check_dlog_statement(&self.dlog_statement, &self.composite_dlog_proof))
+            .map_err(|e| format!("Party {} sent {} in its update", self.party_id, e))?;
+        write_keys(key_share, self);
+        Ok(())
+    }
+}
+
+/// Round 1 message of a recovery session. Helpers send the public part of their keys file,
+/// the recovered party its new keys.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecoveryMessage1 {
+    pub party_id: u16,
+    pub p2p_key: GE, // Ephemeral Diffie-Hellman key of the session's p2p channel
+    pub record: Option<KeyRecord>,
+    pub update: Option<ShareUpdate>,
+}
+
+pub fn help_recover(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    party_id: u16,
+    device_key: &GE,
+    room_id: Option<String>,
+) -> Result<KeyShare, Error> {
+    block_on(help_recover_async(transport, config, key_share, party_id, device_key, room_id))
+}
+
+/// Helper side of a recovery: with threshold other helpers, rebuilds the share of party `party_id`
+/// for its new device. Each helper splits lambda_j * x_j into random parts, one per helper, and
+/// only sends the sum of the parts it received, so no helper and not the new device learns x_j.
+/// `device_key` is the p2p key the new device logged, checked out of band, a device joining with another
+/// key aborts the session before any part of the share is sent. Returns the keys file with the new keys
+/// of the recovered party.
+#[instrument(skip(transport, config, key_share, device_key, room_id), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn help_recover_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    party_id: u16,
+    device_key: &GE,
+    room_id: Option<String>,
+) -> Result<KeyShare, Error> {
+    key_share.validate().map_err(Error::InvalidInput)?;
+    let (threshold, parties) = key_share.params().values().map_err(Error::InvalidInput)?;
+    if party_id == 0 || party_id > parties {
+        return Err(Error::InvalidInput(format!("Party id {} is not between 1 and {}", party_id, parties)));
+    }
+    if party_id == key_share.party_id {
+        return Err(Error::InvalidInput(format!(
+            "This keys file holds the share of party {}, it needs no recovery", party_id
+        )));
+    }
+    let room_id = room_id.unwrap_or(default_room(party_id));
+    Span::current().record("room_id", &room_id.as_str());
+
+    let p2p_secret: FE = ECScalar::new_random();
+    let record = key_share.record();
+    let message1 = RecoveryMessage1 {
+        party_id: key_share.party_id,
+        p2p_key: GE::generator() * &p2p_secret,
+        record: Some(record.clone()),
+        update: None,
+    };
+    let (party_num_int, uuid, round1_vec) = join(transport, config, threshold, room_id, message1).await?;
+    let record_json = serde_json::to_string(&record).unwrap();
+
+    // Find the new device, the others must help with the same key
+    let is_helper = |id: u16| id != party_id && id != 0 && id <= parties;
+    let mut new_device: Option<(u16, ShareUpdate)> = None;
+    let mut helpers: Vec<(u16, u16)> = Vec::new(); // (room number, party id)
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    for (i, message_j) in (1..=round1_vec.len() as u16).zip(round1_vec) {
+        match (&message_j.record, message_j.update) {
+            (None, Some(update)) if message_j.party_id == party_id && update.party_id == party_id => {
+                if new_device.is_some() {
+                    return Err(Error::Protocol(format!("party {} joined twice to be recovered", party_id)));
+                }
+                if message_j.p2p_key != *device_key {
+                    return Err(Error::Protocol(format!(
+                        "party {} joined with key {}, not the device key given",
+                        party_id,
+                        hex::encode(message_j.p2p_key.get_element().serialize())
+                    )));
+                }
+                info!("Recovering device joined with the device key given");
+                shared_secrets.insert(i, vec![message_j.p2p_key * &p2p_secret]);
+                new_device = Some((i, update));
+            }
+            (Some(record_j), None) if is_helper(message_j.party_id) => {
+                if serde_json::to_string(record_j).unwrap() != record_json {
+                    return Err(Error::Protocol(format!("party {} helps with another key", message_j.party_id)));
+                }
+                if helpers.iter().any(|(_, id)| *id == message_j.party_id) {
+                    return Err(Error::Protocol(format!("party {} joined twice", message_j.party_id)));
+                }
+                if i != party_num_int {
+                    // Helpers hold their shares before and after, which authenticates their channel
+                    let X_j = key_share.public_share(message_j.party_id);
+                    shared_secrets.insert(i, vec![X_j * &key_share.shared_keys.x_i, message_j.p2p_key * &p2p_secret]);
+                }
+                helpers.push((i, message_j.party_id));
+            }
+            _ => return Err(Error::Protocol(format!("party {} sent an invalid recovery message", message_j.party_id))),
+        }
+    }
+    let (new_num, update) = new_device
+        .ok_or_else(|| Error::Protocol(format!("party {} did not join to be recovered", party_id)))?;
+    // Checked before any part of the share leaves
+    let mut updated = key_share.clone();
+    update.apply(&mut updated).map_err(Error::Protocol)?;
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+
+    // round 2: send a random part of lambda_j * x_j to every other helper, keep the rest
+    let helper_ids = helpers.iter().map(|(_, id)| *id).collect::<Vec<u16>>();
+    let delta_i = lagrange_at(party_id, key_share.party_id, &helper_ids) * key_share.shared_keys.x_i;
+    let mut sigma_i = delta_i;
+    let others = helpers.iter().map(|(i, _)| *i).filter(|i| *i != party_num_int).collect::<Vec<u16>>();
+    for i in others.iter() {
+        let part: FE = ECScalar::new_random();
+        sigma_i = sigma_i.sub(&part.get_element());
+        sendp2p(
+            transport,
+            party_num_int,
+            *i,
+            "round2",
+            channel.seal(*i, "round2", &serde_json::to_string(&part).unwrap()),
+            uuid.clone(),
+        )
+        .await?;
+    }
+    let round2_ans_vec = poll_for_p2p_from(transport, party_num_int, &others, config, "round2", uuid.clone()).await?;
+    for (i, message) in others.iter().zip(round2_ans_vec.iter()) {
+        let part = channel.open(*i, "round2", message).map_err(Error::Protocol)?;
+        sigma_i = sigma_i + decode::<FE>("round2", &part)?;
+    }
+
+    // round 3: send the sum of the parts to the new device
+    sendp2p(
+        transport,
+        party_num_int,
+        new_num,
+        "round3",
+        channel.seal(new_num, "round3", &serde_json::to_string(&sigma_i).unwrap()),
+        uuid.clone(),
+    )
+    .await?;
+
+    // round 4: the new device confirms its share against the VSS commitments
+    let round4_ans_vec = poll_for_broadcasts_from(transport, &[new_num], config, "round4", uuid.clone()).await?;
+    let public_share: GE = decode("round4", &round4_ans_vec[0])?;
+    if public_share != key_share.public_share(party_id) {
+        return Err(Error::Protocol(format!("party {} did not recover its share, keys file left unchanged", party_id)));
+    }
+    info!("Share of party {} recovered", party_id);
+    Ok(updated)
+}
+
+pub fn recover(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    params: &Params,
+    party_id: u16,
+    room_id: Option<String>,
+) -> Result<(KeyShare, ShareUpdate), Error> {
+    block_on(recover_async(transport, config, params, party_id, room_id))
+}
+
+/// New device side of a recovery: gets the share of party `party_id` back from threshold + 1 helpers,
+/// with new Paillier keys and h1, h2, N~. Returns the keys file and the update that the parties which
+/// did not help write in theirs.
+#[instrument(skip(transport, config, params, room_id), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn recover_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    params: &Params,
+    party_id: u16,
+    room_id: Option<String>,
+) -> Result<(KeyShare, ShareUpdate), Error> {
+    let (threshold, parties) = params.values().map_err(Error::InvalidInput)?;
+    if party_id == 0 || party_id > parties {
+        return Err(Error::InvalidInput(format!("Party id {} is not between 1 and {}", party_id, parties)));
+    }
+    let room_id = room_id.unwrap_or(default_room(party_id));
+    Span::current().record("room_id", &room_id.as_str());
+
+    // Like at keygen, the new Paillier modulus is a Blum integer and proven to be one
+    let mut party_keys = Keys::create(party_id as usize);
+    let (ek, dk) = blum_paillier_keys();
+    party_keys.ek = ek;
+    party_keys.dk = dk;
+    let (dlog_statement, composite_dlog_proof) = create_dlog_statement();
+    let update = ShareUpdate {
+        party_id,
+        ek: party_keys.ek.clone(),
+        correct_key_proof: NICorrectKeyProof::proof(&party_keys.dk, None),
+        modulus_proof: BlumModulusProof::prove(&party_keys.dk),
+        dlog_statement,
+        composite_dlog_proof,
+    };
+    let p2p_secret: FE = ECScalar::new_random();
+    let p2p_key = GE::generator() * &p2p_secret;
+    info!(p2p_key = hex::encode(p2p_key.get_element().serialize()).as_str(), "Helpers must see this key for the new device");
+    let message1 = RecoveryMessage1 {
+        party_id,
+        p2p_key,
+        record: None,
+        update: Some(update.clone()),
+    };
+    let (party_num_int, uuid, round1_vec) = join(transport, config, threshold, room_id, message1).await?;
+
+    // All others are helpers of the same key
+    let total_parties = round1_vec.len() as u16;
+    let is_helper = |id: u16| id != party_id && id != 0 && id <= parties;
+    let mut record: Option<KeyRecord> = None;
+    let mut helper_ids: Vec<u16> = Vec::new();
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    for (i, message_j) in (1..=round1_vec.len() as u16).zip(round1_vec) {
+        if i == party_num_int {
+            continue;
+        }
+        let record_j = match (message_j.record, &message_j.update) {
+            (Some(record_j), None) if is_helper(message_j.party_id) => record_j,
+            _ => return Err(Error::Protocol(format!("party {} sent an invalid recovery message", message_j.party_id))),
+        };
+        if helper_ids.contains(&message_j.party_id) {
+            return Err(Error::Protocol(format!("party {} joined twice", message_j.party_id)));
+        }
+        match &record {
+            None => record = Some(record_j),
+            Some(record) if serde_json::to_string(record).unwrap() != serde_json::to_string(&record_j).unwrap() => {
+                return Err(Error::Protocol("helpers hold different keys".to_string()));
+            }
+            Some(_) => {}
+        }
+        helper_ids.push(message_j.party_id);
+        shared_secrets.insert(i, vec![message_j.p2p_key * &p2p_secret]);
+    }
+    let record = record.ok_or_else(|| Error::Protocol("no helper joined".to_string()))?;
+    let expected = (threshold as usize, parties as usize);
+    let parameters = record.vss_scheme_vec.first()
+        .map(|vss_scheme| (vss_scheme.parameters.threshold, vss_scheme.parameters.share_count));
+    if parameters != Some(expected) {
+        return Err(Error::Protocol(format!("helpers hold a key of other threshold params than {}/{}", threshold, parties)));
+    }
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+
+    // round 3: the share is the sum of what the helpers send
+    let round3_ans_vec = poll_for_p2p(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round3",
+        uuid.clone(),
+    )
+    .await?;
+    let sigma_vec = channel.open_all("round3", &round3_ans_vec)?.iter()
+        .map(|x| decode::<FE>("round3", x))
+        .collect::<Result<Vec<FE>, _>>()?;
+    let (head, tail) = sigma_vec.split_at(1);
+    let x_i = tail.iter().fold(head[0], |acc, x| acc + x);
+
+    let mut key_share = KeyShare::from_record(record, party_keys, x_i);
+    write_keys(&mut key_share, &update);
+    // The keygen secret u_i is lost with the old keys file, signing does not use it
+    key_share.party_keys.u_i = x_i;
+    key_share.party_keys.y_i = GE::generator() * &x_i;
+    let public_share = key_share.public_share(party_id);
+    if key_share.party_keys.y_i != public_share {
+        return Err(Error::Protocol("the recovered share does not match the VSS commitments, a helper sent a wrong part".to_string()));
+    }
+    key_share.validate().map_err(Error::Protocol)?;
+
+    // round 4: confirm to the helpers, which then write the new keys
+    broadcast(
+        transport,
+        party_num_int,
+        "round4",
+        serde_json::to_string(&public_share).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    info!("Share recovered");
+    Ok((key_share, update))
+}
+
+// Signs up in a room of threshold + 2 parties, the helpers and the new device, and runs round 1.
+// Returns the round 1 messages in room order.
+async fn join(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    threshold: u16,
+    room_id: String,
+    message1: RecoveryMessage1,
+) -> Result<(u16, String, Vec<RecoveryMessage1>), Error> {
+    let (party_num_int, uuid, total_parties) = match signup(
+        transport, config, threshold + 1, room_id, message1.party_id, Protocol::Recovery, &SignerPolicy::default()
+    ).await? {
+        (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
+    };
+    Span::current().record("party", &party_num_int);
+    Span::current().record("room_uuid", &uuid.as_str());
+    info!("Recovery room is ready");
+
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&message1).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+    let mut round1_vec: Vec<RecoveryMessage1> = Vec::new();
+    format_vec_from_reads("round1", &round1_ans_vec, party_num_int as usize, message1, &mut round1_vec)?;
+    Ok((party_num_int, uuid, round1_vec))
+}
+
+pub(crate) fn write_keys(key_share: &mut KeyShare, update: &ShareUpdate) {
+    let index = (update.party_id - 1) as usize;
+    key_share.paillier_key_vector[index] = update.ek.clone();
+    // Keys files without statements skip the range proofs, they stay without
+    if !key_share.dlog_statement_vec.is_empty() {
+        key_share.dlog_statement_vec[index] = update.dlog_statement.clone();
+    }
+}
+
+/// Parses the p2p key a new device logged, in hex.
+pub fn parse_device_key(key: &str) -> Result<GE, String> {
+    hex::decode(key.trim())
+        .ok()
+        .filter(|bytes| bytes.len() == 33)
+        .and_then(|bytes| GE::from_bytes(&bytes).ok())
+        .ok_or_else(|| format!("Device key {:?} is not a compressed secp256k1 point in hex", key))
+}
+
+fn default_room(party_id: u16) -> String {
+    format!("recover-share-{}", party_id)
+}
+
+/// Lagrange coefficient of party `party_id` among `party_ids` for the share at `point`,
+/// i.e. x_point = sum of lambda_j * x_j.
+pub fn lagrange_at(point: u16, party_id: u16, party_ids: &[u16]) -> FE {
+    party_ids.iter()
+        .filter(|k| **k != party_id)
+        .fold(scalar(1), |acc, k| {
+            let numerator = scalar(point).sub(&scalar(*k).get_element());
+            let denominator = scalar(party_id).sub(&scalar(*k).get_element());
+            acc * numerator * denominator.invert()
+        })
+}
+
+fn scalar(value: u16) -> FE {
+    ECScalar::from(&BigInt::from(i32::from(value)))
+}
@@ -0,0 +1,289 @@
+use curv::arithmetic::Converter;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::BigInt;
+use ring::digest::{digest, Context, SHA256};
+use serde_json::json;
+use tracing::{field, instrument, Span};
+
+use crate::common::frost::{nonce_points, public_share, sum_points, NonceCommitments};
+use crate::common::signer::{format_vec_from_reads, join_signing, SignOptions, SigningContext, SigningSession};
+use crate::common::{
+    block_on, broadcast, poll_for_broadcasts, sha256_digest, ClientConfig, Error, KeyShare, Params, Protocol, Transport,
+};
+
+/// BIP340 key the signature verifies against, with how the shared secret maps to it.
+#[derive(Clone, Debug)]
+pub struct TweakedKey {
+    pub internal_key: GE, // Derived key with even y
+    pub output_key: GE, // Internal key with the BIP341 tweak if any, even y
+    negate: bool, // Output secret is minus the shared secret, up to the offset
+    offset: Option<FE>, // Added to the output secret by the tweak
+}
+
+impl TweakedKey {
+
+    /// `taproot` is the merkle root of the script tree, empty for a key path only output, or None for a plain BIP340 key.
+    pub fn new(public_key: &GE, taproot: Option<&[u8]>) -> Result<Self, String> {
+        let internal_negate = is_odd(public_key);
+        let internal_key = negate_if(internal_negate, public_key);
+        let root = match taproot {
+            None => return Ok(TweakedKey { internal_key, output_key: internal_key, negate: internal_negate, offset: None }),
+            Some(root) => root,
+        };
+        let t = tagged_hash("TapTweak", &[&x_only(&internal_key), root]);
+        if t >= FE::q() {
+            return Err("Taproot tweak is not a valid scalar, use another script tree".to_string());
+        }
+        let t: FE = ECScalar::from(&t);
+        let tweaked = internal_key + GE::generator() * &t;
+        let output_negate = is_odd(&tweaked);
+        Ok(TweakedKey {
+            internal_key,
+            output_key: negate_if(output_negate, &tweaked),
+            negate: internal_negate != output_negate,
+            offset: Some(match output_negate {
+                true => negate(&t),
+                false => t,
+            }),
+        })
+    }
+}
+
+/// Result of a Schnorr signing session: the 64 bytes BIP340 signature and the key it verifies against.
+#[derive(Clone, Debug)]
+pub struct SchnorrSignResult {
+    pub signature: Vec<u8>,
+    pub key: TweakedKey,
+    pub message: Vec<u8>,
+}
+
+impl SchnorrSignResult {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "r": hex::encode(&self.signature[..32]),
+            "s": hex::encode(&self.signature[32..]),
+            "signature": hex::encode(&self.signature),
+            "status": "signature_ready",
+            "x_only_pubkey": hex::encode(x_only(&self.key.output_key)),
+            "internal_key": hex::encode(x_only(&self.key.internal_key)),
+            "msg": hex::encode(&self.message),
+        })
+    }
+}
+
+pub fn sign(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    params: &Params,
+    options: &SignOptions,
+    message: &[u8],
+    path: &str,
+) -> Result<SchnorrSignResult, Error> {
+    block_on(sign_async(transport, config, key_share, params, options, message, path))
+}
+
+/// FROST signing with the shares of a GG18 or GG20 key, in two rounds after round 0. The message is
+/// signed as is, BIP340 style, and every signer's partial signature is checked against its share.
+#[instrument(skip(transport, config, key_share, params, options, message), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn sign_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    params: &Params,
+    options: &SignOptions,
+    message: &[u8],
+    path: &str,
+) -> Result<SchnorrSignResult, Error> {
+    key_share.validate().and_then(|_| key_share.check_params(params)).map_err(Error::InvalidInput)?;
+    let (THRESHOLD, PARTIES) = params.values().map_err(Error::InvalidInput)?;
+    let message_hash = sha256_digest(message);
+    // Not the ECDSA room of the same message
+    let room_id = options.room_id.clone().unwrap_or(format!("schnorr-{}", message_hash));
+    Span::current().record("room_id", &room_id.as_str());
+
+    let context = SigningContext {
+        message_hash,
+        path: path.to_string(),
+        public_key: key_share.y_sum,
+        threshold: THRESHOLD,
+        parties: PARTIES,
+        protocol: Protocol::Frost,
+        taproot: options.taproot.as_ref().map(hex::encode),
+        allow_legacy_keys: false,
+    };
+    let session = join_signing(transport, config, key_share, &context, room_id, &options.policy).await?;
+    let key = TweakedKey::new(&session.public_key, options.taproot.as_deref()).map_err(Error::InvalidInput)?;
+    let signature = sign_frost(transport, config, key_share, &session, &key, message).await?;
+
+    Ok(SchnorrSignResult {
+        signature,
+        key,
+        message: message.to_vec(),
+    })
+}
+
+async fn sign_frost(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    session: &SigningSession,
+    key: &TweakedKey,
+    message: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let SigningSession { party_num_int, uuid, total_parties, signers_vec, vss_scheme_vec, .. } = session.clone();
+    let index = (party_num_int - 1) as usize;
+
+    // Shares at a path hold the tweak, like the VSS commitments of the session
+    let x_i = match &session.path_tweak {
+        Some(f_l_new) => key_share.shared_keys.x_i + f_l_new,
+        None => key_share.shared_keys.x_i,
+    };
+
+    // round 1: commit to the nonces
+    let (hiding, binding, commitments) = NonceCommitments::<GE>::create();
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&commitments).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+    let mut commitments_vec: Vec<NonceCommitments<GE>> = Vec::new();
+    format_vec_from_reads("round1", &round1_ans_vec, party_num_int as usize, commitments, &mut commitments_vec)?;
+
+    let mut prefix = x_only(&key.output_key).to_vec();
+    prefix.extend_from_slice(digest(&SHA256, message).as_ref());
+    let nonce_points = nonce_points(
+        prefix,
+        &signers_vec,
+        &commitments_vec,
+        |point| point.get_element().serialize().to_vec(),
+        |binding_input, party_id| {
+            ECScalar::from(&tagged_hash("tss-cli/FROST/rho", &[binding_input, &party_id.to_be_bytes()]))
+        },
+    );
+    let R = sum_points(nonce_points.iter().map(|(_, r_j)| *r_j));
+
+    // BIP340 nonces have an even y, every signer negates its nonce otherwise
+    let negate_nonce = is_odd(&R);
+    let R = negate_if(negate_nonce, &R);
+    let c: FE = ECScalar::from(&tagged_hash(
+        "BIP0340/challenge",
+        &[&x_only(&R), &x_only(&key.output_key), message],
+    ));
+
+    // round 2: send the partial signature, check the others' against their shares
+    let k_i = hiding + binding * nonce_points[index].0;
+    let k_i = if negate_nonce { negate(&k_i) } else { k_i };
+    let factor_i = share_factor(&vss_scheme_vec[signers_vec[index]], &signers_vec, index, key, &c);
+    let z_i = k_i + factor_i * x_i;
+    broadcast(
+        transport,
+        party_num_int,
+        "round2",
+        serde_json::to_string(&z_i).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round2_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round2",
+        uuid.clone(),
+    )
+    .await?;
+    let mut z_vec: Vec<FE> = Vec::new();
+    format_vec_from_reads("round2", &round2_ans_vec, party_num_int as usize, z_i, &mut z_vec)?;
+
+    for (j, z_j) in z_vec.iter().enumerate() {
+        let party_id = signers_vec[j] + 1;
+        let y_j = public_share(&vss_scheme_vec, party_id as u16);
+        let factor_j = share_factor(&vss_scheme_vec[signers_vec[j]], &signers_vec, j, key, &c);
+        let r_j = negate_if(negate_nonce, &nonce_points[j].1);
+        if GE::generator() * z_j != r_j + y_j * &factor_j {
+            return Err(Error::Protocol(format!("party {} sent an invalid partial signature", party_id)));
+        }
+    }
+
+    let (head, tail) = z_vec.split_at(1);
+    let mut z = tail.iter().fold(head[0], |acc, z_j| acc + z_j);
+    if let Some(offset) = &key.offset {
+        z = z + c * offset;
+    }
+    if GE::generator() * &z != R + key.output_key * &c {
+        return Err(Error::Protocol("the Schnorr signature does not verify".to_string()));
+    }
+
+    let mut signature = x_only(&R).to_vec();
+    signature.extend_from_slice(&scalar_bytes(&z));
+    Ok(signature)
+}
+
+// c * lambda_j, negated with the key, so that z_j = k_j + factor_j * x_j
+fn share_factor(
+    vss_scheme: &VerifiableSS<GE>,
+    signers_vec: &[usize],
+    position: usize,
+    key: &TweakedKey,
+    c: &FE,
+) -> FE {
+    let lambda = vss_scheme.map_share_to_new_params(signers_vec[position], signers_vec);
+    let factor = lambda * c;
+    if key.negate { negate(&factor) } else { factor }
+}
+
+/// x coordinate of a point, the 32 bytes of a BIP340 public key.
+pub fn x_only(point: &GE) -> [u8; 32] {
+    let mut x = [0u8; 32];
+    x.copy_from_slice(&point.get_element().serialize()[1..33]);
+    x
+}
+
+fn is_odd(point: &GE) -> bool {
+    point.get_element().serialize()[0] == 0x03
+}
+
+fn negate(scalar: &FE) -> FE {
+    ECScalar::from(&(FE::q() - scalar.to_big_int()))
+}
+
+fn negate_if(condition: bool, point: &GE) -> GE {
+    match condition {
+        true => *point * &negate(&ECScalar::from(&BigInt::from(1))),
+        false => *point,
+    }
+}
+
+pub(crate) fn scalar_bytes(scalar: &FE) -> [u8; 32] {
+    let bytes = BigInt::to_bytes(&scalar.to_big_int());
+    let mut padded = [0u8; 32];
+    padded[32 - bytes.len()..].copy_from_slice(&bytes);
+    padded
+}
+
+// BIP340 tagged hash: SHA256(SHA256(tag) || SHA256(tag) || data)
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> BigInt {
+    let tag_hash = digest(&SHA256, tag.as_bytes());
+    let mut context = Context::new(&SHA256);
+    context.update(tag_hash.as_ref());
+    context.update(tag_hash.as_ref());
+    for part in data {
+        context.update(part);
+    }
+    BigInt::from_bytes(context.finish().as_ref())
+}
@@ -5,8 +5,9 @@ extern crate paillier;
 extern crate reqwest;
 extern crate serde_json;
 
-use std::{thread, time};
-use std::time::Duration;
+use std::collections::HashMap;
+use std::fmt;
+use std::time;
 
 use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
 use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
@@ -19,12 +20,16 @@ use curv::{
 };
 use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::*;
 use multi_party_ecdsa::utilities::mta::*;
+use multi_party_ecdsa::utilities::mta::range_proofs::BobProof;
 use paillier::*;
-use reqwest::blocking::Client;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::json;
+use tracing::{field, info, instrument, warn, Span};
+use zk_paillier::zkproofs::DLogStatement;
 
-use crate::common::{broadcast, poll_for_broadcasts, poll_for_p2p, sendp2p, Params, PartySignup, PartySignupRequestBody, sha256_digest, SigningPartySignup, ManagerError};
+use crate::common::error::decode;
+use crate::common::p2p::P2pChannel;
+use crate::common::{block_on, broadcast, ClientConfig, Error, manager_answer, poll_for_broadcasts, poll_for_p2p, postb, sendp2p, signer_gg20, KeyShare, Params, PartySignup, PartySignupRequestBody, Protocol, sha256_digest, SignerPolicy, SigningPartySignup, ManagerError, Transport};
 
 #[derive(Hash, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
 pub struct TupleKey {
@@ -34,104 +39,399 @@ pub struct TupleKey {
     pub fourth: String,
 }
 
+/// Optional settings of a signing session.
+#[derive(Clone, Debug, Default)]
+pub struct SignOptions {
+    pub room_id: Option<String>, // Signing room shared by the parties, defaults to the hash of the message
+    pub policy: SignerPolicy,
+    pub taproot: Option<Vec<u8>>, // Schnorr only: BIP341 script tree merkle root, empty for a key path only output
+    pub allow_legacy_keys: bool, // GG18 only: sign with a keys file without h1, h2, N~, i.e. without range proofs
+}
+
+/// What a party agrees to sign. All signers must hold the same context.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SigningContext<P = GE> {
+    pub message_hash: String,
+    pub path: String,
+    pub public_key: P, // Root public key of the keys file
+    pub threshold: u16,
+    pub parties: u16,
+    pub protocol: Protocol,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taproot: Option<String>, // Hex of SignOptions::taproot
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub allow_legacy_keys: bool, // Set for keys files without h1, h2, N~, so that every signer has to allow them
+}
+
+impl<P: Serialize> SigningContext<P> {
+    pub fn commitment(&self) -> String {
+        sha256_digest(serde_json::to_string(self).unwrap().as_bytes())
+    }
+}
+
+/// First broadcast of a signing session, lets every party check that they all sign in the same context.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Round0Message {
+    pub party_id: u16,
+    pub commitment: String, // SigningContext::commitment
+    #[serde(default)]
+    pub p2p_key: Option<GE>, // Ephemeral Diffie-Hellman key of the session's p2p channel, if the protocol has one
+}
+
+/// A party's view of the signing room once round 0 agreed on the signers.
+#[derive(Clone, Debug)]
+pub struct SigningSession {
+    pub party_num_int: u16,
+    pub uuid: String,
+    pub total_parties: u16,
+    pub signers_vec: Vec<usize>, // Party ids (0-based) of the signers, in the order of their room numbers
+    pub vss_scheme_vec: Vec<VerifiableSS<GE>>, // Moved to the derived key when signing at a path
+    pub path_tweak: Option<FE>, // Added to the shares when signing at a path
+    pub public_key: GE, // Key the signature verifies against, root or derived
+    pub channel: P2pChannel,
+}
+
+/// P2P message of the MtA: the answers to a signer's encrypted k_i for gamma_i and w_i, with range
+/// proofs made against the recipient's h1, h2, N~. Keys files written before the range proofs have none.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MtaAnswers {
+    pub m_b_gamma: MessageB,
+    pub m_b_w: MessageB,
+    #[serde(default)]
+    pub range_proofs: Option<(BobProof, BobProof)>,
+}
+
+impl MtaAnswers {
+
+    /// Answers the MessageA of the signer at position `alice` once its range proofs check out.
+    /// Also returns beta and ni, our shares of k_alice * gamma_i and k_alice * w_i.
+    pub fn create(
+        key_share: &KeyShare,
+        signers_vec: &[usize],
+        alice: usize,
+        m_a: &MessageA,
+        gamma_i: &FE,
+        w_i: &FE,
+    ) -> Result<(Self, FE, FE), String> {
+        let alice_ek = &key_share.paillier_key_vector[signers_vec[alice]];
+        let statements = range_statements(key_share, signers_vec, alice);
+        let invalid = |e| format!("Party {} sent invalid range proofs: {:?}", signers_vec[alice] + 1, e);
+        let (m_b_gamma, beta, r_gamma, beta_tag_gamma) = MessageB::b(gamma_i, alice_ek, m_a.clone(), &statements)
+            .map_err(invalid)?;
+        let (m_b_w, ni, r_w, beta_tag_w) = MessageB::b(w_i, alice_ek, m_a.clone(), &statements)
+            .map_err(invalid)?;
+        let range_proofs = key_share.dlog_statement_vec.get(signers_vec[alice]).map(|alice_statement| (
+            BobProof::generate(&m_a.c, &m_b_gamma.c, gamma_i, &beta_tag_gamma, alice_ek, &Randomness(r_gamma), alice_statement),
+            BobProof::generate(&m_a.c, &m_b_w.c, w_i, &beta_tag_w, alice_ek, &Randomness(r_w), alice_statement),
+        ));
+        Ok((MtaAnswers { m_b_gamma, m_b_w, range_proofs }, beta, ni))
+    }
+
+    /// Checks the answers of the signer at position `bob` to our MessageA `m_a_k`, including their range
+    /// proofs against our h1, h2, N~, and returns alpha and miu.
+    pub fn verify_get_alphas(
+        &self,
+        key_share: &KeyShare,
+        signers_vec: &[usize],
+        own: usize,
+        bob: usize,
+        m_a_k: &MessageA,
+        k_i: &FE,
+    ) -> Result<(FE, FE), String> {
+        let bob_id = signers_vec[bob] + 1;
+        if let Some(own_statement) = key_share.dlog_statement_vec.get(signers_vec[own]) {
+            let (proof_gamma, proof_w) = self.range_proofs.as_ref()
+                .ok_or(format!("Party {} sent no range proofs", bob_id))?;
+            let ek = &key_share.party_keys.ek;
+            if !proof_gamma.verify(&m_a_k.c, &self.m_b_gamma.c, ek, own_statement)
+                || !proof_w.verify(&m_a_k.c, &self.m_b_w.c, ek, own_statement) {
+                return Err(format!("Party {} sent invalid range proofs", bob_id));
+            }
+        }
+        let invalid = |e| format!("Party {} sent an invalid MtA answer: {:?}", bob_id, e);
+        let alpha = self.m_b_gamma.verify_proofs_get_alpha(&key_share.party_keys.dk, k_i).map_err(invalid)?;
+        let miu = self.m_b_w.verify_proofs_get_alpha(&key_share.party_keys.dk, k_i).map_err(invalid)?;
+        Ok((alpha.0, miu.0))
+    }
+}
+
+/// h1, h2, N~ of the signers other than the one at `position`, which its MessageA range proofs are made
+/// against. Empty for keys files written before the range proofs.
+pub fn range_statements(key_share: &KeyShare, signers_vec: &[usize], position: usize) -> Vec<DLogStatement> {
+    signers_vec.iter()
+        .enumerate()
+        .filter(|(j, _)| *j != position)
+        .filter_map(|(_, id)| key_share.dlog_statement_vec.get(*id).cloned())
+        .collect()
+}
+
+/// Result of a signing session: the signature and the (possibly derived) public key it verifies against.
+#[derive(Clone, Debug)]
+pub struct SignResult {
+    pub signature: SignatureRecid,
+    pub public_key: GE,
+    pub message: BigInt,
+}
+
+impl SignResult {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "r": (BigInt::from_bytes(&(self.signature.r.get_element())[..])).to_str_radix(16),
+            "s": (BigInt::from_bytes(&(self.signature.s.get_element())[..])).to_str_radix(16),
+            "status": "signature_ready",
+            "recid": self.signature.recid.clone(),
+            "x": &self.public_key.x_coor(),
+            "y": &self.public_key.y_coor(),
+            "msg_int": self.message,
+        })
+    }
+}
+
 pub fn sign(
-    addr: String,
-    party_keys: Keys,
-    shared_keys: SharedKeys,
-    party_id: u16,
-    vss_scheme_vec: &mut Vec<VerifiableSS<GE>>,
-    paillier_key_vector: Vec<EncryptionKey>,
-    y_sum: &GE,
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
     params: &Params,
+    options: &SignOptions,
     message: &[u8],
-    f_l_new: &FE,
-    sign_at_path: bool,
-) {
-    let client = Client::new();
-    let delay = time::Duration::from_millis(25);
-    let THRESHOLD = params.threshold.parse::<u16>().unwrap();
-    let room_id = sha256_digest(message);
+    path: &str,
+) -> Result<SignResult, Error> {
+    block_on(sign_async(transport, config, key_share, params, options, message, path))
+}
+
+#[instrument(skip(transport, config, key_share, params, options, message), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn sign_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    params: &Params,
+    options: &SignOptions,
+    message: &[u8],
+    path: &str,
+) -> Result<SignResult, Error> {
+    key_share.validate().and_then(|_| key_share.check_params(params)).map_err(Error::InvalidInput)?;
+    if options.taproot.is_some() {
+        return Err(Error::InvalidInput("Taproot tweaks only apply to Schnorr signatures".to_string()));
+    }
+    let legacy_keys = key_share.dlog_statement_vec.is_empty();
+    if legacy_keys && !options.allow_legacy_keys {
+        return Err(Error::InvalidInput(
+            "Keys file has no h1, h2, N~, signing would run the MtA without range proofs. Run keygen again, \
+             or have every signer allow it with --allow-legacy-keys".to_string()
+        ));
+    }
+    let (THRESHOLD, PARTIES) = params.values().map_err(Error::InvalidInput)?;
+    let message_hash = sha256_digest(message);
+    let room_id = options.room_id.clone().unwrap_or(message_hash.clone());
+    Span::current().record("room_id", &room_id.as_str());
+
+    let context = SigningContext {
+        message_hash,
+        path: path.to_string(),
+        public_key: key_share.y_sum,
+        threshold: THRESHOLD,
+        parties: PARTIES,
+        protocol: key_share.protocol,
+        taproot: None,
+        allow_legacy_keys: legacy_keys,
+    };
+    let session = join_signing(transport, config, key_share, &context, room_id, &options.policy).await?;
+
+    // we assume the message is already hashed (by the signer).
+    let message_int = BigInt::from_bytes(message);
+    let two = BigInt::from(2);
+    let message_bn = message_int.modulus(&two.pow(256));
+
+    let sig = match key_share.protocol {
+        Protocol::Gg18 => sign_gg18(transport, config, key_share, &session, &message_bn).await?,
+        Protocol::Gg20 => signer_gg20::sign_gg20(transport, config, key_share, &session, &message_bn).await?,
+        Protocol::Lindell17 | Protocol::Frost | Protocol::FrostEd25519 | Protocol::Recovery => unreachable!(), // Refused by KeyShare::validate
+    };
+    verify(&sig, &session.public_key, &message_bn)
+        .map_err(|_| Error::Protocol("the signature does not verify against the public key".to_string()))?;
+
+    Ok(SignResult {
+        signature: sig,
+        public_key: session.public_key,
+        message: message_int,
+    })
+}
+
+/// Signs up to the signing room and runs round 0, then prepares the shares for the path.
+pub async fn join_signing(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    context: &SigningContext,
+    room_id: String,
+    policy: &SignerPolicy,
+) -> Result<SigningSession, Error> {
+    let party_id = key_share.party_id;
+    // Get root pub key or HD pub key at specified path
+    let (f_l_new, public_key) = key_share.derive(&context.path).map_err(Error::InvalidInput)?;
 
     // Signup
-    let (party_num_int, uuid, total_parties) = match signup(&addr, &client, THRESHOLD, room_id, party_id).unwrap() {
+    let (party_num_int, uuid, total_parties) = match signup(
+        transport, config, context.threshold, room_id, party_id, context.protocol, policy
+    ).await? {
         (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
     };
 
-    let debug = json!({"manager_addr": &addr, "party_num": party_num_int, "uuid": uuid});
-    println!("{}", serde_json::to_string_pretty(&debug).unwrap());
+    Span::current().record("party", &party_num_int);
+    Span::current().record("room_uuid", &uuid.as_str());
+    info!("Signing room is ready");
+
+    // round 0: collect signers IDs and check that all signers sign the same message with the same key
+    let p2p_secret: FE = ECScalar::new_random();
+    let round0_message = Round0Message {
+        party_id,
+        commitment: context.commitment(),
+        p2p_key: Some(GE::generator() * &p2p_secret),
+    };
+    let round0_vec = round0(transport, config, party_num_int, total_parties, &uuid, round0_message, context).await?;
 
-    // round 0: collect signers IDs
-    assert!(broadcast(
-        &addr,
-        &client,
+    let mut signers_vec: Vec<usize> = Vec::new();
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    for (i, signer_j) in (1..=total_parties).zip(round0_vec) {
+        signers_vec.push((signer_j.party_id - 1) as usize);
+        if i != party_num_int {
+            let p2p_key_j = signer_j.p2p_key
+                .ok_or_else(|| Error::Protocol(format!("party {} sent no p2p key", signer_j.party_id)))?;
+            // The public share x_j * G of the party authenticates the channel, the ephemeral key makes it
+            // specific to this session. Shares survive a recovery, unlike the keygen secret u_i.
+            let X_j = key_share.public_share(signer_j.party_id);
+            shared_secrets.insert(i, vec![X_j * &key_share.shared_keys.x_i, p2p_key_j * &p2p_secret]);
+        }
+    }
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+
+    let mut vss_scheme_vec = key_share.vss_scheme_vec.clone();
+    let path_tweak = match context.path.is_empty() {
+        true => None,
+        false => {
+            tweak_vss_scheme_vec(&mut vss_scheme_vec, &f_l_new);
+            Some(f_l_new)
+        }
+    };
+
+    Ok(SigningSession {
+        party_num_int,
+        uuid,
+        total_parties,
+        signers_vec,
+        vss_scheme_vec,
+        path_tweak,
+        public_key,
+        channel,
+    })
+}
+
+/// Round 0 of every signing protocol: each party broadcasts its id and commitment to the signing context,
+/// and aborts if another one signs in a different context or with an id outside the key. Returns the
+/// messages of all parties, in the order of their room numbers.
+pub async fn round0<P: Serialize + fmt::Debug>(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    party_num_int: u16,
+    total_parties: u16,
+    uuid: &str,
+    round0_message: Round0Message,
+    context: &SigningContext<P>,
+) -> Result<Vec<Round0Message>, Error> {
+    broadcast(
+        transport,
         party_num_int,
         "round0",
-        serde_json::to_string(&party_id).unwrap(),
-        uuid.clone(),
+        serde_json::to_string(&round0_message).unwrap(),
+        uuid.to_string(),
     )
-    .is_ok());
+    .await?;
 
     let round0_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         total_parties,
-        delay,
+        config,
         "round0",
-        uuid.clone(),
-    );
-    let mut j = 0;
-    let mut signers_vec: Vec<usize> = Vec::new();
-    for i in 1..=total_parties {
-        if i == party_num_int {
-            signers_vec.push((party_id - 1) as usize);
-        } else {
-            let signer_j: u16 = serde_json::from_str(&round0_ans_vec[j]).unwrap();
-            signers_vec.push((signer_j - 1) as usize);
-            j = j + 1;
+        uuid.to_string(),
+    )
+    .await?;
+    let mut round0_vec: Vec<Round0Message> = Vec::new();
+    format_vec_from_reads("round0", &round0_ans_vec, party_num_int as usize, round0_message.clone(), &mut round0_vec)?;
+    for (j, signer_j) in round0_vec.iter().enumerate() {
+        if signer_j.commitment != round0_message.commitment {
+            return Err(Error::Protocol(format!(
+                "party {} signs a different message, path, public key or threshold than ours ({:?})",
+                signer_j.party_id, context
+            )));
+        }
+        if signer_j.party_id == 0 || signer_j.party_id > context.parties {
+            return Err(Error::Protocol(format!("party {} is not a party of this key", signer_j.party_id)));
+        }
+        // Each signer's Lagrange coefficient needs the ids to be distinct
+        if round0_vec[..j].iter().any(|x| x.party_id == signer_j.party_id) {
+            return Err(Error::Protocol(format!("two signers of the room hold share {}", signer_j.party_id)));
         }
     }
+    Ok(round0_vec)
+}
 
-    if sign_at_path == true {
-        // optimize!
-        let g: GE = ECPoint::generator();
-        // apply on first commitment for leader (leader is party with num=1)
-        let com_zero_new = vss_scheme_vec[0].commitments[0] + g * f_l_new;
-        // println!("old zero: {:?}, new zero: {:?}", vss_scheme_vec[0].commitments[0], com_zero_new);
-        // get iterator of all commitments and skip first zero commitment
-        let mut com_iter_unchanged = vss_scheme_vec[0].commitments.iter();
-        com_iter_unchanged.next().unwrap();
-        // iterate commitments and inject changed commitments in the beginning then aggregate into vector
-        let com_vec_new = (0..vss_scheme_vec[1].commitments.len())
-            .map(|i| {
-                if i == 0 {
-                    com_zero_new
-                } else {
-                    com_iter_unchanged.next().unwrap().clone()
-                }
-            })
-            .collect::<Vec<GE>>();
-        let new_vss = VerifiableSS {
-            parameters: vss_scheme_vec[0].parameters.clone(),
-            commitments: com_vec_new,
-        };
-        // replace old vss_scheme for leader with new one at position 0
-        //    println!("comparing vectors: \n{:?} \nand \n{:?}", vss_scheme_vec[0], new_vss);
-
-        vss_scheme_vec.remove(0);
-        vss_scheme_vec.insert(0, new_vss);
-        //    println!("NEW VSS VECTOR: {:?}", vss_scheme_vec);
+/// Moves the commitments of the VSS schemes to the derived key, by adding the tweak to the
+/// constant term of the first scheme. The shares get the same tweak.
+fn tweak_vss_scheme_vec(vss_scheme_vec: &mut Vec<VerifiableSS<GE>>, f_l_new: &FE) {
+    // optimize!
+    let g: GE = ECPoint::generator();
+    // apply on first commitment for leader (leader is party with num=1)
+    let com_zero_new = vss_scheme_vec[0].commitments[0] + g * f_l_new;
+    // println!("old zero: {:?}, new zero: {:?}", vss_scheme_vec[0].commitments[0], com_zero_new);
+    // get iterator of all commitments and skip first zero commitment
+    let mut com_iter_unchanged = vss_scheme_vec[0].commitments.iter();
+    com_iter_unchanged.next().unwrap();
+    // iterate commitments and inject changed commitments in the beginning then aggregate into vector
+    let com_vec_new = (0..vss_scheme_vec[1].commitments.len())
+        .map(|i| {
+            if i == 0 {
+                com_zero_new
+            } else {
+                com_iter_unchanged.next().unwrap().clone()
+            }
+        })
+        .collect::<Vec<GE>>();
+    let new_vss = VerifiableSS {
+        parameters: vss_scheme_vec[0].parameters.clone(),
+        commitments: com_vec_new,
+    };
+    // replace old vss_scheme for leader with new one at position 0
+    //    println!("comparing vectors: \n{:?} \nand \n{:?}", vss_scheme_vec[0], new_vss);
+
+    vss_scheme_vec.remove(0);
+    vss_scheme_vec.insert(0, new_vss);
+    //    println!("NEW VSS VECTOR: {:?}", vss_scheme_vec);
+}
+
+async fn sign_gg18(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    session: &SigningSession,
+    message_bn: &BigInt,
+) -> Result<SignatureRecid, Error> {
+    let party_keys = &key_share.party_keys;
+    let SigningSession { party_num_int, uuid, total_parties, signers_vec, vss_scheme_vec, .. } = session.clone();
+    let index = (party_num_int - 1) as usize;
+    if key_share.dlog_statement_vec.is_empty() {
+        warn!("Keys file has no h1, h2, N~, the MtA runs without range proofs as allowed. Run keygen again to get them");
     }
 
-    let mut private = PartyPrivate::set_private(party_keys.clone(), shared_keys);
+    let mut private = PartyPrivate::set_private(party_keys.clone(), key_share.shared_keys.clone());
 
-    if sign_at_path == true {
+    if let Some(f_l_new) = &session.path_tweak {
         if party_num_int == 1 {
             // update u_i and x_i for leader
-            private = private.update_private_key(&f_l_new, &f_l_new);
+            private = private.update_private_key(f_l_new, f_l_new);
         } else {
             // only update x_i for non-leaders
-            private = private.update_private_key(&FE::zero(), &f_l_new);
+            private = private.update_private_key(&FE::zero(), f_l_new);
         }
     }
 
@@ -144,25 +444,24 @@ pub fn sign(
 
     //////////////////////////////////////////////////////////////////////////////
     let (com, decommit) = sign_keys.phase1_broadcast();
-    let (m_a_k, _) = MessageA::a(&sign_keys.k_i, &party_keys.ek, &[]);
-    assert!(broadcast(
-        &addr,
-        &client,
+    let (m_a_k, _) = MessageA::a(&sign_keys.k_i, &party_keys.ek, &range_statements(key_share, &signers_vec, index));
+    broadcast(
+        transport,
         party_num_int,
         "round1",
         serde_json::to_string(&(com.clone(), m_a_k.clone())).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round1_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         total_parties,
-        delay,
+        config,
         "round1",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut j = 0;
     let mut bc1_vec: Vec<SignBroadcastPhase1> = Vec::new();
@@ -175,7 +474,7 @@ pub fn sign(
         } else {
             //     if signers_vec.contains(&(i as usize)) {
             let (bc1_j, m_a_party_j): (SignBroadcastPhase1, MessageA) =
-                serde_json::from_str(&round1_ans_vec[j]).unwrap();
+                decode("round1", &round1_ans_vec[j])?;
             bc1_vec.push(bc1_j);
             m_a_vec.push(m_a_party_j);
 
@@ -183,32 +482,27 @@ pub fn sign(
             //       }
         }
     }
-    assert_eq!(signers_vec.len(), bc1_vec.len());
+    if signers_vec.len() != bc1_vec.len() {
+        return Err(Error::Protocol(format!("{} signers but {} round 1 messages", signers_vec.len(), bc1_vec.len())));
+    }
 
     //////////////////////////////////////////////////////////////////////////////
-    let mut m_b_gamma_send_vec: Vec<MessageB> = Vec::new();
+    let mut mta_answers_send_vec: Vec<MtaAnswers> = Vec::new();
     let mut beta_vec: Vec<FE> = Vec::new();
-    let mut m_b_w_send_vec: Vec<MessageB> = Vec::new();
     let mut ni_vec: Vec<FE> = Vec::new();
     let mut j = 0;
     for i in 1..total_parties + 1 {
         if i != party_num_int {
-            let (m_b_gamma, beta_gamma, _, _) = MessageB::b(
+            let (mta_answers, beta_gamma, beta_wi) = MtaAnswers::create(
+                key_share,
+                &signers_vec,
+                (i - 1) as usize,
+                &m_a_vec[j],
                 &sign_keys.gamma_i,
-                &paillier_key_vector[signers_vec[(i - 1) as usize]],
-                m_a_vec[j].clone(),
-                &[]
-            )
-            .unwrap();
-            let (m_b_w, beta_wi, _, _) = MessageB::b(
                 &sign_keys.w_i,
-                &paillier_key_vector[signers_vec[(i - 1) as usize]],
-                m_a_vec[j].clone(),
-                &[]
             )
-            .unwrap();
-            m_b_gamma_send_vec.push(m_b_gamma);
-            m_b_w_send_vec.push(m_b_w);
+            .map_err(Error::Protocol)?;
+            mta_answers_send_vec.push(mta_answers);
             beta_vec.push(beta_gamma);
             ni_vec.push(beta_wi);
             j = j + 1;
@@ -218,40 +512,36 @@ pub fn sign(
     let mut j = 0;
     for i in 1..total_parties + 1 {
         if i != party_num_int {
-            assert!(sendp2p(
-                &addr,
-                &client,
+            sendp2p(
+                transport,
                 party_num_int.clone(),
                 i.clone(),
                 "round2",
-                serde_json::to_string(&(m_b_gamma_send_vec[j].clone(), m_b_w_send_vec[j].clone()))
-                    .unwrap(),
+                session.channel.seal(i, "round2", &serde_json::to_string(&mta_answers_send_vec[j]).unwrap()),
                 uuid.clone(),
             )
-            .is_ok());
+            .await?;
             j = j + 1;
         }
     }
 
     let round2_ans_vec = poll_for_p2p(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         total_parties,
-        delay,
+        config,
         "round2",
         uuid.clone(),
-    );
+    )
+    .await?;
 
-    let mut m_b_gamma_rec_vec: Vec<MessageB> = Vec::new();
-    let mut m_b_w_rec_vec: Vec<MessageB> = Vec::new();
+    let round2_ans_vec = session.channel.open_all("round2", &round2_ans_vec)?;
+    let mut mta_answers_rec_vec: Vec<MtaAnswers> = Vec::new();
 
     for i in 0..total_parties-1 {
         //  if signers_vec.contains(&(i as usize)) {
-        let (m_b_gamma_i, m_b_w_i): (MessageB, MessageB) =
-            serde_json::from_str(&round2_ans_vec[i as usize]).unwrap();
-        m_b_gamma_rec_vec.push(m_b_gamma_i);
-        m_b_w_rec_vec.push(m_b_w_i);
+        let mta_answers_i: MtaAnswers = decode("round2", &round2_ans_vec[i as usize])?;
+        mta_answers_rec_vec.push(mta_answers_i);
         //     }
     }
 
@@ -264,26 +554,21 @@ pub fn sign(
         //        println!("mbproof p={}, i={}, j={}", party_num_int, i, j);
         if i != party_num_int {
             //            println!("verifying: p={}, i={}, j={}", party_num_int, i, j);
-            let m_b = m_b_gamma_rec_vec[j].clone();
-
-            let alpha_ij_gamma = m_b
-                .verify_proofs_get_alpha(&party_keys.dk, &sign_keys.k_i)
-                .expect("wrong dlog or m_b");
-            let m_b = m_b_w_rec_vec[j].clone();
-            let alpha_ij_wi = m_b
-                .verify_proofs_get_alpha(&party_keys.dk, &sign_keys.k_i)
-                .expect("wrong dlog or m_b");
-            alpha_vec.push(alpha_ij_gamma.0);
-            miu_vec.push(alpha_ij_wi.0);
+            let (alpha_ij_gamma, alpha_ij_wi) = mta_answers_rec_vec[j]
+                .verify_get_alphas(key_share, &signers_vec, index, (i - 1) as usize, &m_a_k, &sign_keys.k_i)
+                .map_err(Error::Protocol)?;
+            let m_b = &mta_answers_rec_vec[j].m_b_w;
+            alpha_vec.push(alpha_ij_gamma);
+            miu_vec.push(alpha_ij_wi);
             let g_w_i = Keys::update_commitments_to_xi(
                 &xi_com_vec[signers_vec[(i - 1) as usize]],
                 &vss_scheme_vec[signers_vec[(i - 1) as usize]],
                 signers_vec[(i - 1) as usize],
                 &signers_vec,
             );
-            //println!("Verifying client {}", party_num_int);
-            assert_eq!(m_b.b_proof.pk.clone(), g_w_i);
-            //println!("Verified client {}", party_num_int);
+            if m_b.b_proof.pk != g_w_i {
+                return Err(Error::Protocol(format!("party {} answered the MtA with a wrong w_i", i)));
+            }
             j = j + 1;
         }
     }
@@ -291,115 +576,108 @@ pub fn sign(
     let delta_i = sign_keys.phase2_delta_i(&alpha_vec, &beta_vec);
     let sigma = sign_keys.phase2_sigma_i(&miu_vec, &ni_vec);
 
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int,
         "round3",
         serde_json::to_string(&delta_i).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round3_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         total_parties,
-        delay,
+        config,
         "round3",
         uuid.clone(),
-    );
+    )
+    .await?;
     let mut delta_vec: Vec<FE> = Vec::new();
     format_vec_from_reads(
+        "round3",
         &round3_ans_vec,
         party_num_int as usize,
         delta_i,
         &mut delta_vec,
-    );
+    )?;
     let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
 
     //////////////////////////////////////////////////////////////////////////////
     // decommit to gamma_i
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int,
         "round4",
         serde_json::to_string(&decommit).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round4_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int,
         total_parties,
-        delay,
+        config,
         "round4",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut decommit_vec: Vec<SignDecommitPhase1> = Vec::new();
     format_vec_from_reads(
+        "round4",
         &round4_ans_vec,
         party_num_int as usize,
         decommit,
         &mut decommit_vec,
-    );
+    )?;
     let decomm_i = decommit_vec.remove((party_num_int - 1) as usize);
     bc1_vec.remove((party_num_int - 1) as usize);
-    let b_proof_vec = (0..m_b_gamma_rec_vec.len())
-        .map(|i| &m_b_gamma_rec_vec[i].b_proof)
+    let b_proof_vec = (0..mta_answers_rec_vec.len())
+        .map(|i| &mta_answers_rec_vec[i].m_b_gamma.b_proof)
         .collect::<Vec<&DLogProof<GE>>>();
     let R = SignKeys::phase4(&delta_inv, &b_proof_vec, decommit_vec, &bc1_vec)
-        .expect("bad gamma_i decommit");
+        .map_err(|e| Error::Protocol(format!("bad gamma_i decommit: {:?}", e)))?;
 
     // adding local g_gamma_i
     let R = R + decomm_i.g_gamma_i * &delta_inv;
 
-    // we assume the message is already hashed (by the signer).
-    let message_bn = BigInt::from_bytes(message);
-    //    println!("message_bn INT: {}", message_bn);
-    let message_int = BigInt::from_bytes(message);
-    let two = BigInt::from(2);
-    let message_bn = message_bn.modulus(&two.pow(256));
     let local_sig =
-        LocalSignature::phase5_local_sig(&sign_keys.k_i, &message_bn, &R, &sigma, &y_sum);
+        LocalSignature::phase5_local_sig(&sign_keys.k_i, message_bn, &R, &sigma, &session.public_key);
 
     let (phase5_com, phase_5a_decom, helgamal_proof, dlog_proof_rho) = local_sig.phase5a_broadcast_5b_zkproof();
 
     //phase (5A)  broadcast commit
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int.clone(),
         "round5",
         serde_json::to_string(&phase5_com).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round5_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int.clone(),
         total_parties,
-        delay.clone(),
+        config,
         "round5",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut commit5a_vec: Vec<Phase5Com1> = Vec::new();
     format_vec_from_reads(
+        "round5",
         &round5_ans_vec,
         party_num_int.clone() as usize,
         phase5_com,
         &mut commit5a_vec,
-    );
+    )?;
 
     //phase (5B)  broadcast decommit and (5B) ZK proof
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int.clone(),
         "round6",
         serde_json::to_string(&(
@@ -410,16 +688,16 @@ pub fn sign(
         .unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round6_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int.clone(),
         total_parties,
-        delay.clone(),
+        config,
         "round6",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut decommit5a_and_elgamal_and_dlog_vec: Vec<(
         Phase5ADecom1,
@@ -427,6 +705,7 @@ pub fn sign(
         DLogProof<GE>,
     )> = Vec::new();
     format_vec_from_reads(
+        "round6",
         &round6_ans_vec,
         party_num_int as usize,
         (
@@ -435,7 +714,7 @@ pub fn sign(
             dlog_proof_rho.clone(),
         ),
         &mut decommit5a_and_elgamal_and_dlog_vec,
-    );
+    )?;
     let decommit5a_and_elgamal_vec_includes_i = decommit5a_and_elgamal_and_dlog_vec.clone();
     decommit5a_and_elgamal_and_dlog_vec.remove((party_num_int - 1) as usize);
     commit5a_vec.remove((party_num_int - 1) as usize);
@@ -457,63 +736,63 @@ pub fn sign(
             &phase_5a_decom.V_i,
             &R.clone(),
         )
-        .expect("error phase5");
+        .map_err(|e| Error::Protocol(format!("phase 5 check failed: {:?}", e)))?;
 
     //////////////////////////////////////////////////////////////////////////////
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int.clone(),
         "round7",
         serde_json::to_string(&phase5_com2).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round7_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int.clone(),
         total_parties,
-        delay.clone(),
+        config,
         "round7",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut commit5c_vec: Vec<Phase5Com2> = Vec::new();
     format_vec_from_reads(
+        "round7",
         &round7_ans_vec,
         party_num_int.clone() as usize,
         phase5_com2,
         &mut commit5c_vec,
-    );
+    )?;
 
     //phase (5B)  broadcast decommit and (5B) ZK proof
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int.clone(),
         "round8",
         serde_json::to_string(&phase_5d_decom2).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round8_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int.clone(),
         total_parties,
-        delay.clone(),
+        config,
         "round8",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut decommit5d_vec: Vec<Phase5DDecom2> = Vec::new();
     format_vec_from_reads(
+        "round8",
         &round8_ans_vec,
         party_num_int.clone() as usize,
         phase_5d_decom2.clone(),
         &mut decommit5d_vec,
-    );
+    )?;
 
     let phase_5a_decomm_vec_includes_i = (0..total_parties)
         .map(|i| decommit5a_and_elgamal_vec_includes_i[i as usize].0.clone())
@@ -524,40 +803,40 @@ pub fn sign(
             &commit5c_vec,
             &phase_5a_decomm_vec_includes_i,
         )
-        .expect("bad com 5d");
+        .map_err(|e| Error::Protocol(format!("bad phase 5d decommit: {:?}", e)))?;
 
     //////////////////////////////////////////////////////////////////////////////
-    assert!(broadcast(
-        &addr,
-        &client,
+    broadcast(
+        transport,
         party_num_int.clone(),
         "round9",
         serde_json::to_string(&s_i).unwrap(),
         uuid.clone(),
     )
-    .is_ok());
+    .await?;
     let round9_ans_vec = poll_for_broadcasts(
-        &addr,
-        &client,
+        transport,
         party_num_int.clone(),
         total_parties,
-        delay.clone(),
+        config,
         "round9",
         uuid.clone(),
-    );
+    )
+    .await?;
 
     let mut s_i_vec: Vec<FE> = Vec::new();
     format_vec_from_reads(
+        "round9",
         &round9_ans_vec,
         party_num_int.clone() as usize,
         s_i,
         &mut s_i_vec,
-    );
+    )?;
 
     s_i_vec.remove((party_num_int - 1) as usize);
     let sig = local_sig
         .output_signature(&s_i_vec)
-        .expect("verification failed");
+        .map_err(|e| Error::Protocol(format!("the combined signature does not verify: {:?}", e)))?;
     //    println!(" \n");
     //    println!("party {:?} Output Signature: \n", party_num_int);
     //    println!("SIG msg: {:?}", sig.m);
@@ -566,82 +845,58 @@ pub fn sign(
     //    println!("child pubkey: {:?} \n", y_sum);
 
     //    println!("pubkey: {:?} \n", y_sum);
-    //    println!("verifying signature with public key");
-    verify(&sig, &y_sum, &message_bn).expect("false");
-    //    println!("verifying signature with child pub key");
-    //    verify(&sig, &new_key, &message_bn).expect("false");
 
     //    println!("{:?}", sig.recid.clone());
     //    print(sig.recid.clone()
 
-    let ret_dict = json!({
-        "r": (BigInt::from_bytes(&(sig.r.get_element())[..])).to_str_radix(16),
-        "s": (BigInt::from_bytes(&(sig.s.get_element())[..])).to_str_radix(16),
-        "status": "signature_ready",
-        "recid": sig.recid.clone(),
-        "x": &y_sum.x_coor(),
-        "y": &y_sum.y_coor(),
-        "msg_int": message_int,
-    });
-    println!("{}", ret_dict.to_string());
-
-    //    fs::write("signature".to_string(), sign_json).expect("Unable to save !");
-
-    //    println!("Public key Y: {:?}", to_bitcoin_public_key(y_sum.get_element()).to_bytes());
-    //    println!("Public child key X: {:?}", &new_key.x_coor());
-    //    println!("Public child key Y: {:?}", &new_key.y_coor());
-    //    println!("Public key big int: {:?}", &y_sum.bytes_compressed_to_big_int());
-    //    println!("Public key ge: {:?}", &y_sum.get_element().serialize());
-    //    println!("Public key ge: {:?}", PK::serialize_uncompressed(&y_sum.get_element()));
-    //    println!("New public key: {:?}", &y_sum.x_coor);
+    Ok(sig)
 }
 
-fn format_vec_from_reads<'a, T: serde::Deserialize<'a> + Clone>(
-    ans_vec: &'a Vec<String>,
+pub fn format_vec_from_reads<T: DeserializeOwned + Clone>(
+    round: &str,
+    ans_vec: &[String],
     party_num: usize,
     value_i: T,
-    new_vec: &'a mut Vec<T>,
-) {
+    new_vec: &mut Vec<T>,
+) -> Result<(), Error> {
     let mut j = 0;
     for i in 1..ans_vec.len() + 2 {
         if i == party_num {
             new_vec.push(value_i.clone());
         } else {
-            let value_j: T = serde_json::from_str(&ans_vec[j]).unwrap();
+            let value_j: T = decode(round, &ans_vec[j])?;
             new_vec.push(value_j);
             j = j + 1;
         }
     }
+    Ok(())
 }
 
-pub fn postb<T>(addr: &String, client: &Client, path: &str, body: T) -> Option<String>
-where
-    T: serde::ser::Serialize,
-{
-    let res = client
-        .post(&format!("{}/{}", addr, path))
-        .json(&body)
-        .timeout(Duration::from_secs(600))
-        .send();
-    Some(res.unwrap().text().unwrap())
-}
-
-pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, party_id: u16) -> Result<(PartySignup, u16), ()> {
+pub async fn signup(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    threshold: u16,
+    room_id: String,
+    party_id: u16,
+    protocol: Protocol,
+    policy: &SignerPolicy,
+) -> Result<(PartySignup, u16), Error> {
     let mut request_body = PartySignupRequestBody{
         threshold,
         room_id: room_id.clone(),
         party_number: party_id,
-        party_uuid: "".to_string()
+        party_uuid: "".to_string(),
+        protocol,
+        policy: Some(policy.clone()).filter(|x| *x != SignerPolicy::default()),
     };
     let path = "signupsign";
     let delay = time::Duration::from_millis(100);
-    let timeout = std::env::var("TSS_CLI_SIGNUP_TIMEOUT")
-        .unwrap_or("30".to_string()).parse::<u64>().unwrap();
-    let res_body = postb(&addr, &client, path, request_body.clone()).unwrap();
-    let answer: Result<SigningPartySignup, ManagerError> = serde_json::from_str(&res_body).unwrap();
+    let timeout = config.signup_timeout;
+    let res_body = postb(transport, path, request_body.clone()).await?;
+    let answer: Result<SigningPartySignup, ManagerError> = manager_answer(path, &res_body)?;
     let (output, total_parties) = match answer {
         Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined}) => {
-            println!("Signed up, party order: {:?}, joined so far: {:?}, waiting for room uuid", party_order, total_joined);
+            info!(party_order, total_joined, "Signed up, waiting for room uuid");
             let mut now = time::SystemTime::now();
             let mut last_total_joined = total_joined;
             let mut party_signup = PartySignup {
@@ -649,41 +904,37 @@ pub fn signup(addr: &String, client: &Client, threshold: u16, room_id: String, p
                 uuid: room_uuid
             };
             while party_signup.uuid.is_empty() {
-                thread::sleep(delay);
+                tokio::time::sleep(delay).await;
                 request_body.party_uuid = party_uuid.clone();
-                let res_body = postb(&addr, &client, path, request_body.clone()).unwrap();
-                let answer: Result<SigningPartySignup, ManagerError> = serde_json::from_str(&res_body).unwrap();
+                let res_body = postb(transport, path, request_body.clone()).await?;
+                let answer: Result<SigningPartySignup, ManagerError> = manager_answer(path, &res_body)?;
                 match answer {
                     Ok(SigningPartySignup{party_order, party_uuid, room_uuid, total_joined}) => {
                         request_body.party_uuid = party_uuid;
                         if party_signup.number != party_order {
-                            println!("Order is changed: {:?}", party_order);
+                            info!(party_order, "Order is changed");
                             party_signup.number = party_order;
                         }
                         party_signup.uuid = room_uuid;
                         if total_joined != last_total_joined {
-                            println!("Joined so far: {:?}", total_joined);
+                            info!(total_joined, "Joined so far");
                             last_total_joined = total_joined;
                             //Reset the signup timeout
                             now = time::SystemTime::now();
                         }
                     },
-                    Err(ManagerError{error}) => {
-                        panic!("{}", error);
-                    }
+                    Err(ManagerError{error}) => return Err(Error::Manager(error)),
                 };
-                if now.elapsed().unwrap().as_secs() > timeout{
+                if now.elapsed().unwrap_or_default().as_secs() > timeout{
                     break;
                 }
             }
             if party_signup.uuid.is_empty() {
-                panic!("Could not get room uuid after {:?} seconds of tries", timeout);
+                return Err(Error::Timeout(format!("Could not get room uuid after {:?} seconds of tries", timeout)));
             }
             (party_signup, last_total_joined)
         },
-        Err(ManagerError{error}) => {
-            panic!("{}", error);
-        }
+        Err(ManagerError{error}) => return Err(Error::Manager(error)),
     };
 
     return Ok((output, total_parties));
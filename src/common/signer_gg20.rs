@@ -0,0 +1,357 @@
+use curv::cryptographic_primitives::proofs::sigma_correct_homomorphic_elgamal_enc::HomoELGamalProof;
+use curv::cryptographic_primitives::proofs::sigma_dlog::DLogProof;
+use curv::cryptographic_primitives::proofs::sigma_valid_pedersen::PedersenProof;
+use curv::{
+    BigInt,
+    elliptic::curves::secp256_k1::{FE, GE},
+};
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::SignatureRecid;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2020::party_i::{
+    Keys, LocalSignature, SignBroadcastPhase1, SignDecommitPhase1, SignKeys,
+};
+use multi_party_ecdsa::utilities::mta::{MessageA, MessageB};
+use multi_party_ecdsa::utilities::zk_pdl_with_slack::PDLwSlackProof;
+use tracing::info;
+
+use crate::common::error::decode;
+use crate::common::{
+    broadcast, poll_for_broadcasts, poll_for_p2p, sendp2p, ClientConfig, Error, KeyShare, Transport,
+};
+use crate::common::signer::{format_vec_from_reads, range_statements, MtaAnswers, SigningSession};
+
+/// GG20 signing rounds 1 to 7, run after round 0. Every message is checked as soon as it arrives
+/// and a bad one aborts the session naming the party that sent it.
+pub async fn sign_gg20(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &KeyShare,
+    session: &SigningSession,
+    message_bn: &BigInt,
+) -> Result<SignatureRecid, Error> {
+    let party_keys = &key_share.party_keys;
+    let paillier_key_vector = &key_share.paillier_key_vector;
+    let dlog_statement_vec = &key_share.dlog_statement_vec;
+    let SigningSession { party_num_int, uuid, total_parties, signers_vec, vss_scheme_vec, .. } = session.clone();
+    let index = (party_num_int - 1) as usize;
+    let party_id = |position: usize| signers_vec[position] + 1;
+
+    let x_i = match &session.path_tweak {
+        Some(f_l_new) => key_share.shared_keys.x_i + f_l_new,
+        None => key_share.shared_keys.x_i,
+    };
+    let sign_keys = SignKeys::create(&x_i, &vss_scheme_vec[signers_vec[index]], signers_vec[index], &signers_vec);
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 1: commit to g^gamma_i, send k_i encrypted with range proofs
+    let (com, decommit) = sign_keys.phase1_broadcast();
+    let (m_a_k, k_randomness) = MessageA::a(&sign_keys.k_i, &party_keys.ek, &range_statements(key_share, &signers_vec, index));
+    broadcast(
+        transport,
+        party_num_int,
+        "round1",
+        serde_json::to_string(&(com.clone(), m_a_k.clone())).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round1_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round1",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut round1_vec: Vec<(SignBroadcastPhase1, MessageA)> = Vec::new();
+    format_vec_from_reads(
+        "round1",
+        &round1_ans_vec,
+        party_num_int as usize,
+        (com, m_a_k.clone()),
+        &mut round1_vec,
+    )?;
+    let (bc1_vec, m_a_vec): (Vec<SignBroadcastPhase1>, Vec<MessageA>) = round1_vec.into_iter().unzip();
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 2: MtA with every other signer, checking their range proofs
+    let mut mta_answers_send_vec: Vec<MtaAnswers> = Vec::new();
+    let mut beta_vec: Vec<FE> = Vec::new();
+    let mut ni_vec: Vec<FE> = Vec::new();
+    for j in (0..total_parties as usize).filter(|j| *j != index) {
+        let (mta_answers, beta_gamma, beta_wi) = MtaAnswers::create(
+            key_share,
+            &signers_vec,
+            j,
+            &m_a_vec[j],
+            &sign_keys.gamma_i,
+            &sign_keys.w_i,
+        )
+        .map_err(Error::Protocol)?;
+        mta_answers_send_vec.push(mta_answers);
+        beta_vec.push(beta_gamma);
+        ni_vec.push(beta_wi);
+    }
+
+    let mut j = 0;
+    for i in 1..=total_parties {
+        if i != party_num_int {
+            sendp2p(
+                transport,
+                party_num_int,
+                i,
+                "round2",
+                session.channel.seal(i, "round2", &serde_json::to_string(&mta_answers_send_vec[j]).unwrap()),
+                uuid.clone(),
+            )
+            .await?;
+            j += 1;
+        }
+    }
+
+    let round2_ans_vec = poll_for_p2p(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round2",
+        uuid.clone(),
+    )
+    .await?;
+    let round2_ans_vec = session.channel.open_all("round2", &round2_ans_vec)?;
+
+    let mut m_b_gamma_rec_vec: Vec<MessageB> = Vec::new();
+    let mut alpha_vec: Vec<FE> = Vec::new();
+    let mut miu_vec: Vec<FE> = Vec::new();
+    let xi_com_vec = Keys::get_commitments_to_xi(&vss_scheme_vec);
+    for (answer, j) in round2_ans_vec.iter().zip((0..total_parties as usize).filter(|j| *j != index)) {
+        let mta_answers: MtaAnswers = decode("round2", answer)?;
+        let (alpha_ij_gamma, alpha_ij_wi) = mta_answers
+            .verify_get_alphas(key_share, &signers_vec, index, j, &m_a_k, &sign_keys.k_i)
+            .map_err(Error::Protocol)?;
+        let g_w_j = Keys::update_commitments_to_xi(
+            &xi_com_vec[signers_vec[j]],
+            &vss_scheme_vec[signers_vec[j]],
+            signers_vec[j],
+            &signers_vec,
+        );
+        if mta_answers.m_b_w.b_proof.pk != g_w_j {
+            return Err(Error::Protocol(format!("party {} used a share that does not match its VSS commitments", party_id(j))));
+        }
+        alpha_vec.push(alpha_ij_gamma);
+        miu_vec.push(alpha_ij_wi);
+        m_b_gamma_rec_vec.push(mta_answers.m_b_gamma);
+    }
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 3: send delta_i and the commitment T_i to sigma_i
+    let delta_i = sign_keys.phase2_delta_i(&alpha_vec, &beta_vec);
+    let sigma_i = sign_keys.phase2_sigma_i(&miu_vec, &ni_vec);
+    let (T_i, l_i, T_proof_i) = SignKeys::phase3_compute_t_i(&sigma_i);
+
+    broadcast(
+        transport,
+        party_num_int,
+        "round3",
+        serde_json::to_string(&(delta_i, T_i, T_proof_i.clone())).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round3_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round3",
+        uuid.clone(),
+    )
+    .await?;
+    let mut round3_vec: Vec<(FE, GE, PedersenProof<GE>)> = Vec::new();
+    format_vec_from_reads(
+        "round3",
+        &round3_ans_vec,
+        party_num_int as usize,
+        (delta_i, T_i, T_proof_i),
+        &mut round3_vec,
+    )?;
+    for (j, (_, _, T_proof_j)) in round3_vec.iter().enumerate() {
+        if PedersenProof::verify(T_proof_j).is_err() {
+            return Err(Error::Protocol(format!("party {} sent an invalid proof for T_i", party_id(j))));
+        }
+    }
+    let delta_vec = round3_vec.iter().map(|x| x.0).collect::<Vec<FE>>();
+    let T_vec = round3_vec.iter().map(|x| x.1).collect::<Vec<GE>>();
+    let delta_inv = SignKeys::phase3_reconstruct_delta(&delta_vec);
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 4: decommit to gamma_i
+    broadcast(
+        transport,
+        party_num_int,
+        "round4",
+        serde_json::to_string(&decommit).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round4_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round4",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut decommit_vec: Vec<SignDecommitPhase1> = Vec::new();
+    format_vec_from_reads(
+        "round4",
+        &round4_ans_vec,
+        party_num_int as usize,
+        decommit,
+        &mut decommit_vec,
+    )?;
+    let b_proof_vec = m_b_gamma_rec_vec.iter()
+        .map(|m_b| &m_b.b_proof)
+        .collect::<Vec<&DLogProof<GE>>>();
+    let R = SignKeys::phase4(&delta_inv, &b_proof_vec, decommit_vec, &bc1_vec, index)
+        .map_err(|e| Error::Protocol(format!("invalid gamma_i decommitment: {:?}", e)))?;
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 5: send R_i = R^k_i with proofs that it matches the encrypted k_i
+    let R_dash = R * &sign_keys.k_i;
+    let pdl_proof_vec = (0..total_parties as usize)
+        .filter(|j| *j != index)
+        .map(|j| LocalSignature::phase5_proof_pdl(
+            &R_dash,
+            &R,
+            &m_a_k.c,
+            &party_keys.ek,
+            &sign_keys.k_i,
+            &k_randomness,
+            &dlog_statement_vec[signers_vec[j]],
+        ))
+        .collect::<Vec<PDLwSlackProof>>();
+
+    broadcast(
+        transport,
+        party_num_int,
+        "round5",
+        serde_json::to_string(&(R_dash, pdl_proof_vec.clone())).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round5_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round5",
+        uuid.clone(),
+    )
+    .await?;
+    let mut round5_vec: Vec<(GE, Vec<PDLwSlackProof>)> = Vec::new();
+    format_vec_from_reads(
+        "round5",
+        &round5_ans_vec,
+        party_num_int as usize,
+        (R_dash, pdl_proof_vec),
+        &mut round5_vec,
+    )?;
+    for (j, (R_dash_j, pdl_proof_vec_j)) in round5_vec.iter().enumerate().filter(|(j, _)| *j != index) {
+        LocalSignature::phase5_verify_pdl(
+            pdl_proof_vec_j,
+            R_dash_j,
+            &R,
+            &m_a_vec[j].c,
+            &paillier_key_vector[signers_vec[j]],
+            dlog_statement_vec,
+            &signers_vec,
+            j,
+        )
+        .map_err(|e| Error::Protocol(format!("party {} sent an invalid R_i proof: {:?}", party_id(j), e)))?;
+    }
+    let R_dash_vec = round5_vec.iter().map(|x| x.0).collect::<Vec<GE>>();
+    LocalSignature::phase5_check_R_dash_sum(&R_dash_vec)
+        .map_err(|e| Error::Protocol(format!("R_i values do not add up, a party lied about its share of R: {:?}", e)))?;
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 6: send S_i = R^sigma_i with a proof of consistency with T_i
+    let (S_i, homo_elgamal_proof) = LocalSignature::phase6_compute_S_i_and_proof_of_consistency(
+        &R, &T_i, &sigma_i, &l_i,
+    );
+    broadcast(
+        transport,
+        party_num_int,
+        "round6",
+        serde_json::to_string(&(S_i, homo_elgamal_proof.clone())).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round6_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round6",
+        uuid.clone(),
+    )
+    .await?;
+    let mut round6_vec: Vec<(GE, HomoELGamalProof<GE>)> = Vec::new();
+    format_vec_from_reads(
+        "round6",
+        &round6_ans_vec,
+        party_num_int as usize,
+        (S_i, homo_elgamal_proof),
+        &mut round6_vec,
+    )?;
+    let (S_vec, homo_elgamal_proof_vec): (Vec<GE>, Vec<HomoELGamalProof<GE>>) = round6_vec.into_iter().unzip();
+    let R_vec = vec![R; total_parties as usize];
+    LocalSignature::phase6_verify_proof(&S_vec, &homo_elgamal_proof_vec, &R_vec, &T_vec)
+        .map_err(|e| Error::Protocol(format!("invalid S_i proof: {:?}", e)))?;
+    LocalSignature::phase6_check_S_i_sum(&session.public_key, &S_vec)
+        .map_err(|e| Error::Protocol(format!("S_i values do not add up to the public key: {:?}", e)))?;
+    info!("All signers proved their shares, sending signature shares");
+
+    //////////////////////////////////////////////////////////////////////////////
+    // round 7: send the signature share
+    let local_sig = LocalSignature::phase7_local_sig(
+        &sign_keys.k_i, message_bn, &R, &sigma_i, &session.public_key,
+    );
+    broadcast(
+        transport,
+        party_num_int,
+        "round7",
+        serde_json::to_string(&local_sig.s_i).unwrap(),
+        uuid.clone(),
+    )
+    .await?;
+    let round7_ans_vec = poll_for_broadcasts(
+        transport,
+        party_num_int,
+        total_parties,
+        config,
+        "round7",
+        uuid.clone(),
+    )
+    .await?;
+
+    let mut s_i_vec: Vec<FE> = Vec::new();
+    format_vec_from_reads(
+        "round7",
+        &round7_ans_vec,
+        party_num_int as usize,
+        local_sig.s_i,
+        &mut s_i_vec,
+    )?;
+    s_i_vec.remove(index);
+    let sig = local_sig
+        .output_signature(&s_i_vec)
+        .map_err(|e| Error::Protocol(format!("the combined signature does not verify: {:?}", e)))?;
+
+    Ok(SignatureRecid {
+        r: sig.r,
+        s: sig.s,
+        recid: sig.recid,
+    })
+}
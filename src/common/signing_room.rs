@@ -1,14 +1,42 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
-use std::env;
-use std::str::FromStr;
 use std::time::{SystemTime, UNIX_EPOCH};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::common::{SigningPartyInfo, SigningPartySignup};
+use crate::common::{ManagerConfig, Protocol, SigningPartyInfo, SigningPartySignup};
+
+/// Which parties may join a signing room, set by the initiator when the room is created.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct SignerPolicy {
+    #[serde(default)]
+    pub allowed: Vec<u16>,   // Party indices allowed to join, any party if empty
+    #[serde(default)]
+    pub preferred: Vec<u16>, // Parties picked first, in this order
+    #[serde(default)]
+    pub fallback_after: u64, // Seconds after the room opened before other parties may replace missing preferred ones
+}
+
+impl SignerPolicy {
+
+    pub fn admits(&self, party_number: u16) -> bool {
+        self.allowed.is_empty() || self.allowed.contains(&party_number)
+    }
+
+    pub fn validate(&self, room_size: u16) -> Result<(), String> {
+        if !self.allowed.is_empty() && self.allowed.len() < usize::from(room_size) {
+            return Err(format!("Signer policy allows {} parties, {} are needed", self.allowed.len(), room_size));
+        }
+        if let Some(party) = self.preferred.iter().find(|x| !self.admits(**x)) {
+            return Err(format!("Preferred party {} is not in the allowed parties", party));
+        }
+        Ok(())
+    }
 
-pub const SIGNUP_TIMEOUT_ENV: &str = "TSS_MANAGER_SIGNUP_TIMEOUT";
-pub const SIGNUP_TIMEOUT_DEFAULT: &str = "2";
+    fn rank(&self, party_number: u16) -> (usize, u16) {
+        let position = self.preferred.iter().position(|x| *x == party_number);
+        (position.unwrap_or(usize::MAX), party_number)
+    }
+}
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct SigningRoom {
@@ -16,14 +44,30 @@ pub struct SigningRoom {
     pub room_uuid: String, // ID set by manager, used during the rounds
     pub room_size: u16,
     pub member_info: HashMap<u16, SigningPartyInfo>,
-    pub last_stage: String
+    pub last_stage: String,
+    #[serde(default = "default_signup_timeout")]
+    pub signup_timeout: u64, // Seconds without a ping before a member is considered inactive
+    #[serde(default)]
+    pub protocol: Protocol,
+    #[serde(default)]
+    pub policy: SignerPolicy,
+    #[serde(default)]
+    pub created: u64,
+}
+
+fn default_signup_timeout() -> u64 {
+    ManagerConfig::default().signup_timeout
 }
 
 impl SigningRoom {
 
-    pub fn new(room_id: String, size: u16) -> Self {
+    pub fn new(room_id: String, size: u16, protocol: Protocol, signup_timeout: u64, policy: SignerPolicy) -> Self {
         SigningRoom {
             room_size: size,
+            signup_timeout,
+            protocol,
+            policy,
+            created: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
             member_info: Default::default(),
             room_id,
             last_stage: "signup".to_string(),
@@ -40,17 +84,21 @@ impl SigningRoom {
         }
     }
 
+    /// Number of members the room holds during signup: `room_size`, plus a waiting spot per
+    /// preferred party for the fallbacks that may replace it.
+    fn capacity(&self) -> usize {
+        let size = usize::from(self.room_size);
+        size + self.policy.preferred.len().min(size)
+    }
+
     pub fn is_full(&self) -> bool {
-        self.member_info.len() >= usize::from(self.room_size)
+        self.member_info.len() >= self.capacity()
     }
 
-    fn is_timeout(party: &SigningPartyInfo) -> bool {
+    fn is_timeout(&self, party: &SigningPartyInfo) -> bool {
         let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let timeout = u64::from_str(
-            env::var(SIGNUP_TIMEOUT_ENV).unwrap_or(SIGNUP_TIMEOUT_DEFAULT.to_string()).as_str()
-        ).unwrap();
 
-        party.last_ping < now - timeout
+        party.last_ping < now - self.signup_timeout
     }
 
     pub fn add_party(&mut self, party_number: u16) -> SigningPartySignup {
@@ -80,42 +128,59 @@ impl SigningRoom {
 
     pub fn are_all_members_active(&self) -> bool {
         self.member_info.values().all(
-            |x| !SigningRoom::is_timeout(x)
+            |x| !self.is_timeout(x)
         )
     }
 
     pub fn are_all_members_inactive(&self) -> bool {
-        self.is_full() && self.member_info.values().all(
-            |x| SigningRoom::is_timeout(x)
+        self.member_info.len() >= usize::from(self.room_size) && self.member_info.values().all(
+            |x| self.is_timeout(x)
         )
     }
 
     pub fn is_member_active(&self, party_number: u16) -> bool {
         let party_data = self.member_info.get(&party_number).unwrap();
 
-        !SigningRoom::is_timeout(party_data)
+        !self.is_timeout(party_data)
     }
 
     pub fn update_ping(&mut self, party_number: u16) -> SigningPartySignup {
         let party_data = self.member_info.get_mut(&party_number).unwrap();
         party_data.last_ping = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs();
-        if self.is_full() && self.active_members().len() >= usize::from(self.room_size) {
-            self.close_signup_window();
+        if let Some(signers) = self.select_signers() {
+            self.close_signup_window(signers);
         }
         self.get_signup_info(party_number)
     }
 
-    fn close_signup_window(&mut self){
+    /// Picks `room_size` active members, preferred parties first. Returns None while there are not
+    /// enough of them, or while a missing preferred party would be replaced before `fallback_after`.
+    fn select_signers(&self) -> Option<Vec<u16>> {
+        let size = usize::from(self.room_size);
         let active_members = self.active_members();
-        if self.is_full() && active_members.len() >= usize::from(self.room_size) {
-            self.last_stage = "terminated".to_string();
-            let mut new_order = 1;
-            for (key, value) in self.member_info.iter_mut() {
-                if active_members.contains_key(&key) {
-                    value.party_order = new_order;
-                    new_order = new_order + 1;
-                }
-            }
+        let mut signers: Vec<u16> = active_members.keys().cloned().collect();
+        if signers.len() < size {
+            return None;
+        }
+        signers.sort_by_key(|x| self.policy.rank(*x));
+        signers.truncate(size);
+
+        let preferred_missing = self.policy.preferred.iter().any(|x| !active_members.contains_key(x));
+        let has_fallback = signers.iter().any(|x| !self.policy.preferred.contains(x));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        if preferred_missing && has_fallback && now < self.created + self.policy.fallback_after {
+            return None;
+        }
+        Some(signers)
+    }
+
+    /// Ends signup with `signers` ordered by party number, other members are dropped.
+    fn close_signup_window(&mut self, mut signers: Vec<u16>) {
+        self.last_stage = "terminated".to_string();
+        signers.sort();
+        self.member_info.retain(|key, _value| signers.contains(key));
+        for (i, party_number) in signers.iter().enumerate() {
+            self.member_info.get_mut(party_number).unwrap().party_order = u16::try_from(i).unwrap() + 1;
         }
     }
 
@@ -126,7 +191,7 @@ impl SigningRoom {
 
     fn active_members(&self) -> HashMap<u16, SigningPartyInfo> {
         self.member_info.clone().into_iter()
-            .filter(|(_key, x)| !SigningRoom::is_timeout(x)).collect()
+            .filter(|(_key, x)| !self.is_timeout(x)).collect()
     }
 
     pub fn get_signup_info(&self, party_number: u16) -> SigningPartySignup {
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use curv::elliptic::curves::secp256_k1::{FE, GE};
+use curv::elliptic::curves::traits::*;
+use curv::arithmetic::{BasicOps, Converter, Modulo};
+use curv::BigInt;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{verify, SignatureRecid};
+use multi_party_ecdsa::protocols::two_party_ecdsa::lindell_2017::{party_one, party_two};
+use multi_party_ecdsa::utilities::zk_pdl_with_slack::{PDLwSlackProof, PDLwSlackStatement};
+use paillier::{Add, Encrypt, EncryptionKey, Paillier, RawCiphertext, RawPlaintext};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tracing::{field, info, instrument, Span};
+use zk_paillier::zkproofs::{CompositeDLogProof, NICorrectKeyProof};
+
+use crate::common::error::decode;
+use crate::common::key_share::{load_keys_file, write_keys_file};
+use crate::common::keygen::{keygen_signup, verify_paillier_keys};
+use crate::common::p2p::P2pChannel;
+use crate::common::signer::{round0, signup, Round0Message, SignOptions, SignResult, SigningContext};
+use crate::common::{
+    block_on, broadcast, hd_keys, poll_for_broadcasts, poll_for_p2p, sendp2p, sha256_digest, ClientConfig, Error,
+    PartySignup, Protocol, Transport,
+};
+
+/// A party's output of the two-party (Lindell 2017) keygen, i.e. the content of its keys file.
+/// The key is x1 * x2 * G, party 1 holds x1 and the Paillier secret key, party 2 holds x2 and x1 encrypted.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TwoPartyKeyShare {
+    pub protocol: Protocol, // Always lindell17, tells these files apart from the threshold ones
+    pub party_id: u16,
+    pub public_key: GE,
+    pub share: TwoPartyShare,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "role", rename_all = "lowercase")]
+pub enum TwoPartyShare {
+    One {
+        private: party_one::Party1Private,
+    },
+    Two {
+        x2: FE,
+        ek: EncryptionKey, // Party 1's Paillier key
+        encrypted_share: BigInt, // x1 under ek
+    },
+}
+
+/// Party 1's second keygen message: the opening of its commitment, x1 encrypted to its own
+/// Paillier key, and proofs that the key is well formed and that the ciphertext holds x1.
+#[derive(Clone, Serialize, Deserialize)]
+struct KeygenRound2 {
+    second_msg: party_one::KeyGenSecondMsg,
+    ek: EncryptionKey,
+    encrypted_share: BigInt,
+    correct_key_proof: NICorrectKeyProof,
+    pdl_statement: PDLwSlackStatement,
+    pdl_proof: PDLwSlackProof,
+    composite_dlog_proof: CompositeDLogProof,
+}
+
+impl TwoPartyKeyShare {
+
+    pub fn load(keysfile_path: &str) -> Result<Self, Error> {
+        load_keys_file(keysfile_path)
+    }
+
+    pub fn save(&self, keysfile_path: &str) -> Result<(), Error> {
+        write_keys_file(keysfile_path, self)
+    }
+
+    /// Checks that the parts of the keys file agree with each other.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.protocol != Protocol::Lindell17 {
+            return Err(format!("Keys file holds {} keys, not lindell17 ones", self.protocol));
+        }
+        match (&self.share, self.party_id) {
+            (TwoPartyShare::One { .. }, 1) | (TwoPartyShare::Two { .. }, 2) => Ok(()),
+            _ => Err(format!("Party id {} of the keys file does not match its share", self.party_id)),
+        }
+    }
+
+    /// Returns the derived public key at `path` together with its tweak, or the root public key
+    /// and a zero tweak if `path` is empty.
+    pub fn derive(&self, path: &str) -> Result<(FE, GE), String> {
+        match path.is_empty() {
+            true => Ok((ECScalar::zero(), self.public_key)),
+            false => hd_keys::call_hd_key(path, self.public_key),
+        }
+    }
+}
+
+pub fn run_keygen(transport: &dyn Transport, config: &ClientConfig) -> Result<TwoPartyKeyShare, Error> {
+    block_on(run_keygen_async(transport, config))
+}
+
+/// Two-party keygen: party 1 is the first one in the room, party 2 the other one.
+#[instrument(skip(transport, config), fields(party = field::Empty, uuid = field::Empty))]
+pub async fn run_keygen_async(transport: &dyn Transport, config: &ClientConfig) -> Result<TwoPartyKeyShare, Error> {
+    let signup = keygen_signup(transport, config, 1, 2, Protocol::Lindell17).await;
+    let (party_num_int, uuid) = match signup? {
+        PartySignup { number, uuid } => (number, uuid),
+    };
+    Span::current().record("party", &party_num_int);
+    Span::current().record("uuid", &uuid.as_str());
+    info!("Signed up for keygen");
+
+    match party_num_int {
+        1 => keygen_party_one(transport, config, uuid).await,
+        _ => keygen_party_two(transport, config, uuid).await,
+    }
+}
+
+async fn keygen_party_one(transport: &dyn Transport, config: &ClientConfig, uuid: String) -> Result<TwoPartyKeyShare, Error> {
+    // round 1: commit to x1 * G, then get x2 * G with its dlog proof, along with the p2p keys of later rounds
+    let (first_msg, comm_witness, ec_key_pair) = party_one::KeyGenFirstMsg::create_commitments();
+    let p2p_secret: FE = ECScalar::new_random();
+    publish(transport, 1, "round1", &(first_msg, GE::generator() * &p2p_secret), &uuid).await?;
+    let (party_two_first_msg, p2p_key_2): (party_two::KeyGenFirstMsg, GE) =
+        receive_public(transport, config, 1, "round1", &uuid).await?;
+    // x2 * G, proven by party 2, authenticates the channel
+    let channel = keygen_channel(1, &uuid, vec![p2p_key_2 * &p2p_secret, party_two_first_msg.public_share * &p2p_secret]);
+
+    // round 2: open the commitment, send x1 encrypted with the proofs
+    let second_msg = party_one::KeyGenSecondMsg::verify_and_decommit(comm_witness, &party_two_first_msg.d_log_proof)
+        .map_err(|_| Error::Protocol("party 2 sent an invalid dlog proof".to_string()))?;
+    let paillier_key_pair = party_one::PaillierKeyPair::generate_keypair_and_encrypted_share(&ec_key_pair);
+    let private = party_one::Party1Private::set_private_key(&ec_key_pair, &paillier_key_pair);
+    let correct_key_proof = party_one::PaillierKeyPair::generate_ni_proof_correct_key(&paillier_key_pair);
+    let (pdl_statement, pdl_proof, composite_dlog_proof) =
+        party_one::PaillierKeyPair::pdl_proof(&private, &paillier_key_pair);
+    let round2 = KeygenRound2 {
+        second_msg,
+        ek: paillier_key_pair.ek.clone(),
+        encrypted_share: paillier_key_pair.encrypted_share.clone(),
+        correct_key_proof,
+        pdl_statement,
+        pdl_proof,
+        composite_dlog_proof,
+    };
+    send(transport, &channel, 1, "round2", &round2, &uuid).await?;
+
+    // round 3: party 2 confirms the public key
+    let public_key = party_one::compute_pubkey(&ec_key_pair, &party_two_first_msg.public_share);
+    let public_key_2: GE = receive(transport, config, &channel, 1, "round3", &uuid).await?;
+    if public_key_2 != public_key {
+        return Err(Error::Protocol("party 2 computed a different public key".to_string()));
+    }
+
+    Ok(TwoPartyKeyShare {
+        protocol: Protocol::Lindell17,
+        party_id: 1,
+        public_key,
+        share: TwoPartyShare::One { private },
+    })
+}
+
+async fn keygen_party_two(transport: &dyn Transport, config: &ClientConfig, uuid: String) -> Result<TwoPartyKeyShare, Error> {
+    // round 1: once party 1 is committed, send x2 * G with its dlog proof
+    let x2: FE = ECScalar::new_random();
+    let (first_msg, ec_key_pair) = party_two::KeyGenFirstMsg::create_with_fixed_secret_share(x2);
+    let p2p_secret: FE = ECScalar::new_random();
+    let (party_one_first_msg, p2p_key_1): (party_one::KeyGenFirstMsg, GE) =
+        receive_public(transport, config, 2, "round1", &uuid).await?;
+    publish(transport, 2, "round1", &(first_msg, GE::generator() * &p2p_secret), &uuid).await?;
+    let channel = keygen_channel(2, &uuid, vec![p2p_key_1 * &p2p_secret, p2p_key_1 * &x2]);
+
+    // round 2: check the opening, the Paillier key and that the ciphertext holds x1
+    let round2: KeygenRound2 = receive(transport, config, &channel, 2, "round2", &uuid).await?;
+    party_two::KeyGenSecondMsg::verify_commitments_and_dlog_proof(&party_one_first_msg, &round2.second_msg)
+        .map_err(|_| Error::Protocol("party 1 sent an invalid commitment opening or dlog proof".to_string()))?;
+    verify_paillier_keys(&[(&round2.ek, &round2.correct_key_proof)])
+        .map_err(Error::Protocol)?;
+    let paillier_public = party_two::PaillierPublic {
+        ek: round2.ek.clone(),
+        encrypted_secret_share: round2.encrypted_share.clone(),
+    };
+    let q1 = round2.second_msg.comm_witness.public_share;
+    party_two::PaillierPublic::pdl_verify(
+        &round2.composite_dlog_proof,
+        &round2.pdl_statement,
+        &round2.pdl_proof,
+        &paillier_public,
+        &q1,
+    )
+    .map_err(|_| Error::Protocol("party 1 sent an encrypted share that fails its proof".to_string()))?;
+
+    // round 3: send the public key for party 1 to confirm
+    let public_key = party_two::compute_pubkey(&ec_key_pair, &q1);
+    send(transport, &channel, 2, "round3", &public_key, &uuid).await?;
+
+    Ok(TwoPartyKeyShare {
+        protocol: Protocol::Lindell17,
+        party_id: 2,
+        public_key,
+        share: TwoPartyShare::Two {
+            x2,
+            ek: round2.ek,
+            encrypted_share: round2.encrypted_share,
+        },
+    })
+}
+
+pub fn sign(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &TwoPartyKeyShare,
+    options: &SignOptions,
+    message: &[u8],
+    path: &str,
+) -> Result<SignResult, Error> {
+    block_on(sign_async(transport, config, key_share, options, message, path))
+}
+
+/// Two-party signing. Party 2 sends party 1 a ciphertext of its part of the signature, from which
+/// party 1 computes the signature and sends it back once it verifies against the (derived) public key.
+#[instrument(skip(transport, config, key_share, options, message), fields(room_id = field::Empty, room_uuid = field::Empty, party = field::Empty))]
+pub async fn sign_async(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    key_share: &TwoPartyKeyShare,
+    options: &SignOptions,
+    message: &[u8],
+    path: &str,
+) -> Result<SignResult, Error> {
+    key_share.validate().map_err(Error::InvalidInput)?;
+    if options.taproot.is_some() {
+        return Err(Error::InvalidInput("Taproot tweaks only apply to Schnorr signatures".to_string()));
+    }
+    let (f_l_new, public_key) = key_share.derive(path).map_err(Error::InvalidInput)?;
+    let party_id = key_share.party_id;
+    let message_hash = sha256_digest(message);
+    let room_id = options.room_id.clone().unwrap_or(message_hash.clone());
+    Span::current().record("room_id", &room_id.as_str());
+
+    let context = SigningContext {
+        message_hash,
+        path: path.to_string(),
+        public_key: key_share.public_key,
+        threshold: 1,
+        parties: 2,
+        protocol: Protocol::Lindell17,
+        taproot: None,
+        allow_legacy_keys: false,
+    };
+    let (party_num_int, uuid, total_parties) = match signup(
+        transport, config, context.threshold, room_id, party_id, context.protocol, &options.policy
+    ).await? {
+        (PartySignup { number, uuid }, total_parties) => (number, uuid, total_parties),
+    };
+    Span::current().record("party", &party_num_int);
+    Span::current().record("room_uuid", &uuid.as_str());
+    info!("Signing room is ready");
+
+    // round 0: check that the other party signs the same message with the same key, and holds the other share
+    let p2p_secret: FE = ECScalar::new_random();
+    let round0_message = Round0Message {
+        party_id,
+        commitment: context.commitment(),
+        p2p_key: Some(GE::generator() * &p2p_secret),
+    };
+    let round0_vec = round0(transport, config, party_num_int, total_parties, &uuid, round0_message, &context).await?;
+    if round0_vec.iter().filter(|x| x.party_id == party_id).count() != 1 {
+        return Err(Error::Protocol(format!("both parties of the room hold share {}", party_id)));
+    }
+    let other = 3 - party_num_int;
+    let p2p_key_j = round0_vec[usize::from(other - 1)].p2p_key
+        .ok_or_else(|| Error::Protocol("the other party sent no p2p key".to_string()))?;
+    // x1 * G and x2 * G authenticate the channel, each party gets the other's from the public key
+    let own_share = match &key_share.share {
+        TwoPartyShare::One { private } => party_one_share(private)?,
+        TwoPartyShare::Two { x2, .. } => *x2,
+    };
+    let other_public = key_share.public_key * own_share.invert();
+    let (own_term, other_term) = (p2p_key_j * &own_share, other_public * &p2p_secret);
+    let authentication = match party_id {
+        1 => vec![own_term, other_term],
+        _ => vec![other_term, own_term],
+    };
+    let mut shared_secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    shared_secrets.insert(other, [vec![p2p_key_j * &p2p_secret], authentication].concat());
+    let channel = P2pChannel::new(party_num_int, &uuid, shared_secrets);
+
+    // we assume the message is already hashed (by the signer).
+    let message_int = BigInt::from_bytes(message);
+    let two = BigInt::from(2);
+    let message_bn = message_int.modulus(&two.pow(256));
+
+    let sig = match &key_share.share {
+        TwoPartyShare::One { private } => {
+            sign_party_one(transport, config, &channel, party_num_int, &uuid, private, &public_key, &message_bn).await?
+        }
+        TwoPartyShare::Two { x2, ek, encrypted_share } => {
+            // x1 * x2 + f = x2 * (x1 + f / x2), so x1 is moved to the derived key under the encryption
+            let encrypted_secret_share = match path.is_empty() {
+                true => encrypted_share.clone(),
+                false => {
+                    let tweak = f_l_new * x2.invert();
+                    let encrypted_tweak = Paillier::encrypt(ek, RawPlaintext::from(tweak.to_big_int()));
+                    BigInt::from(Paillier::add(ek, RawCiphertext::from(encrypted_share.clone()), encrypted_tweak))
+                }
+            };
+            let paillier_public = party_two::PaillierPublic { ek: ek.clone(), encrypted_secret_share };
+            sign_party_two(transport, config, &channel, party_num_int, &uuid, x2, &paillier_public, &message_bn).await?
+        }
+    };
+    verify(&sig, &public_key, &message_bn)
+        .map_err(|_| Error::Protocol("the signature does not verify against the public key".to_string()))?;
+
+    Ok(SignResult {
+        signature: sig,
+        public_key,
+        message: message_int,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn sign_party_one(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    channel: &P2pChannel,
+    party_num_int: u16,
+    uuid: &str,
+    private: &party_one::Party1Private,
+    public_key: &GE,
+    message_bn: &BigInt,
+) -> Result<SignatureRecid, Error> {
+    // round 1: once party 2 is committed to its nonce share, send ours with its dlog proof
+    let party_two_first_msg: party_two::EphKeyGenFirstMsg =
+        receive(transport, config, channel, party_num_int, "round1", uuid).await?;
+    let (first_msg, eph_key_pair) = party_one::EphKeyGenFirstMsg::create();
+    send(transport, channel, party_num_int, "round1", &first_msg, uuid).await?;
+
+    // round 2: get the opening of party 2's commitment and its encrypted part of the signature
+    let (second_msg, partial_sig): (party_two::EphKeyGenSecondMsg, party_two::PartialSig) =
+        receive(transport, config, channel, party_num_int, "round2", uuid).await?;
+    party_one::EphKeyGenSecondMsg::verify_commitments_and_dlog_proof(&party_two_first_msg, &second_msg)
+        .map_err(|_| Error::Protocol("party 2 sent an invalid nonce commitment opening".to_string()))?;
+    let sig = party_one::Signature::compute_with_recid(
+        private,
+        &partial_sig.c3,
+        &eph_key_pair,
+        &second_msg.comm_witness.public_share,
+    );
+
+    // round 3: send the signature, only if it verifies. A bad one would be computed from a ciphertext
+    // party 2 chose, and could tell it about x1
+    let sig = SignatureRecid {
+        r: ECScalar::from(&sig.r),
+        s: ECScalar::from(&sig.s),
+        recid: sig.recid,
+    };
+    verify(&sig, public_key, message_bn)
+        .map_err(|_| Error::Protocol("party 2 sent a partial signature that gives an invalid signature".to_string()))?;
+    send(transport, channel, party_num_int, "round3", &sig, uuid).await?;
+    Ok(sig)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn sign_party_two(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    channel: &P2pChannel,
+    party_num_int: u16,
+    uuid: &str,
+    x2: &FE,
+    paillier_public: &party_two::PaillierPublic, // Holds x1, moved to the derived key if signing at a path
+    message_bn: &BigInt,
+) -> Result<SignatureRecid, Error> {
+    let (_, ec_key_pair) = party_two::KeyGenFirstMsg::create_with_fixed_secret_share(*x2);
+    let private = party_two::Party2Private::set_private_key(&ec_key_pair);
+
+    // round 1: commit to our nonce share, get party 1's with its dlog proof
+    let (first_msg, eph_comm_witness, eph_key_pair) = party_two::EphKeyGenFirstMsg::create_commitments();
+    send(transport, channel, party_num_int, "round1", &first_msg, uuid).await?;
+    let party_one_first_msg: party_one::EphKeyGenFirstMsg =
+        receive(transport, config, channel, party_num_int, "round1", uuid).await?;
+
+    // round 2: open the commitment and send our part of the signature, encrypted under party 1's key
+    let second_msg = party_two::EphKeyGenSecondMsg::verify_and_decommit(eph_comm_witness, &party_one_first_msg)
+        .map_err(|_| Error::Protocol("party 1 sent an invalid nonce dlog proof".to_string()))?;
+    let partial_sig = party_two::PartialSig::compute(
+        &paillier_public.ek,
+        &paillier_public.encrypted_secret_share,
+        &private,
+        &eph_key_pair,
+        &party_one_first_msg.public_share,
+        message_bn,
+    );
+    send(transport, channel, party_num_int, "round2", &(second_msg, partial_sig), uuid).await?;
+
+    // round 3: get the signature
+    receive(transport, config, channel, party_num_int, "round3", uuid).await
+}
+
+// Party1Private keeps x1 to itself, it is read back from its keys file form
+fn party_one_share(private: &party_one::Party1Private) -> Result<FE, Error> {
+    serde_json::to_value(private).ok()
+        .and_then(|value| serde_json::from_value(value["x1"].clone()).ok())
+        .ok_or_else(|| Error::InvalidInput("Keys file of party 1 holds no x1".to_string()))
+}
+
+fn keygen_channel(party_num_int: u16, uuid: &str, shared_secrets: Vec<GE>) -> P2pChannel {
+    let mut secrets: HashMap<u16, Vec<GE>> = HashMap::new();
+    secrets.insert(3 - party_num_int, shared_secrets);
+    P2pChannel::new(party_num_int, uuid, secrets)
+}
+
+// Lindell 2017 messages go one way at a time. The first ones, public and carrying the p2p keys, are
+// broadcast, the others are sealed to the other party
+async fn publish<T: Serialize>(transport: &dyn Transport, party_num_int: u16, round: &str, message: &T, uuid: &str) -> Result<(), Error> {
+    broadcast(transport, party_num_int, round, serde_json::to_string(message).unwrap(), uuid.to_string()).await
+}
+
+async fn receive_public<T: DeserializeOwned>(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    party_num_int: u16,
+    round: &str,
+    uuid: &str,
+) -> Result<T, Error> {
+    let ans_vec = poll_for_broadcasts(transport, party_num_int, 2, config, round, uuid.to_string()).await?;
+    decode(round, &ans_vec[0])
+}
+
+async fn send<T: Serialize>(
+    transport: &dyn Transport,
+    channel: &P2pChannel,
+    party_num_int: u16,
+    round: &str,
+    message: &T,
+    uuid: &str,
+) -> Result<(), Error> {
+    let to = 3 - party_num_int;
+    let sealed = channel.seal(to, round, &serde_json::to_string(message).unwrap());
+    sendp2p(transport, party_num_int, to, round, sealed, uuid.to_string()).await
+}
+
+async fn receive<T: DeserializeOwned>(
+    transport: &dyn Transport,
+    config: &ClientConfig,
+    channel: &P2pChannel,
+    party_num_int: u16,
+    round: &str,
+    uuid: &str,
+) -> Result<T, Error> {
+    let ans_vec = poll_for_p2p(transport, party_num_int, 2, config, round, uuid.to_string()).await?;
+    decode(round, &channel.open_all(round, &ans_vec)?[0])
+}
@@ -0,0 +1,18 @@
+#![allow(non_snake_case)]
+
+extern crate curv;
+extern crate hex;
+extern crate multi_party_ecdsa;
+extern crate paillier;
+extern crate reqwest;
+extern crate serde_json;
+
+pub mod common;
+#[cfg(test)]
+mod test;
+
+pub use common::{hd_keys, ClientConfig, Config, Error, HttpTransport, KeyShare, ManagerConfig, Params, Protocol, SignerPolicy, Transport};
+pub use common::keygen::{run_keygen, run_keygen_async};
+pub use common::signer::{sign, sign_async, SignOptions, SignResult};
+pub use common::eddsa::EdKeyShare;
+pub use common::two_party::TwoPartyKeyShare;
@@ -2,33 +2,26 @@
 //#![feature(proc_macro_hygiene, decl_macro)]
 
 extern crate clap;
-extern crate curv;
 extern crate hex;
-extern crate multi_party_ecdsa;
-extern crate paillier;
-extern crate reqwest;
 extern crate serde_json;
 
 use std::fs;
+use std::io::{self, BufRead};
+use std::path::Path;
+use std::process;
 
-use clap::{App, AppSettings, Arg, SubCommand};
-use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use curv::arithmetic::Converter;
+use curv::elliptic::curves::secp256_k1::GE;
 use curv::elliptic::curves::traits::*;
-
-use curv::{
-    BigInt,
-    elliptic::curves::secp256_k1::{GE},
-    arithmetic::Converter
-};
-use curv::elliptic::curves::secp256_k1::FE;
-use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::*;
-use paillier::*;
 use serde_json::json;
+use tracing::{info, warn};
 
-use common::{hd_keys, keygen, manager, signer, Params};
-
-mod common;
-mod test;
+use tss_cli::common::key_share::{keys_file_protocol, KeyRecord};
+use tss_cli::common::schnorr::{x_only, TweakedKey};
+use tss_cli::common::recovery::{self, ShareUpdate};
+use tss_cli::common::{admin, backup, block_on, dealer, eddsa, init_logging, manager, schnorr, two_party, Curve};
+use tss_cli::{run_keygen, sign, ClientConfig, Config, EdKeyShare, HttpTransport, KeyShare, Params, Protocol, SignOptions, SignerPolicy, TwoPartyKeyShare};
 
 fn main() {
     let matches = App::new("TSS CLI Utility")
@@ -36,8 +29,42 @@ fn main() {
         .author("Kaspars Sprogis <darklow@gmail.com>")
 //        .about("")
         .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("log_level")
+            .long("log-level")
+            .global(true)
+            .takes_value(true)
+            .help("Log level or filter, e.g. debug (Optional, defaults to TSS_CLI_LOG env var or info)"))
+        .arg(Arg::with_name("log_json")
+            .long("log-json")
+            .global(true)
+            .help("Write logs to stderr as JSON lines"))
+        .arg(Arg::with_name("config")
+            .long("config")
+            .global(true)
+            .takes_value(true)
+            .help("Config file (Optional, defaults to TSS_CLI_CONFIG env var or tss.toml if present)"))
+        .arg(Arg::with_name("profile")
+            .long("profile")
+            .global(true)
+            .takes_value(true)
+            .help("Config profile (Optional, defaults to TSS_CLI_PROFILE env var or default)"))
         .subcommands(vec![
             SubCommand::with_name("manager").about("Run state manager"),
+            SubCommand::with_name("manager-status").about("List rooms held by the manager or force-expire one")
+                .arg(Arg::with_name("manager_addr")
+                    .short("a")
+                    .long("addr")
+                    .takes_value(true)
+                    .help("URL to manager"))
+                .arg(Arg::with_name("token")
+                    .short("t")
+                    .long("token")
+                    .takes_value(true)
+                    .help("Admin token (Optional, defaults to the manager admin_token setting)"))
+                .arg(Arg::with_name("expire")
+                    .long("expire")
+                    .takes_value(true)
+                    .help("Signing room id or session uuid to force-expire")),
             SubCommand::with_name("keygen").about("Run keygen")
                 .arg(Arg::with_name("keysfile")
                     .required(true)
@@ -49,150 +76,566 @@ fn main() {
                     .required(true)
                     .takes_value(true)
                     .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("protocol")
+                    .long("protocol")
+                    .takes_value(true)
+                    .default_value("gg18")
+                    .help("Protocol of the key, gg18, gg20 (identifiable abort) or lindell17 (2 of 2 only). Signing uses the one of the keys file"))
+                .arg(Arg::with_name("curve")
+                    .long("curve")
+                    .takes_value(true)
+                    .default_value("secp256k1")
+                    .help("Curve of the key, secp256k1 or ed25519. Ed25519 keys sign with FROST, --protocol does not apply"))
                 .arg(Arg::with_name("manager_addr")
                     .short("a")
                     .long("addr")
                     .takes_value(true)
                     .help("URL to manager. E.g. http://127.0.0.2:8002")),
-            SubCommand::with_name("pubkey").about("Get X,Y of a pub key")
-                .arg(Arg::with_name("keysfile")
+            SubCommand::with_name("import").about("Split an existing private key into the keys files of a threshold key")
+                .arg(Arg::with_name("private_key")
                     .required(true)
                     .index(1)
                     .takes_value(true)
-                    .help("Keys file"))
+                    .help("Private key in hex or WIF, or - to read it from stdin"))
+                .arg(Arg::with_name("params")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
+                .arg(Arg::with_name("keysfiles")
+                    .index(3)
+                    .required(true)
+                    .multiple(true)
+                    .help("Target keys files, one per party in party order")),
+            SubCommand::with_name("reconstruct").about("Disaster recovery: rebuild the full private key from threshold+1 keys files")
+                .arg(Arg::with_name("keysfiles")
+                    .required(true)
+                    .index(1)
+                    .multiple(true)
+                    .help("Keys files of at least threshold+1 distinct parties"))
                 .arg(Arg::with_name("path")
                     .short("p")
                     .long("path")
                     .takes_value(true)
-                    .help("Derivation path (Optional)")),
-            SubCommand::with_name("sign").about("Run signer")
+                    .help("Derivation path of the key to rebuild (Optional)"))
+                .arg(Arg::with_name("confirm")
+                    .long("i-understand-this-exposes-the-key")
+                    .help("Required: the key will exist in full on this machine and no threshold will protect it anymore")),
+            SubCommand::with_name("recover-share").about("Rebuild the share of a party that lost its keys file, with threshold+1 helpers")
                 .arg(Arg::with_name("keysfile")
                     .required(true)
                     .index(1)
                     .takes_value(true)
-                    .help("Keys file"))
+                    .help("Keys file of a helper, or target keys file with --new"))
                 .arg(Arg::with_name("params")
                     .index(2)
+                    .takes_value(true)
+                    .help("Threshold params: threshold/parties (t+1/n) of the key, required with --new"))
+                .arg(Arg::with_name("party")
+                    .long("party")
+                    .takes_value(true)
+                    .required_unless("apply")
+                    .help("Id of the party to recover"))
+                .arg(Arg::with_name("new")
+                    .long("new")
+                    .requires("params")
+                    .help("Run on the new device of the recovered party, prints the update for the other parties"))
+                .arg(Arg::with_name("device_key")
+                    .long("device-key")
+                    .takes_value(true)
+                    .required_unless_one(&["new", "apply"])
+                    .conflicts_with("new")
+                    .help("Hex key the new device logged, as it showed it to the helpers. Helpers abort on another key"))
+                .arg(Arg::with_name("apply")
+                    .long("apply")
+                    .takes_value(true)
+                    .conflicts_with_all(&["new", "party", "device_key"])
+                    .help("Write the update printed by a recovered party in the keys file, for parties that did not help"))
+                .arg(Arg::with_name("room")
+                    .long("room")
+                    .takes_value(true)
+                    .help("Recovery room id shared by the helpers and the new device (Optional, defaults to recover-share-<party>)"))
+                .arg(Arg::with_name("manager_addr")
+                    .short("a")
+                    .long("addr")
+                    .takes_value(true)
+                    .help("URL to manager")),
+            SubCommand::with_name("backup").about("Split the secrets of a keys file into word lists, any threshold of which restore it")
+                .arg(Arg::with_name("keysfile")
                     .required(true)
+                    .index(1)
                     .takes_value(true)
-                    .help("Threshold params: threshold/parties (t+1/n). E.g. 1/3 for 2 of 3 schema."))
-                .arg(Arg::with_name("message")
+                    .help("Keys file to back up, left unchanged"))
+                .arg(Arg::with_name("shares")
+                    .index(2)
+                    .required(true)
+                    .takes_value(true)
+                    .help("Word lists needed and written: threshold/shares, e.g. 2/3"))
+                .arg(Arg::with_name("record")
+                    .long("record")
+                    .required(true)
+                    .takes_value(true)
+                    .help("Target file of the public keygen record, needed to restore")),
+            SubCommand::with_name("restore").about("Rebuild a keys file from the word lists of its backup and the public keygen record")
+                .arg(Arg::with_name("keysfile")
+                    .required(true)
+                    .index(1)
+                    .takes_value(true)
+                    .help("Target keys file"))
+                .arg(Arg::with_name("record")
+                    .required(true)
+                    .index(2)
+                    .takes_value(true)
+                    .help("Public keygen record written by backup"))
+                .arg(Arg::with_name("words")
                     .index(3)
+                    .multiple(true)
+                    .help("Word lists, each quoted (Optional, read from stdin one per line otherwise)")),
+            SubCommand::with_name("pubkey").about("Get X,Y of a pub key")
+                .arg(Arg::with_name("keysfile")
                     .required(true)
+                    .index(1)
                     .takes_value(true)
-                    .help("Message to sign in hex format"))
+                    .help("Keys file"))
                 .arg(Arg::with_name("path")
                     .short("p")
                     .long("path")
                     .takes_value(true)
-                    .help("Derivation path"))
-                .arg(Arg::with_name("manager_addr")
-                    .short("a")
-                    .long("addr")
-                    .takes_value(true)
-                    .help("URL to manager"))
+                    .help("Derivation path (Optional)"))
+                .args(&taproot_args()),
+            sign_subcommand("sign", "Run signer")
+                .arg(Arg::with_name("allow_legacy_keys")
+                    .long("allow-legacy-keys")
+                    .help("Sign with a keys file without h1, h2, N~, i.e. without range proofs. Every signer must pass it")),
+            sign_subcommand("sign-schnorr", "Run FROST signer for a BIP340 Schnorr signature")
+                .args(&taproot_args()),
         ])
         .get_matches();
 
+    init_logging(
+        global_value(&matches, "log_level"),
+        matches.is_present("log_json") || matches.subcommand().1.map_or(false, |x| x.is_present("log_json")),
+    );
+
+    let config = Config::load(global_value(&matches, "config"), global_value(&matches, "profile"))
+        .and_then(|mut config| {
+            // Flags override the config file
+            if let Some(addr) = matches.subcommand().1.and_then(|x| x.value_of("manager_addr")) {
+                config.client.manager_addr = addr.to_string();
+            }
+            config.validate().map(|_| config)
+        });
+    let mut config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
     match matches.subcommand() {
-        ("pubkey", Some(sub_matches)) | ("sign", Some(sub_matches)) => {
+        ("pubkey", Some(sub_matches)) | ("sign", Some(sub_matches)) | ("sign-schnorr", Some(sub_matches))
+            if keys_file_protocol(sub_matches.value_of("keysfile").unwrap_or("")) == Ok(Protocol::FrostEd25519) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let key_share = EdKeyShare::load(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            if let Err(error) = key_share.validate() {
+                fail(&format!("Invalid keys file {}: {}", keysfile_path, error));
+            }
+            if taproot_option(sub_matches).is_some() || matches.subcommand_name() == Some("sign-schnorr") {
+                fail("Ed25519 keys only sign Ed25519, with sign");
+            }
+            if sub_matches.is_present("path") {
+                fail("Ed25519 keys have no derivation paths");
+            }
+
+            if matches.subcommand_name() == Some("pubkey") {
+                println!("{}", json!({"pubkey": key_share.public_key_hex(), "path": ""}).to_string());
+            } else {
+                let args: Vec<&str> = sub_matches.values_of("params_message").unwrap().collect();
+                let (params, message_str) = match args.as_slice() {
+                    [params, message] => (Params::parse(params).unwrap_or_else(|e| fail(&e)), *message),
+                    _ => (key_share.params(), args[0]),
+                };
+                if let Err(error) = key_share.check_params(&params) {
+                    fail(&error);
+                }
+                let message = parse_message(message_str);
+                let options = sign_options(sub_matches);
+                let transport = http_transport(&config.client);
+                let result = eddsa::sign(&transport, &config.client, &key_share, &params, &options, &message).unwrap_or_else(|e| fail(&e.to_string()));
+                println!("{}", result.to_json().to_string());
+            }
+        }
+        ("pubkey", Some(sub_matches)) | ("sign", Some(sub_matches)) | ("sign-schnorr", Some(sub_matches))
+            if keys_file_protocol(sub_matches.value_of("keysfile").unwrap_or("")) == Ok(Protocol::Lindell17) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
+            let key_share = TwoPartyKeyShare::load(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            if let Err(error) = key_share.validate() {
+                fail(&format!("Invalid keys file {}: {}", keysfile_path, error));
+            }
+            if taproot_option(sub_matches).is_some() || matches.subcommand_name() == Some("sign-schnorr") {
+                fail("Lindell17 keys only sign ECDSA");
+            }
+
+            let path = sub_matches.value_of("path").unwrap_or("");
+            if matches.subcommand_name() == Some("pubkey") {
+                let (_f_l_new, public_key) = key_share.derive(path).unwrap_or_else(|e| fail(&e));
+                println!("{}", pubkey_json(&public_key, path));
+            } else {
+                let args: Vec<&str> = sub_matches.values_of("params_message").unwrap().collect();
+                if let [params, _] = args.as_slice() {
+                    let params = Params::parse(params).unwrap_or_else(|e| fail(&e));
+                    if params.values() != Ok((1, 2)) {
+                        fail("Lindell17 keys sign with threshold params 1/2");
+                    }
+                }
+                let message = parse_message(args[args.len() - 1]);
+                let options = sign_options(sub_matches);
+                let transport = http_transport(&config.client);
+                let result = two_party::sign(&transport, &config.client, &key_share, &options, &message, path).unwrap_or_else(|e| fail(&e.to_string()));
+                println!("{}", result.to_json().to_string());
+            }
+        }
+        ("pubkey", Some(sub_matches)) | ("sign", Some(sub_matches)) | ("sign-schnorr", Some(sub_matches)) => {
             let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("");
 
             // Read data from keys file
-            let data = fs::read_to_string(keysfile_path).expect(
-                format!("Unable to load keys file at location: {}", keysfile_path).as_str(),
-            );
-            let (party_keys, shared_keys, party_id, mut vss_scheme_vec, paillier_key_vector, y_sum): (
-                Keys,
-                SharedKeys,
-                u16,
-                Vec<VerifiableSS<GE>>,
-                Vec<EncryptionKey>,
-                GE,
-            ) = serde_json::from_str(&data).unwrap();
-
-            // Get root pub key or HD pub key at specified path
+            let key_share = KeyShare::load(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            if let Err(error) = key_share.validate() {
+                fail(&format!("Invalid keys file {}: {}", keysfile_path, error));
+            }
+
             let path = sub_matches.value_of("path").unwrap_or("");
-            let (f_l_new, y_sum) = match path.is_empty() {
-                true => (ECScalar::zero(), y_sum),
-                false => call_hd_key(path, y_sum)
-            };
 
             // Return pub key as x,y
             if let Some(_sub_matches) = matches.subcommand_matches("pubkey") {
-                let ret_dict = json!({
-                    "x": &y_sum.x_coor(),
-                    "y": &y_sum.y_coor(),
-                    "path": path,
-                });
+                // Get root pub key or HD pub key at specified path
+                let (_f_l_new, y_sum) = key_share.derive(path).unwrap_or_else(|e| fail(&e));
+                let mut ret_dict = pubkey_json(&y_sum, path);
+                if let Some(taproot) = taproot_option(sub_matches) {
+                    let key = TweakedKey::new(&y_sum, Some(&taproot)).unwrap_or_else(|e| fail(&e));
+                    ret_dict["taproot_output_key"] = json!(hex::encode(x_only(&key.output_key)));
+                }
                 println!("{}", ret_dict.to_string());
-            } else if let Some(sub_matches) = matches.subcommand_matches("sign") {
-                // Parse message to sign
-                let message_str = sub_matches.value_of("message").unwrap_or("");
-                let message = match hex::decode(message_str.clone()) {
-                    Ok(x) => x,
-                    Err(_e) => message_str.as_bytes().to_vec(),
-                };
-                let message = &message[..];
-                let manager_addr = sub_matches
-                    .value_of("manager_addr")
-                    .unwrap_or("http://127.0.0.1:8001")
-                    .to_string();
-
-                // Parse threshold params
-                let params: Vec<&str> = sub_matches
-                    .value_of("params")
-                    .unwrap_or("")
-                    .split("/")
-                    .collect();
-                //            println!("sign me {:?} / {:?} / {:?}", manager_addr, message, params);
-                let params = Params {
-                    threshold: params[0].to_string(),
-                    parties: params[1].to_string(),
+            } else {
+                let args: Vec<&str> = sub_matches.values_of("params_message").unwrap().collect();
+                let (params, message_str) = match args.as_slice() {
+                    [params, message] => (Params::parse(params).unwrap_or_else(|e| fail(&e)), *message),
+                    _ => (key_share.params(), args[0]),
                 };
-                signer::sign(
-                    manager_addr,
-                    party_keys,
-                    shared_keys,
-                    party_id,
-                    &mut vss_scheme_vec,
-                    paillier_key_vector,
-                    &y_sum,
-                    &params,
-                    &message,
-                    &f_l_new,
-                    !path.is_empty(),
-                )
+                if let Err(error) = key_share.check_params(&params) {
+                    fail(&error);
+                }
+
+                let message = parse_message(message_str);
+                let mut options = sign_options(sub_matches);
+                let transport = http_transport(&config.client);
+                if matches.subcommand_name() == Some("sign-schnorr") {
+                    options.taproot = taproot_option(sub_matches);
+                    let result = schnorr::sign(&transport, &config.client, &key_share, &params, &options, &message, path).unwrap_or_else(|e| fail(&e.to_string()));
+                    println!("{}", result.to_json().to_string());
+                } else {
+                    let result = sign(&transport, &config.client, &key_share, &params, &options, &message, path).unwrap_or_else(|e| fail(&e.to_string()));
+                    println!("{}", result.to_json().to_string());
+                }
             }
         }
         ("manager", Some(_matches)) => {
-            manager::run_manager();
+            manager::run_manager(config.manager);
+        }
+        ("manager-status", Some(sub_matches)) => {
+            let token = sub_matches.value_of("token").map(String::from)
+                .or(config.manager.admin_token.clone());
+            if token.is_some() {
+                config.client.auth_token = token;
+            }
+            let transport = http_transport(&config.client);
+            let ret_dict = match sub_matches.value_of("expire") {
+                Some(room) => block_on(admin::expire_room(&transport, room)).map(|x| json!(x)),
+                None => block_on(admin::manager_status(&transport)).map(|x| json!(x)),
+            }
+            .unwrap_or_else(|e| fail(&e.error));
+            println!("{}", serde_json::to_string_pretty(&ret_dict).unwrap());
+        }
+        ("import", Some(sub_matches)) => {
+            let params = Params::parse(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| fail(&e));
+            let (threshold, parties) = params.values().unwrap_or_else(|e| fail(&e));
+            let keysfile_paths: Vec<&str> = sub_matches.values_of("keysfiles").unwrap().collect();
+            if keysfile_paths.len() != parties as usize {
+                fail(&format!("Expected {} keys files, one per party, got {}", parties, keysfile_paths.len()));
+            }
+            let private_key = match sub_matches.value_of("private_key").unwrap() {
+                "-" => {
+                    let mut line = String::new();
+                    io::stdin().read_line(&mut line).unwrap_or_else(|e| fail(&e.to_string()));
+                    line
+                }
+                value => value.to_string(),
+            };
+            let secret = dealer::parse_private_key(&private_key).unwrap_or_else(|e| fail(&e));
+            let key_shares = dealer::split_key(&secret, threshold, parties).unwrap_or_else(|e| fail(&e));
+            for (key_share, keysfile_path) in key_shares.iter().zip(keysfile_paths) {
+                key_share.save(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+                info!(keysfile = keysfile_path, party = key_share.party_id, "Keys data written to file");
+            }
+            warn!("All shares were written on this machine: move each keys file to its party and erase them and the private key here");
+            println!("{}", pubkey_json(&key_shares[0].y_sum, ""));
+        }
+        ("reconstruct", Some(sub_matches)) => {
+            if !sub_matches.is_present("confirm") {
+                fail("reconstruct puts the whole private key on this machine, defeating the threshold. \
+                      Only use it when the parties can no longer sign, and pass --i-understand-this-exposes-the-key");
+            }
+            let keysfile_paths: Vec<&str> = sub_matches.values_of("keysfiles").unwrap().collect();
+            let key_shares = keysfile_paths.iter()
+                .map(|keysfile_path| load_ecdsa_key_share(keysfile_path))
+                .collect::<Vec<KeyShare>>();
+            let path = sub_matches.value_of("path").unwrap_or("");
+            let secret = dealer::reconstruct_key(&key_shares, path).unwrap_or_else(|e| fail(&e));
+            let (_f_l_new, public_key) = key_shares[0].derive(path).unwrap_or_else(|e| fail(&e));
+            warn!("THE FULL PRIVATE KEY IS PRINTED BELOW. Anyone who sees it can spend without the other parties");
+            warn!("Move the funds to a new key now, then erase this output, the keys files used and the shell history");
+            let mut ret_dict = pubkey_json(&public_key, path);
+            ret_dict["private_key"] = json!(format!("{:0>64}", secret.to_big_int().to_hex()));
+            ret_dict["wif"] = json!(dealer::encode_wif(&secret));
+            println!("{}", ret_dict.to_string());
+        }
+        ("recover-share", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap();
+            if let Some(update_path) = sub_matches.value_of("apply") {
+                let mut key_share = load_ecdsa_key_share(keysfile_path);
+                let data = fs::read_to_string(update_path).unwrap_or_else(|e| fail(&e.to_string()));
+                let update: ShareUpdate = serde_json::from_str(&data).unwrap_or_else(|e| fail(&e.to_string()));
+                update.apply(&mut key_share).unwrap_or_else(|e| fail(&e));
+                key_share.save(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+                info!(keysfile = keysfile_path, party = update.party_id, "New keys of the recovered party written to file");
+                return;
+            }
+            let party_id = sub_matches.value_of("party").unwrap().parse::<u16>().unwrap_or_else(|e| fail(&e.to_string()));
+            let room_id = sub_matches.value_of("room").map(String::from);
+            let transport = http_transport(&config.client);
+            if sub_matches.is_present("new") {
+                if Path::new(keysfile_path).exists() {
+                    fail(&format!("Keys file {} exists, the recovered share is not written over it", keysfile_path));
+                }
+                let params = Params::parse(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| fail(&e));
+                let (key_share, update) = recovery::recover(&transport, &config.client, &params, party_id, room_id).unwrap_or_else(|e| fail(&e.to_string()));
+                key_share.save(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+                info!(keysfile = keysfile_path, "Keys data written to file");
+                warn!("Parties that did not help must write the update below in their keys files with --apply");
+                println!("{}", serde_json::to_string(&update).unwrap());
+                return;
+            }
+            let device_key = recovery::parse_device_key(sub_matches.value_of("device_key").unwrap()).unwrap_or_else(|e| fail(&e));
+            let key_share = load_ecdsa_key_share(keysfile_path);
+            let key_share = recovery::help_recover(&transport, &config.client, &key_share, party_id, &device_key, room_id).unwrap_or_else(|e| fail(&e.to_string()));
+            key_share.save(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            info!(keysfile = keysfile_path, party = party_id, "New keys of the recovered party written to file");
+        }
+        ("backup", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap();
+            let (threshold, shares) = match sub_matches.value_of("shares").unwrap().split('/').collect::<Vec<_>>()[..] {
+                [threshold, shares] => match (threshold.parse::<u8>(), shares.parse::<u8>()) {
+                    (Ok(threshold), Ok(shares)) => (threshold, shares),
+                    _ => fail("Word lists must be given as threshold/shares, e.g. 2/3"),
+                },
+                _ => fail("Word lists must be given as threshold/shares, e.g. 2/3"),
+            };
+            let key_share = load_ecdsa_key_share(keysfile_path);
+            let word_lists = backup::backup(&key_share, threshold, shares).unwrap_or_else(|e| fail(&e));
+            let record_path = sub_matches.value_of("record").unwrap();
+            fs::write(record_path, serde_json::to_string(&key_share.record()).unwrap())
+                .unwrap_or_else(|e| fail(&e.to_string()));
+            info!(keysfile = keysfile_path, record = record_path, "Public keygen record written");
+            warn!("Write down each word list and keep them apart: any {} of them rebuild this keys file", threshold);
+            let ret_dict = json!({
+                "party_id": key_share.party_id,
+                "shares": word_lists,
+            });
+            println!("{}", serde_json::to_string_pretty(&ret_dict).unwrap());
+        }
+        ("restore", Some(sub_matches)) => {
+            let keysfile_path = sub_matches.value_of("keysfile").unwrap();
+            if Path::new(keysfile_path).exists() {
+                fail(&format!("Keys file {} exists, the restored one is not written over it", keysfile_path));
+            }
+            let data = fs::read_to_string(sub_matches.value_of("record").unwrap()).unwrap_or_else(|e| fail(&e.to_string()));
+            let record: KeyRecord = serde_json::from_str(&data).unwrap_or_else(|e| fail(&e.to_string()));
+            let word_lists: Vec<String> = match sub_matches.values_of("words") {
+                Some(values) => values.map(String::from).collect(),
+                None => io::stdin().lock().lines()
+                    .map(|line| line.unwrap_or_else(|e| fail(&e.to_string())))
+                    .filter(|line| !line.trim().is_empty())
+                    .collect(),
+            };
+            let (key_share, update) = backup::restore(record, &word_lists).unwrap_or_else(|e| fail(&e));
+            key_share.save(keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            info!(keysfile = keysfile_path, party = key_share.party_id, "Keys data written to file");
+            warn!("The restored party has new Paillier keys, the other parties must write the update below with recover-share --apply");
+            println!("{}", serde_json::to_string(&update).unwrap());
         }
         ("keygen", Some(sub_matches)) => {
-            let addr = sub_matches
-                .value_of("manager_addr")
-                .unwrap_or("http://127.0.0.1:8001")
-                .to_string();
             let keysfile_path = sub_matches.value_of("keysfile").unwrap_or("").to_string();
 
-            let params: Vec<&str> = sub_matches
-                .value_of("params")
-                .unwrap_or("")
-                .split("/")
-                .collect();
-            keygen::run_keygen(&addr, &keysfile_path, &params);
+            let params = Params::parse(sub_matches.value_of("params").unwrap()).unwrap_or_else(|e| fail(&e));
+            let (threshold, parties) = params.values().unwrap_or_else(|e| fail(&e));
+            let protocol: Protocol = sub_matches.value_of("protocol").unwrap().parse().unwrap_or_else(|e: String| fail(&e));
+            let curve: Curve = sub_matches.value_of("curve").unwrap().parse().unwrap_or_else(|e: String| fail(&e));
+            let transport = http_transport(&config.client);
+            if curve == Curve::Ed25519 {
+                if sub_matches.occurrences_of("protocol") > 0 {
+                    fail("Ed25519 keys use FROST, --protocol does not apply");
+                }
+                let key_share = eddsa::run_keygen(&transport, &config.client, threshold, parties).unwrap_or_else(|e| fail(&e.to_string()));
+                key_share.save(&keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+                info!(keysfile = keysfile_path.as_str(), "Keys data written to file");
+                return;
+            }
+            if protocol == Protocol::Lindell17 {
+                if (threshold, parties) != (1, 2) {
+                    fail("Lindell17 keys are 2 of 2, use threshold params 1/2");
+                }
+                let key_share = two_party::run_keygen(&transport, &config.client).unwrap_or_else(|e| fail(&e.to_string()));
+                key_share.save(&keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+                info!(keysfile = keysfile_path.as_str(), "Keys data written to file");
+                return;
+            }
+            let key_share = run_keygen(&transport, &config.client, threshold, parties, protocol).unwrap_or_else(|e| fail(&e.to_string()));
+            key_share.save(&keysfile_path).unwrap_or_else(|e| fail(&e.to_string()));
+            info!(keysfile = keysfile_path.as_str(), "Keys data written to file");
         }
         _ => {}
     }
 }
 
-fn call_hd_key(path: &str, public_key: GE) -> (FE, GE) {
+fn sign_subcommand<'a, 'b>(name: &str, about: &'a str) -> App<'a, 'b> {
+    SubCommand::with_name(name).about(about)
+        .arg(Arg::with_name("keysfile")
+            .required(true)
+            .index(1)
+            .takes_value(true)
+            .help("Keys file"))
+        .arg(Arg::with_name("params_message")
+            .index(2)
+            .required(true)
+            .multiple(true)
+            .max_values(2)
+            .value_name("[params] message")
+            .help("Threshold params: threshold/parties (t+1/n), e.g. 1/3 for 2 of 3 schema (Optional, defaults to the keys file), then the message to sign in hex format"))
+        .arg(Arg::with_name("path")
+            .short("p")
+            .long("path")
+            .takes_value(true)
+            .help("Derivation path"))
+        .arg(Arg::with_name("room")
+            .long("room")
+            .takes_value(true)
+            .help("Signing room or request id shared by the signers (Optional, defaults to the message hash)"))
+        .arg(Arg::with_name("signers")
+            .long("signers")
+            .takes_value(true)
+            .help("Comma separated party indices allowed to sign, e.g. 1,2,4 (Optional, defaults to any party)"))
+        .arg(Arg::with_name("prefer")
+            .long("prefer")
+            .takes_value(true)
+            .help("Comma separated party indices picked first, others only replace missing ones (Optional)"))
+        .arg(Arg::with_name("fallback_after")
+            .long("fallback-after")
+            .takes_value(true)
+            .help("Seconds to wait for preferred parties before picking others (Optional, defaults to 10)"))
+        .arg(Arg::with_name("manager_addr")
+            .short("a")
+            .long("addr")
+            .takes_value(true)
+            .help("URL to manager"))
+}
+
+fn taproot_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("taproot")
+            .long("taproot")
+            .help("Use the BIP341 output key of the (derived) key, without script tree"),
+        Arg::with_name("merkle_root")
+            .long("merkle-root")
+            .takes_value(true)
+            .help("Use the BIP341 output key committing to this script tree merkle root, in hex"),
+    ]
+}
+
+// Global args are given either before or after the subcommand
+fn global_value<'a>(matches: &'a ArgMatches<'a>, name: &str) -> Option<&'a str> {
+    matches.subcommand().1
+        .and_then(|x| x.value_of(name))
+        .or(matches.value_of(name))
+}
 
-    let path_vector: Vec<BigInt> = path
-        .split('/')
-        .map(|s| BigInt::from_str_radix(s.trim(), 10).unwrap())
-        .collect();
-    let (public_key_child, f_l_new) = hd_keys::get_hd_key(&public_key, path_vector.clone());
-    (f_l_new, public_key_child.clone())
+fn pubkey_json(public_key: &GE, path: &str) -> serde_json::Value {
+    json!({
+        "x": &public_key.x_coor(),
+        "y": &public_key.y_coor(),
+        "path": path,
+    })
+}
 
-}
\ No newline at end of file
+// Hex if it decodes, the raw bytes otherwise
+fn parse_message(message_str: &str) -> Vec<u8> {
+    match hex::decode(message_str) {
+        Ok(x) => x,
+        Err(_e) => message_str.as_bytes().to_vec(),
+    }
+}
+
+fn sign_options(sub_matches: &ArgMatches) -> SignOptions {
+    let preferred = party_list(sub_matches.value_of("prefer"));
+    let policy = SignerPolicy {
+        allowed: party_list(sub_matches.value_of("signers")),
+        fallback_after: match preferred.is_empty() {
+            true => 0,
+            false => sub_matches.value_of("fallback_after").map_or(10, |x| x.parse::<u64>().unwrap_or_else(|_| {
+                fail(&format!("Invalid --fallback-after {:?}, expected a number of seconds", x))
+            })),
+        },
+        preferred,
+    };
+    SignOptions {
+        room_id: sub_matches.value_of("room").map(String::from),
+        policy,
+        taproot: None,
+        allow_legacy_keys: sub_matches.is_present("allow_legacy_keys"),
+    }
+}
+
+// BIP341 merkle root: given, empty for --taproot alone, or None for a plain key
+fn taproot_option(sub_matches: &ArgMatches) -> Option<Vec<u8>> {
+    match sub_matches.value_of("merkle_root") {
+        Some(root) => match hex::decode(root) {
+            Ok(root) if root.len() == 32 => Some(root),
+            _ => fail(&format!("Merkle root {:?} is not 32 bytes of hex", root)),
+        },
+        None if sub_matches.is_present("taproot") => Some(vec![]),
+        None => None,
+    }
+}
+
+fn party_list(value: Option<&str>) -> Vec<u16> {
+    value.map_or(vec![], |x| x.split(',').map(|i| i.trim().parse::<u16>().unwrap_or_else(|_| {
+        fail(&format!("Invalid party list {:?}, expected party numbers separated by commas", x))
+    })).collect())
+}
+
+// Keys files of gg18 and gg20 keys only, the ones with VSS shares of secp256k1 ECDSA keys
+fn load_ecdsa_key_share(keysfile_path: &str) -> KeyShare {
+    match keys_file_protocol(keysfile_path).unwrap_or_else(|e| fail(&e.to_string())) {
+        Protocol::Gg18 | Protocol::Gg20 => KeyShare::load(keysfile_path).unwrap_or_else(|e| fail(&e.to_string())),
+        protocol => fail(&format!("Keys file {} holds {} keys, only gg18 and gg20 ones have this command", keysfile_path, protocol)),
+    }
+}
+
+fn http_transport(config: &ClientConfig) -> HttpTransport {
+    HttpTransport::from_config(config).unwrap_or_else(|e| fail(&e.to_string()))
+}
+
+fn fail(error: &str) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
@@ -1,25 +1,528 @@
-#[cfg(test)]
-mod tests {
-    use curv::arithmetic::Converter;
-    use curv::BigInt;
-    use curv::elliptic::curves::traits::ECPoint;
-    use crate::{call_hd_key, GE};
-
-    #[test]
-    fn test_pubkey() {
-        let original_x = BigInt::from_hex(
-            "d6f3c325eb3fda7061983141278484c0dd452a6702fd537b89c09ddf2b6f3238").unwrap();
-        let original_y = BigInt::from_hex(
-            "4e12adae75c29b29cc094fd3d94aa401ea646104f0d1ae3c59f710ec92640e21").unwrap();
-        let original_public_key: GE = GE::from_coor(&original_x, &original_y);
+use async_trait::async_trait;
+use curv::arithmetic::{Converter, Modulo};
+use curv::BigInt;
+use curv::cryptographic_primitives::secret_sharing::feldman_vss::VerifiableSS;
+use curv::elliptic::curves::secp256_k1::GE;
+use std::collections::HashMap;
+use curv::elliptic::curves::secp256_k1::FE;
+use curv::elliptic::curves::traits::{ECPoint, ECScalar};
+use crate::common::hd_keys::call_hd_key;
+use crate::common::backup::{backup, decode_shares, encode_shares, restore, BackupSecret, WORDS_PER_SHARE};
+use crate::common::{block_on, split_round_key, ClientConfig, Config, Curve, Entry, Error, KeyShare, ManagerConfig, ManagerError, Params, Protocol, Transport};
+use crate::common::keygen_room::KeygenRoom;
+use crate::common::limits::Limits;
+use crate::common::manager::build_manager;
+use crate::common::eddsa::{hash_to_scalar, scalar_le_bytes};
+use crate::common::dealer::{encode_wif, parse_private_key, split_key};
+use crate::common::keygen::{create_dlog_statement, verify_paillier_keys, verify_paillier_moduli};
+use crate::common::p2p::P2pChannel;
+use crate::common::paillier_keys::{blum_paillier_keys, BlumModulusProof};
+use crate::common::recovery::{lagrange_at, parse_device_key, write_keys, ShareUpdate};
+use crate::common::schnorr::{x_only, TweakedKey};
+use crate::common::signer::{self, SignOptions};
+use crate::common::signing_room::{SignerPolicy, SigningRoom};
+use crate::common::two_party;
+use multi_party_ecdsa::protocols::multi_party_ecdsa::gg_2018::party_i::{verify, SignBroadcastPhase1};
+use multi_party_ecdsa::utilities::mta::MessageA;
+use paillier::{EncryptionKey, Keypair};
+use rocket::futures::future::join_all;
+use rocket::http::ContentType;
+use rocket::local::asynchronous::Client;
+use zk_paillier::zkproofs::NICorrectKeyProof;
 
+#[test]
+fn test_pubkey() {
+    let original_x = BigInt::from_hex(
+        "d6f3c325eb3fda7061983141278484c0dd452a6702fd537b89c09ddf2b6f3238").unwrap();
+    let original_y = BigInt::from_hex(
+        "4e12adae75c29b29cc094fd3d94aa401ea646104f0d1ae3c59f710ec92640e21").unwrap();
+    let original_public_key: GE = GE::from_coor(&original_x, &original_y);
+
+    let path = "1/2/3";
+    let expected_pubkey_x = "e891363052c09185814e92ce7a1a1946631dc53d058a01176fcf27a66b5674c2";
+    let expected_pubkey_y = "cfbe0a84b7f7c49b5bb2a48999a761fc6c5dd6526aa79a58d4029865ef7d4a17";
+    let (_f_l_new, public_key_child, ) = call_hd_key(path, original_public_key).unwrap();
+
+    assert_eq!(public_key_child.x_coor().unwrap().to_hex(), expected_pubkey_x);
+    assert_eq!(public_key_child.y_coor().unwrap().to_hex(), expected_pubkey_y);
+}
+
+#[test]
+fn test_config_profiles() {
+    let data = r#"
+        [default.client]
+        manager_addr = "http://10.0.0.1:8001"
+        poll_timeout = 45
+
+        [production.client]
+        manager_addr = "https://tss.example.com"
+    "#;
+
+    let config = Config::from_toml(data, "production").unwrap();
+    assert_eq!(config.client.manager_addr, "https://tss.example.com");
+    assert_eq!(config.client.poll_timeout, 45);
+
+    assert!(Config::from_toml(data, "staging").is_err());
+    assert!(Config::from_toml("[default.client]\npoll_delay = \"fast\"", "default").is_err());
+    assert!(Config::from_toml("[default.client]\npoll_dealy = 10", "default").is_err());
+}
+
+#[test]
+fn test_manager_limits() {
+    let config = ManagerConfig {
+        max_value_size: 8,
+        max_session_entries: 2,
+        max_rooms: 1,
+        client_rate_limit: 2,
+        ..ManagerConfig::default()
+    };
+    let limits = Limits::new(&config);
+    let uuid = "6f1c1e36-0d2b-4c6e-9a4c-2b7e0b5f8a11";
+    let other_uuid = "0b9e6c1a-7f3d-4e2a-8c5b-1d4f6a2e9c30";
+
+    assert!(limits.check_entry(&format!("1-round1-{}", uuid), "value", true).is_ok());
+    assert!(limits.check_entry(&format!("1-round1-{}", uuid), "value", false).is_ok());
+    assert!(limits.check_entry("arbitrary-key", "value", true).is_err());
+    assert!(limits.check_entry(&format!("2-round1-{}", uuid), "too large value", true).is_err());
+    assert!(limits.check_entry(&format!("2-round1-{}", uuid), "value", true).is_ok());
+    assert!(limits.check_entry(&format!("3-round1-{}", uuid), "value", true).is_err());
+    assert!(limits.check_entry(&format!("1-round1-{}", other_uuid), "value", true).is_err());
+
+    assert!(limits.check_rate("127.0.0.1", uuid).is_ok());
+    assert!(limits.check_rate("127.0.0.1", uuid).is_ok());
+    assert!(limits.check_rate("127.0.0.1", uuid).is_err());
+    assert!(limits.check_rate("127.0.0.2", uuid).is_ok());
+}
+
+#[test]
+fn test_split_round_key() {
+    let uuid = "6f1c1e36-0d2b-4c6e-9a4c-2b7e0b5f8a11";
+    assert_eq!(split_round_key(&format!("1-round1-{}", uuid)), Some(("round1", uuid)));
+    assert_eq!(split_round_key(&format!("1-2-round3-{}", uuid)), Some(("round3", uuid)));
+    assert_eq!(split_round_key("round1"), None);
+    assert_eq!(split_round_key(""), None);
+    assert_eq!(split_round_key(&"é".repeat(20)), None);
+    assert_eq!(split_round_key(&format!("1-round1{}", uuid)), None);
+    assert_eq!(split_round_key(&format!("{}-{}", "x".repeat(128), uuid)), None);
+}
+
+#[test]
+fn test_signer_policy() {
+    let policy = SignerPolicy {
+        allowed: vec![1, 2, 3],
+        preferred: vec![1, 2],
+        fallback_after: 60,
+    };
+    assert!(policy.validate(2).is_ok());
+    assert!(policy.validate(4).is_err());
+    assert!(!policy.admits(4));
+
+    let mut room = SigningRoom::new("room".to_string(), 2, Protocol::Gg18, 30, policy);
+    room.add_party(3);
+    room.add_party(1);
+    room.update_ping(1);
+    assert_eq!(room.last_stage, "signup"); // Party 3 may not replace party 2 yet
+
+    room.add_party(2);
+    let signup = room.update_ping(2);
+    assert_eq!(room.last_stage, "terminated");
+    assert_eq!(signup.party_order, 2);
+    assert!(room.member_info.contains_key(&1) && !room.member_info.contains_key(&3));
+}
+
+#[test]
+fn test_params() {
+    assert_eq!(Params::parse("1/3").unwrap().values(), Ok((1, 3)));
+    assert!(Params::parse("1").is_err());
+    assert!(Params::parse("3/3").is_err());
+    assert!(Params::parse("0/2").is_err());
+    assert!(Params::parse("a/3").is_err());
+    assert!(Params::parse("1/2/3").is_err());
+
+    assert_eq!("GG20".parse::<Protocol>(), Ok(Protocol::Gg20));
+    assert_eq!(Protocol::Lindell17.to_string().parse::<Protocol>(), Ok(Protocol::Lindell17));
+    assert_eq!(serde_json::to_string(&Protocol::FrostEd25519).unwrap(), format!("{:?}", Protocol::FrostEd25519.to_string()));
+    assert_eq!("Ed25519".parse::<Curve>(), Ok(Curve::Ed25519));
+    assert!("secp256r1".parse::<Curve>().unwrap_err().contains("not supported"));
+    assert_eq!(Protocol::default().to_string(), "gg18");
+    assert!("gg19".parse::<Protocol>().is_err());
+}
+
+#[test]
+fn test_keygen_room() {
+    let mut room = KeygenRoom::new(1, 2, Protocol::Gg18, 30);
+    let first = room.add_party().unwrap();
+    let second = room.add_party().unwrap();
+    assert_eq!((first.party_order, second.party_order), (1, 2));
+    assert!(room.add_party().is_none());
+
+    room.remove_party(2);
+    let second = room.add_party().unwrap();
+    assert_eq!(second.party_order, 2);
+
+    assert!(!room.update_ping(1).room_uuid.is_empty());
+    assert!(!room.is_replaceable()); // Party 2 did not learn the room uuid yet
+    room.update_ping(2);
+    assert!(room.is_replaceable());
+}
+
+#[test]
+fn test_p2p_channel() {
+    let uuid = "6f1c1e36-0d2b-4c6e-9a4c-2b7e0b5f8a11";
+    let (u_1, u_2): (FE, FE) = (ECScalar::new_random(), ECScalar::new_random());
+    let shared: GE = GE::generator() * &u_1 * &u_2;
+    let channel_1 = P2pChannel::new(1, uuid, vec![(2, vec![shared])].into_iter().collect::<HashMap<_, _>>());
+    let channel_2 = P2pChannel::new(2, uuid, vec![(1, vec![shared])].into_iter().collect::<HashMap<_, _>>());
+
+    let sealed = channel_1.seal(2, "round2", "answer");
+    assert!(!sealed.contains("answer"));
+    assert_eq!(channel_2.open(1, "round2", &sealed), Ok("answer".to_string()));
+    assert!(channel_2.open(1, "round3", &sealed).is_err());
+    assert!(channel_1.open(2, "round2", &sealed).is_err()); // Keys differ per direction
+
+    let other_session = P2pChannel::new(2, "0b9e6c1a-7f3d-4e2a-8c5b-1d4f6a2e9c30", vec![(1, vec![shared])].into_iter().collect());
+    assert!(other_session.open(1, "round2", &sealed).is_err());
+}
+
+#[test]
+fn test_taproot_tweak() {
+    // BIP341 wallet test vector, key path only output
+    let internal_key = GE::from_bytes(&hex::decode(
+        "d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").unwrap()).unwrap();
+    let key = TweakedKey::new(&internal_key, Some(&[])).unwrap();
+    assert_eq!(key.internal_key, internal_key);
+    assert_eq!(
+        hex::encode(x_only(&key.output_key)),
+        "53a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+    );
+
+    // An odd key is used negated, as BIP340 keys are x-only
+    let minus_one: FE = ECScalar::from(&(FE::q() - BigInt::from(1)));
+    let odd_key = internal_key * &minus_one;
+    let key = TweakedKey::new(&odd_key, None).unwrap();
+    assert_eq!(key.output_key, internal_key);
+}
+
+#[test]
+fn test_import_private_key() {
+    let hex_key = "0c28fca386c7a227600b2fe50b7cae11ec86d3bf1fbe471be89827e19d72aa1d";
+    let expected = parse_private_key(hex_key).unwrap();
+    assert_eq!(expected.to_big_int(), BigInt::from_hex(hex_key).unwrap());
+    assert_eq!(parse_private_key("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTJ").unwrap(), expected);
+    assert!(parse_private_key("5HueCGU8rMjxEXxiPuD5BDku4MkFqeZyd4dZ1jvhTVqvbTLvyTK").is_err());
+    assert!(parse_private_key(&"00".repeat(32)).is_err());
+    let wif = encode_wif(&expected);
+    assert!(wif.starts_with('K') || wif.starts_with('L'));
+    assert_eq!(parse_private_key(&wif).unwrap(), expected);
+}
+
+#[test]
+fn test_ed25519_encoding() {
+    use curv::elliptic::curves::ed25519::{FE as EdFE, GE as EdGE};
+    use ring::digest::{digest, SHA512};
+
+    // RFC 8032 section 7.1, test 1
+    let seed = hex::decode("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60").unwrap();
+    let public_key = "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a";
+    let signature = "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b";
+    let message: &[u8] = b"";
+
+    let h = digest(&SHA512, &seed).as_ref().to_vec();
+    let mut a_bytes = h[..32].to_vec();
+    a_bytes[0] &= 248;
+    a_bytes[31] &= 127;
+    a_bytes[31] |= 64;
+    a_bytes.reverse();
+    let a: EdFE = ECScalar::from(&BigInt::from_bytes(&a_bytes).modulus(&EdFE::q()));
+    let A = EdGE::generator() * &a;
+    assert_eq!(hex::encode(A.pk_to_key_slice()), public_key);
+
+    let r = hash_to_scalar(&[&h[32..], message]);
+    let R = EdGE::generator() * &r;
+    let k = hash_to_scalar(&[&R.pk_to_key_slice(), &A.pk_to_key_slice(), message]);
+    let mut encoded = R.pk_to_key_slice();
+    encoded.extend_from_slice(&scalar_le_bytes(&(r + k * a)));
+    assert_eq!(hex::encode(encoded), signature);
+}
+
+#[test]
+fn test_paillier_modulus_proof() {
+    let (ek, dk) = blum_paillier_keys();
+    let proof = BlumModulusProof::prove(&dk);
+    assert!(verify_paillier_moduli(&[(&ek, &proof)]).is_ok());
+    assert!(verify_paillier_keys(&[(&ek, &NICorrectKeyProof::proof(&dk, None))]).is_ok());
+
+    // Blum integer of two small primes: the modulus proof holds, the key is refused for its size
+    let (small_ek, small_dk) = Keypair { p: BigInt::from(1000003), q: BigInt::from(999983) }.keys();
+    let small_proof = BlumModulusProof::prove(&small_dk);
+    assert!(verify_paillier_moduli(&[(&small_ek, &small_proof)]).is_ok());
+    assert!(verify_paillier_keys(&[(&small_ek, &NICorrectKeyProof::proof(&small_dk, None))]).is_err());
+
+    // 1000033 is 1 mod 4, no proof of another modulus passes for it, nor for a prime
+    let (other_ek, _) = Keypair { p: BigInt::from(1000033), q: BigInt::from(999983) }.keys();
+    assert!(verify_paillier_moduli(&[(&other_ek, &small_proof)]).is_err());
+    let prime_ek = EncryptionKey { n: BigInt::from(1000003), nn: BigInt::from(1000003) * BigInt::from(1000003) };
+    assert!(verify_paillier_moduli(&[(&prime_ek, &small_proof)]).is_err());
+    let mut forged = small_proof.clone();
+    forged.a[0] = !forged.a[0];
+    assert!(verify_paillier_moduli(&[(&small_ek, &forged)]).is_err());
+}
+
+#[test]
+fn test_validate_commitments() {
+    let secret: FE = ECScalar::new_random();
+    let mut key_share = split_key(&secret, 1, 2).unwrap().remove(0);
+    assert!(key_share.validate().is_ok());
+    // Imported keys hold Blum moduli like generated ones
+    let modulus_proof = BlumModulusProof::prove(&key_share.party_keys.dk);
+    assert!(verify_paillier_moduli(&[(&key_share.party_keys.ek, &modulus_proof)]).is_ok());
+    key_share.vss_scheme_vec[1].commitments.pop();
+    assert!(key_share.validate().is_err());
+    key_share.vss_scheme_vec[1].commitments.clear();
+    assert!(key_share.validate().is_err());
+}
+
+#[test]
+fn test_recover_share() {
+    let secret: FE = ECScalar::new_random();
+    let (vss_scheme, shares) = VerifiableSS::<GE>::share(2, 5, &secret);
+    let helpers = [1u16, 3, 5];
+    // Masked parts sum to the share of party 4, whatever the masks
+    let parts = helpers.iter()
+        .map(|id| lagrange_at(4, *id, &helpers) * shares[(*id - 1) as usize])
+        .collect::<Vec<FE>>();
+    let mask: FE = ECScalar::new_random();
+    let recovered = (parts[0] + mask) + parts[1] + parts[2].sub(&mask.get_element());
+    assert_eq!(recovered, shares[3]);
+    assert_eq!(GE::generator() * &recovered, vss_scheme.get_point_commitment(4));
+}
+
+#[test]
+fn test_share_update() {
+    let secret: FE = ECScalar::new_random();
+    let key_shares = split_key(&secret, 1, 3).unwrap();
+    let (ek, dk) = blum_paillier_keys();
+    let (dlog_statement, composite_dlog_proof) = create_dlog_statement();
+    let update = ShareUpdate {
+        party_id: 2,
+        ek: ek.clone(),
+        correct_key_proof: NICorrectKeyProof::proof(&dk, None),
+        modulus_proof: BlumModulusProof::prove(&dk),
+        dlog_statement,
+        composite_dlog_proof,
+    };
+
+    let mut key_share = key_shares[0].clone();
+    assert!(update.apply(&mut key_share).is_ok());
+    assert_eq!(key_share.paillier_key_vector[1].n, update.ek.n);
+    assert_eq!(key_share.dlog_statement_vec[1].N, update.dlog_statement.N);
+
+    // The recovered party keeps its own keys, ids out of range are refused
+    assert!(update.apply(&mut key_shares[1].clone()).is_err());
+    for party_id in [0u16, 4].iter() {
+        let mut out_of_range = update.clone();
+        out_of_range.party_id = *party_id;
+        assert!(out_of_range.apply(&mut key_shares[2].clone()).is_err());
+    }
+
+    // Proofs of other keys fail and leave the keys file unchanged
+    let mut bad_key_proof = update.clone();
+    bad_key_proof.correct_key_proof = NICorrectKeyProof::proof(&key_shares[2].party_keys.dk, None);
+    let mut bad_dlog_proof = update.clone();
+    bad_dlog_proof.dlog_statement = key_shares[2].dlog_statement_vec[0].clone();
+    let mut forged_modulus_proof = update.clone();
+    forged_modulus_proof.modulus_proof.a[0] = !forged_modulus_proof.modulus_proof.a[0];
+    let mut missing_modulus_proof = update.clone();
+    missing_modulus_proof.modulus_proof = BlumModulusProof { w: BigInt::from(0), x: vec![], a: vec![], b: vec![], z: vec![] };
+    for bad in [bad_key_proof, bad_dlog_proof, forged_modulus_proof, missing_modulus_proof].iter() {
+        let mut key_share = key_shares[2].clone();
+        assert!(bad.apply(&mut key_share).unwrap_err().starts_with("Party 2 sent"));
+        assert_eq!(key_share.paillier_key_vector[1].n, key_shares[2].paillier_key_vector[1].n);
+        assert_eq!(key_share.dlog_statement_vec[1].N, key_shares[2].dlog_statement_vec[1].N);
+    }
+
+    // Keys files without statements stay without
+    let mut legacy = key_shares[2].clone();
+    legacy.dlog_statement_vec.clear();
+    write_keys(&mut legacy, &update);
+    assert_eq!(legacy.paillier_key_vector[1].n, update.ek.n);
+    assert!(legacy.dlog_statement_vec.is_empty());
+
+    let device_key = GE::generator() * &secret;
+    assert_eq!(parse_device_key(&hex::encode(device_key.get_element().serialize())), Ok(device_key));
+    assert!(parse_device_key(&hex::encode(x_only(&device_key))).is_err());
+}
+
+#[test]
+fn test_backup_words() {
+    let secret = BackupSecret {
+        party_id: 2,
+        u_i: ECScalar::new_random(),
+        x_i: ECScalar::new_random(),
+    };
+    let word_lists = encode_shares(&secret, 2, 3).unwrap();
+    assert!(word_lists.iter().all(|x| x.split(' ').count() == WORDS_PER_SHARE));
+    assert_eq!(decode_shares(&word_lists[1..]).unwrap(), secret);
+    assert_eq!(decode_shares(&[word_lists[2].clone(), word_lists[0].clone()]).unwrap(), secret);
+    assert!(decode_shares(&word_lists[..1]).is_err());
+    assert!(encode_shares(&secret, 3, 2).is_err());
+
+    // A wrong word fails the checksum
+    let mut words: Vec<&str> = word_lists[0].split(' ').collect();
+    words[3] = if words[3] == "abandon" { "ability" } else { "abandon" };
+    assert!(decode_shares(&[words.join(" "), word_lists[1].clone()]).is_err());
+}
+
+#[test]
+fn test_backup_restore() {
+    let secret: FE = ECScalar::new_random();
+    let key_shares = split_key(&secret, 1, 2).unwrap();
+    // A recovered party holds x_i in place of its lost keygen secret u_i
+    let mut recovered = key_shares[1].clone();
+    recovered.party_keys.u_i = recovered.shared_keys.x_i;
+    recovered.party_keys.y_i = GE::generator() * &recovered.shared_keys.x_i;
+    let word_lists = backup(&recovered, 2, 3).unwrap();
+
+    let (restored, update) = restore(key_shares[0].record(), &word_lists[1..]).unwrap();
+    assert_eq!(restored.party_id, 2);
+    assert_eq!(restored.shared_keys.x_i, recovered.shared_keys.x_i);
+    assert_eq!(restored.paillier_key_vector[1].n, update.ek.n);
+    assert!(verify_paillier_moduli(&[(&update.ek, &update.modulus_proof)]).is_ok());
+    assert!(update.apply(&mut key_shares[0].clone()).is_ok());
+
+    // A share that does not match the VSS commitments is refused before any word list is written
+    let mut broken = key_shares[1].clone();
+    broken.shared_keys.x_i = ECScalar::new_random();
+    assert!(backup(&broken, 2, 3).is_err());
+}
+
+/// A manager served in memory, shared by the parties of a test
+struct LocalTransport {
+    client: Client,
+}
+
+impl LocalTransport {
+    async fn new() -> Self {
+        let config = ManagerConfig {
+            ttl: 3600,
+            signup_timeout: 60,
+            client_rate_limit: 0,
+            room_rate_limit: 0,
+            ..Default::default()
+        };
+        LocalTransport { client: Client::untracked(build_manager(config)).await.unwrap() }
+    }
+}
+
+#[async_trait]
+impl Transport for LocalTransport {
+    async fn post(&self, path: &str, body: &str) -> Result<String, ManagerError> {
+        let res = self.client.post(format!("/{}", path))
+            .header(ContentType::JSON)
+            .body(body)
+            .dispatch()
+            .await;
+        res.into_string().await
+            .ok_or_else(|| ManagerError{ error: format!("Manager answered /{} without a body", path) })
+    }
+}
+
+#[test]
+fn test_two_party_sign_at_path() {
+    block_on(async {
+        let transport = LocalTransport::new().await;
+        let config = ClientConfig::default();
+        let key_shares = join_all((0..2).map(|_| two_party::run_keygen_async(&transport, &config))).await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(key_shares[0].public_key, key_shares[1].public_key);
+
+        // Party 2 moves the derivation tweak into party 1's encrypted share
         let path = "1/2/3";
-        let expected_pubkey_x = "e891363052c09185814e92ce7a1a1946631dc53d058a01176fcf27a66b5674c2";
-        let expected_pubkey_y = "cfbe0a84b7f7c49b5bb2a48999a761fc6c5dd6526aa79a58d4029865ef7d4a17";
-        let (_f_l_new, public_key_child, ) = call_hd_key(path, original_public_key);
+        let message = [7u8; 32];
+        let options = SignOptions::default();
+        let results = join_all(key_shares.iter().map(|key_share| {
+            two_party::sign_async(&transport, &config, key_share, &options, &message, path)
+        })).await;
 
-        assert_eq!(public_key_child.x_coor().unwrap().to_hex(), expected_pubkey_x);
-        assert_eq!(public_key_child.y_coor().unwrap().to_hex(), expected_pubkey_y);
+        let (_, derived_key) = call_hd_key(path, key_shares[0].public_key).unwrap();
+        assert!(derived_key != key_shares[0].public_key);
+        for result in results {
+            let result = result.unwrap();
+            assert_eq!(result.public_key, derived_key);
+            assert!(verify(&result.signature, &derived_key, &BigInt::from_bytes(&message)).is_ok());
+        }
+    });
+}
+
+/// Stores the round 1 broadcasts of its party with an altered MtA ciphertext
+struct TamperingTransport<'a> {
+    inner: &'a LocalTransport,
+}
+
+#[async_trait]
+impl Transport for TamperingTransport<'_> {
+    async fn post(&self, path: &str, body: &str) -> Result<String, ManagerError> {
+        let mut entry: Entry = match (path, serde_json::from_str::<Entry>(body)) {
+            ("set", Ok(entry)) if entry.key.contains("-round1-") => entry,
+            _ => return self.inner.post(path, body).await,
+        };
+        let (bc1, mut m_a): (SignBroadcastPhase1, MessageA) = serde_json::from_str(&entry.value).unwrap();
+        m_a.c = m_a.c + BigInt::from(1);
+        entry.value = serde_json::to_string(&(bc1, m_a)).unwrap();
+        self.inner.post(path, &serde_json::to_string(&entry).unwrap()).await
     }
+}
+
+/// Signs with the shares 1 and 3 of a 2-of-3 key, the one of party 3 through `tampered` if given
+fn sign_two_of_three(protocol: Protocol, tampered: bool) -> Vec<Result<signer::SignResult, Error>> {
+    let secret: FE = ECScalar::new_random();
+    let key_shares: Vec<KeyShare> = split_key(&secret, 1, 3).unwrap().into_iter()
+        .map(|mut x| { x.protocol = protocol; x })
+        .collect();
+    let params = Params::new(1, 3);
+    let options = SignOptions::default();
+    let message = [9u8; 32];
+    // The tampering party waits for answers that never come
+    let config = ClientConfig::default();
+    let short_config = ClientConfig { poll_timeout: 5, ..ClientConfig::default() };
+
+    block_on(async {
+        let transport = LocalTransport::new().await;
+        let tampering = TamperingTransport { inner: &transport };
+        let party_three: (&dyn Transport, &ClientConfig) = match tampered {
+            true => (&tampering as &dyn Transport, &short_config),
+            false => (&transport, &config),
+        };
+        join_all(vec![
+            signer::sign_async(&transport, &config, &key_shares[0], &params, &options, &message, ""),
+            signer::sign_async(party_three.0, party_three.1, &key_shares[2], &params, &options, &message, ""),
+        ]).await
+    })
+}
 
-}
\ No newline at end of file
+#[test]
+fn test_sign_two_of_three() {
+    let message_bn = BigInt::from_bytes(&[9u8; 32]);
+    for protocol in [Protocol::Gg18, Protocol::Gg20].iter() {
+        for result in sign_two_of_three(*protocol, false) {
+            let result = result.unwrap();
+            assert!(verify(&result.signature, &result.public_key, &message_bn).is_ok());
+        }
+    }
+}
+
+#[test]
+fn test_sign_tampered_message() {
+    for protocol in [Protocol::Gg18, Protocol::Gg20].iter() {
+        let results = sign_two_of_three(*protocol, true);
+        // The honest party aborts and names the sender of the altered message
+        match &results[0] {
+            Err(Error::Protocol(e)) => assert!(e.starts_with("Party 3 sent invalid range proofs"), "{}", e),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("signed with a tampered message"),
+        }
+        assert!(results[1].is_err());
+    }
+}